@@ -0,0 +1,21 @@
+ALTER TABLE subscriptions ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}'::jsonb;
+
+CREATE TABLE attribute_definitions(
+   key TEXT NOT NULL,
+   PRIMARY KEY (key),
+   value_type TEXT NOT NULL CHECK (value_type IN ('string', 'number', 'boolean')),
+   publicly_settable BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE tags(
+   id uuid NOT NULL,
+   PRIMARY KEY (id),
+   name TEXT NOT NULL UNIQUE,
+   publicly_settable BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE subscription_tags(
+   subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+   tag_id uuid NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+   PRIMARY KEY (subscriber_id, tag_id)
+);
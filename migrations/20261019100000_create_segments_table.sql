@@ -0,0 +1,10 @@
+-- Every existing subscription is treated as confirmed until double opt-in is in place.
+ALTER TABLE subscriptions ADD COLUMN status TEXT NOT NULL DEFAULT 'confirmed';
+
+CREATE TABLE segments(
+   id uuid NOT NULL,
+   PRIMARY KEY (id),
+   name TEXT NOT NULL UNIQUE,
+   expression TEXT NOT NULL,
+   created_at timestamptz NOT NULL
+);
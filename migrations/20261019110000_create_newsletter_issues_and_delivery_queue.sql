@@ -0,0 +1,22 @@
+CREATE TABLE newsletter_issues(
+   id uuid NOT NULL,
+   PRIMARY KEY (id),
+   title TEXT NOT NULL,
+   text_content TEXT NOT NULL,
+   html_content TEXT NOT NULL,
+   segment TEXT,
+   status TEXT NOT NULL,
+   scheduled_at timestamptz NOT NULL,
+   enqueued_at timestamptz,
+   created_at timestamptz NOT NULL
+);
+
+CREATE INDEX newsletter_issues_due_idx ON newsletter_issues (scheduled_at) WHERE status = 'scheduled';
+
+CREATE TABLE issue_delivery_queue(
+   newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+   subscriber_email TEXT NOT NULL,
+   n_retries SMALLINT NOT NULL DEFAULT 0,
+   execute_after timestamptz NOT NULL DEFAULT now(),
+   PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,18 @@
+ALTER TABLE subscriptions ADD COLUMN timezone TEXT;
+
+-- Issues sent at a wall-clock time in each subscriber's timezone.
+ALTER TABLE newsletter_issues ADD COLUMN local_send_at TIMESTAMP;
+ALTER TABLE newsletter_issues ADD COLUMN fallback_timezone TEXT;
+
+CREATE TABLE issue_delivery_buckets(
+   newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+   timezone TEXT NOT NULL,
+   PRIMARY KEY (newsletter_issue_id, timezone),
+   send_at timestamptz NOT NULL,
+   recipients INT,
+   enqueued_at timestamptz
+);
+
+CREATE INDEX issue_delivery_buckets_due_idx ON issue_delivery_buckets (send_at) WHERE enqueued_at IS NULL;
+
+ALTER TABLE issue_delivery_queue ADD COLUMN timezone_bucket TEXT;
@@ -0,0 +1,34 @@
+CREATE TABLE newsletter_issue_revisions(
+   newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+   revision INT NOT NULL,
+   PRIMARY KEY (newsletter_issue_id, revision),
+   title TEXT NOT NULL,
+   text_content TEXT NOT NULL,
+   html_content TEXT NOT NULL,
+   created_at timestamptz NOT NULL
+);
+
+CREATE FUNCTION reject_revision_update() RETURNS trigger AS $$
+BEGIN
+   RAISE EXCEPTION 'newsletter issue revisions are immutable';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER newsletter_issue_revisions_immutable
+   BEFORE UPDATE ON newsletter_issue_revisions
+   FOR EACH ROW EXECUTE FUNCTION reject_revision_update();
+
+INSERT INTO newsletter_issue_revisions (
+   newsletter_issue_id, revision, title, text_content, html_content, created_at
+)
+SELECT id, 1, title, text_content, html_content, created_at FROM newsletter_issues;
+
+ALTER TABLE newsletter_issues ADD COLUMN current_revision INT NOT NULL DEFAULT 1;
+ALTER TABLE newsletter_issues DROP COLUMN title;
+ALTER TABLE newsletter_issues DROP COLUMN text_content;
+ALTER TABLE newsletter_issues DROP COLUMN html_content;
+
+-- Drafts have no send time yet; cancelling an issue turns it back into a draft.
+ALTER TABLE newsletter_issues ALTER COLUMN scheduled_at DROP NOT NULL;
+UPDATE newsletter_issues SET status = 'draft', scheduled_at = NULL WHERE status = 'cancelled';
+ALTER TABLE newsletter_issues ADD COLUMN sent_at timestamptz;
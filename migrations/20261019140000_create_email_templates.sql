@@ -0,0 +1,11 @@
+CREATE TABLE email_templates(
+   name TEXT NOT NULL,
+   PRIMARY KEY (name),
+   html_source TEXT NOT NULL,
+   text_source TEXT NOT NULL,
+   updated_at timestamptz NOT NULL
+);
+
+ALTER TABLE subscriptions
+   ADD COLUMN unsubscribe_token TEXT NOT NULL DEFAULT replace(gen_random_uuid()::text, '-', '');
+CREATE UNIQUE INDEX subscriptions_unsubscribe_token_idx ON subscriptions (unsubscribe_token);
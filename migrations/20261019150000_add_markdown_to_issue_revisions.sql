@@ -0,0 +1,3 @@
+-- The HTML and text bodies of a revision are generated from its Markdown source, if any,
+-- unless the editor provided them explicitly.
+ALTER TABLE newsletter_issue_revisions ADD COLUMN markdown_content TEXT;
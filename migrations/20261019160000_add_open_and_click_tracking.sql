@@ -0,0 +1,19 @@
+ALTER TABLE newsletter_issues ADD COLUMN tracking_enabled BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE newsletter_issues ADD COLUMN recipients INT NOT NULL DEFAULT 0;
+
+CREATE TABLE issue_opens(
+   newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+   subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+   PRIMARY KEY (newsletter_issue_id, subscriber_id),
+   opens INT NOT NULL DEFAULT 1,
+   first_opened_at timestamptz NOT NULL
+);
+
+CREATE TABLE issue_clicks(
+   newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+   subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+   url TEXT NOT NULL,
+   PRIMARY KEY (newsletter_issue_id, subscriber_id, url),
+   clicks INT NOT NULL DEFAULT 1,
+   first_clicked_at timestamptz NOT NULL
+);
@@ -0,0 +1,21 @@
+-- Outcome of every delivery task once it leaves the queue: 'sent', 'failed' or 'skipped'.
+CREATE TABLE issue_deliveries(
+   newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+   subscriber_email TEXT NOT NULL,
+   PRIMARY KEY (newsletter_issue_id, subscriber_email),
+   outcome TEXT NOT NULL,
+   attempts SMALLINT NOT NULL,
+   completed_at timestamptz NOT NULL
+);
+
+CREATE INDEX issue_deliveries_recipient_idx ON issue_deliveries (subscriber_email, completed_at);
+
+-- Bounces and spam complaints reported by the email provider, and unsubscribes through the
+-- link of an issue: 'bounced', 'complained' or 'unsubscribed'.
+CREATE TABLE issue_feedback_events(
+   newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+   subscriber_email TEXT NOT NULL,
+   kind TEXT NOT NULL,
+   PRIMARY KEY (newsletter_issue_id, subscriber_email, kind),
+   received_at timestamptz NOT NULL
+);
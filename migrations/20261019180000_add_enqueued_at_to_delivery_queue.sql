@@ -0,0 +1,3 @@
+-- When a task entered the queue, so metrics can report how long the oldest one waited.
+-- Retries and postponements move `execute_after` but keep `enqueued_at`.
+ALTER TABLE issue_delivery_queue ADD COLUMN enqueued_at timestamptz NOT NULL DEFAULT now();
@@ -0,0 +1,2 @@
+-- W3C trace context of the request that scheduled the issue, so delivery spans can link to it.
+ALTER TABLE newsletter_issues ADD COLUMN traceparent TEXT;
@@ -0,0 +1,3 @@
+-- Name of the saved segment an issue goes to. Its expression is copied into `segment` when
+-- delivery starts, so later edits of the saved segment do not change who received the issue.
+ALTER TABLE newsletter_issues ADD COLUMN saved_segment TEXT;
@@ -0,0 +1,605 @@
+//! Checks run on every public signup to keep bots from subscribing random addresses.
+//!
+//! - A honeypot field, hidden from people by the signup forms, that bots fill in.
+//! - A form token carrying the time the form was served and a random nonce, signed with the
+//!   application HMAC secret. Signups submitted faster than a person could fill in the form,
+//!   or with a stale, forged or already used token, are rejected. Used nonces are recorded in
+//!   Redis so every instance sees them, or in memory if Redis cannot be reached.
+//! - An optional CAPTCHA, checked by a [`CaptchaVerifier`].
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use redis::aio::ConnectionManager;
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::configuration::{ApplicationSettings, CaptchaSettings};
+
+/// Name of the honeypot field of the signup forms.
+pub const HONEYPOT_FIELD: &str = "website";
+
+/// Verifies the response a CAPTCHA widget produced in the browser.
+#[async_trait]
+pub trait CaptchaVerifier: Send + Sync {
+    async fn verify(&self, response: &str) -> anyhow::Result<bool>;
+}
+
+/// Verifies Cloudflare Turnstile responses. hCaptcha and reCAPTCHA expose the same API,
+/// so they work as well with their own `verify_url`.
+pub struct TurnstileVerifier {
+    http_client: reqwest::Client,
+    verify_url: String,
+    secret_key: Secret<String>,
+}
+
+impl TurnstileVerifier {
+    pub fn new(settings: &CaptchaSettings) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+        Self {
+            http_client,
+            verify_url: settings.verify_url.clone(),
+            secret_key: settings.secret_key.clone(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct VerifyResponse {
+    success: bool,
+}
+
+#[async_trait]
+impl CaptchaVerifier for TurnstileVerifier {
+    async fn verify(&self, response: &str) -> anyhow::Result<bool> {
+        let outcome: VerifyResponse = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&[
+                ("secret", self.secret_key.expose_secret().as_str()),
+                ("response", response),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Invalid response from the CAPTCHA verification API")?;
+        Ok(outcome.success)
+    }
+}
+
+/// How a signup reached us.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignupChannel {
+    /// A form post from the hosted signup page, which always carries a form token.
+    Form,
+    /// A JSON request from the widget or an API client.
+    Json,
+}
+
+/// The fields of a signup that prove it was made by a person.
+#[derive(Default)]
+pub struct SignupProof {
+    pub honeypot: Option<String>,
+    pub form_token: Option<String>,
+    pub captcha_response: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BotCheckFailure {
+    /// The honeypot was filled in. Bots are not told their signup was dropped.
+    Honeypot,
+    MissingFormToken,
+    InvalidFormToken,
+    TooFast,
+    ExpiredFormToken,
+    Captcha,
+}
+
+impl BotCheckFailure {
+    /// A message meant for a person whose signup was rejected.
+    pub fn message(&self) -> &'static str {
+        match self {
+            BotCheckFailure::Honeypot => "Thanks for subscribing!",
+            BotCheckFailure::MissingFormToken
+            | BotCheckFailure::InvalidFormToken
+            | BotCheckFailure::ExpiredFormToken => {
+                "This form has expired. Please reload the page and try again."
+            }
+            BotCheckFailure::TooFast => {
+                "That was quick! Please take a moment to check your details and try again."
+            }
+            BotCheckFailure::Captcha => "Please complete the CAPTCHA and try again.",
+        }
+    }
+}
+
+pub struct BotProtection {
+    hmac_secret: Secret<String>,
+    require_form_token: bool,
+    require_form_token_for_json: bool,
+    min_fill_time: TimeDelta,
+    max_token_age: TimeDelta,
+    captcha_site_key: Option<String>,
+    captcha: Option<Arc<dyn CaptchaVerifier>>,
+    redis: Option<ConnectionManager>,
+    /// Nonces used on this instance, with the time their token expires.
+    used_nonces: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+/// A form token whose signature checked out.
+struct FormToken<'a> {
+    nonce: &'a str,
+    expires_at: DateTime<Utc>,
+}
+
+impl BotProtection {
+    pub fn new(settings: &ApplicationSettings, captcha: Option<Arc<dyn CaptchaVerifier>>) -> Self {
+        let bot_protection = &settings.bot_protection;
+        Self {
+            hmac_secret: settings.hmac_secret.clone(),
+            require_form_token: bot_protection.require_form_token,
+            require_form_token_for_json: bot_protection.require_form_token_for_json,
+            min_fill_time: TimeDelta::seconds(bot_protection.min_fill_seconds as i64),
+            max_token_age: TimeDelta::seconds(bot_protection.max_token_age_seconds as i64),
+            captcha_site_key: bot_protection
+                .captcha
+                .as_ref()
+                .map(|captcha| captcha.site_key.clone()),
+            captcha,
+            redis: None,
+            used_nonces: Mutex::default(),
+        }
+    }
+
+    /// Set up the checks from the configuration, verifying CAPTCHAs with Turnstile if enabled.
+    /// Used form tokens are recorded in `redis`, or in memory only if it is `None` or fails.
+    pub fn from_settings(settings: &ApplicationSettings, redis: Option<ConnectionManager>) -> Self {
+        let captcha =
+            settings.bot_protection.captcha.as_ref().map(|captcha| {
+                Arc::new(TurnstileVerifier::new(captcha)) as Arc<dyn CaptchaVerifier>
+            });
+        Self {
+            redis,
+            ..Self::new(settings, captcha)
+        }
+    }
+
+    /// Site key for the CAPTCHA widget of the signup forms, if CAPTCHAs are enabled.
+    pub fn captcha_site_key(&self) -> Option<&str> {
+        self.captcha_site_key.as_deref()
+    }
+
+    /// A single-use token for a signup form served at `issued_at`.
+    pub fn issue_form_token(&self, issued_at: DateTime<Utc>) -> String {
+        let timestamp = issued_at.timestamp();
+        let nonce = Uuid::new_v4().simple().to_string();
+        format!(
+            "{}.{}.{}",
+            timestamp,
+            nonce,
+            hex::encode(self.sign(timestamp, &nonce))
+        )
+    }
+
+    /// Check a signup. A form token is only used up once every other check passed, so a
+    /// person who fails the CAPTCHA can try again with the same form.
+    pub async fn check(
+        &self,
+        proof: &SignupProof,
+        channel: SignupChannel,
+        now: DateTime<Utc>,
+    ) -> Result<(), BotCheckFailure> {
+        if proof
+            .honeypot
+            .as_deref()
+            .is_some_and(|value| !value.trim().is_empty())
+        {
+            return Err(BotCheckFailure::Honeypot);
+        }
+
+        let form_token = match proof
+            .form_token
+            .as_deref()
+            .filter(|token| !token.is_empty())
+        {
+            Some(token) => Some(self.check_form_token(token, now)?),
+            None if self.requires_form_token(channel) => {
+                return Err(BotCheckFailure::MissingFormToken)
+            }
+            None => None,
+        };
+
+        if let Some(captcha) = &self.captcha {
+            let response = proof
+                .captcha_response
+                .as_deref()
+                .filter(|response| !response.is_empty())
+                .ok_or(BotCheckFailure::Captcha)?;
+            match captcha.verify(response).await {
+                Ok(true) => {}
+                Ok(false) => return Err(BotCheckFailure::Captcha),
+                Err(e) => {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to verify a CAPTCHA response"
+                    );
+                    return Err(BotCheckFailure::Captcha);
+                }
+            }
+        }
+
+        if let Some(form_token) = form_token {
+            if !self.use_nonce(&form_token, now).await {
+                return Err(BotCheckFailure::InvalidFormToken);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn requires_form_token(&self, channel: SignupChannel) -> bool {
+        match channel {
+            SignupChannel::Form => self.require_form_token,
+            SignupChannel::Json => self.require_form_token_for_json,
+        }
+    }
+
+    fn check_form_token<'a>(
+        &self,
+        token: &'a str,
+        now: DateTime<Utc>,
+    ) -> Result<FormToken<'a>, BotCheckFailure> {
+        let mut parts = token.splitn(3, '.');
+        let (Some(timestamp), Some(nonce), Some(signature)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(BotCheckFailure::InvalidFormToken);
+        };
+        let timestamp: i64 = timestamp
+            .parse()
+            .map_err(|_| BotCheckFailure::InvalidFormToken)?;
+        let signature = hex::decode(signature).map_err(|_| BotCheckFailure::InvalidFormToken)?;
+        self.mac(timestamp, nonce)
+            .verify_slice(&signature)
+            .map_err(|_| BotCheckFailure::InvalidFormToken)?;
+
+        let issued_at =
+            DateTime::from_timestamp(timestamp, 0).ok_or(BotCheckFailure::InvalidFormToken)?;
+        let elapsed = now - issued_at;
+        if elapsed < self.min_fill_time {
+            return Err(BotCheckFailure::TooFast);
+        }
+        if elapsed > self.max_token_age {
+            return Err(BotCheckFailure::ExpiredFormToken);
+        }
+        Ok(FormToken {
+            nonce,
+            expires_at: issued_at + self.max_token_age,
+        })
+    }
+
+    /// Record the nonce of `token` as used, returning whether it was unused until now.
+    /// Nonces are kept until their token expires, after which it is rejected anyway.
+    async fn use_nonce(&self, token: &FormToken<'_>, now: DateTime<Utc>) -> bool {
+        if let Some(mut connection) = self.redis.clone() {
+            let ttl = (token.expires_at - now).num_seconds().max(1);
+            let result: redis::RedisResult<Option<String>> = redis::cmd("SET")
+                .arg(format!("form_token:{}", token.nonce))
+                .arg(1)
+                .arg("NX")
+                .arg("EX")
+                .arg(ttl)
+                .query_async(&mut connection)
+                .await;
+            match result {
+                Ok(set) => return set.is_some(),
+                Err(e) => tracing::warn!(
+                    error.message = %e,
+                    "Failed to record a used form token in Redis. Recording it in memory."
+                ),
+            }
+        }
+        let mut used_nonces = self.used_nonces.lock().unwrap();
+        used_nonces.retain(|_, expires_at| *expires_at >= now);
+        used_nonces
+            .insert(token.nonce.to_owned(), token.expires_at)
+            .is_none()
+    }
+
+    fn mac(&self, timestamp: i64, nonce: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any size.");
+        mac.update(format!("form:{}:{}", timestamp, nonce).as_bytes());
+        mac
+    }
+
+    fn sign(&self, timestamp: i64, nonce: &str) -> Vec<u8> {
+        self.mac(timestamp, nonce).finalize().into_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use chrono::{TimeDelta, Utc};
+    use claims::{assert_err_eq, assert_ok};
+    use secrecy::Secret;
+
+    use crate::{
+        bot_protection::{
+            BotCheckFailure, BotProtection, CaptchaVerifier, SignupChannel, SignupProof,
+        },
+        configuration::{
+            ApplicationSettings, BotProtectionSettings, BrandingSettings, HealthSettings,
+            RateLimitSettings, WindowLimit,
+        },
+    };
+
+    /// Accepts exactly one CAPTCHA response and records every response it was asked about.
+    struct MockCaptchaVerifier {
+        valid_response: &'static str,
+        seen: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl CaptchaVerifier for MockCaptchaVerifier {
+        async fn verify(&self, response: &str) -> anyhow::Result<bool> {
+            self.seen.lock().unwrap().push(response.to_owned());
+            Ok(response == self.valid_response)
+        }
+    }
+
+    fn settings(hmac_secret: &str) -> ApplicationSettings {
+        ApplicationSettings {
+            port: 0,
+            host: "127.0.0.1".into(),
+            base_url: "http://127.0.0.1".into(),
+            hmac_secret: Secret::new(hmac_secret.into()),
+            admin_token: Secret::new("admin".into()),
+            webhook_token: Secret::new("webhook".into()),
+            branding: BrandingSettings {
+                name: "Newsletter".into(),
+                logo_url: None,
+                accent_color: "#000000".into(),
+            },
+            allowed_origins: Vec::new(),
+            bot_protection: BotProtectionSettings {
+                require_form_token: true,
+                require_form_token_for_json: true,
+                min_fill_seconds: 3,
+                max_token_age_seconds: 3600,
+                captcha: None,
+            },
+            rate_limit: RateLimitSettings {
+                trusted_proxies: Vec::new(),
+                per_ip: WindowLimit {
+                    requests: 10,
+                    window_seconds: 60,
+                },
+                per_email: WindowLimit {
+                    requests: 3,
+                    window_seconds: 3600,
+                },
+            },
+            health: HealthSettings {
+                require_redis: false,
+                check_email_provider: false,
+            },
+            background_workers: true,
+            shutdown_timeout_seconds: 30,
+        }
+    }
+
+    fn proof(form_token: String) -> SignupProof {
+        SignupProof {
+            form_token: Some(form_token),
+            ..SignupProof::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn a_token_is_accepted_between_the_minimum_fill_time_and_its_expiry() {
+        let protection = BotProtection::new(&settings("secret"), None);
+        let issued_at = Utc::now();
+        let token = protection.issue_form_token(issued_at);
+
+        assert_err_eq!(
+            protection
+                .check(
+                    &proof(token.clone()),
+                    SignupChannel::Json,
+                    issued_at + TimeDelta::seconds(1)
+                )
+                .await,
+            BotCheckFailure::TooFast
+        );
+        assert_ok!(
+            protection
+                .check(
+                    &proof(token.clone()),
+                    SignupChannel::Json,
+                    issued_at + TimeDelta::seconds(5)
+                )
+                .await
+        );
+        assert_err_eq!(
+            protection
+                .check(
+                    &proof(token),
+                    SignupChannel::Json,
+                    issued_at + TimeDelta::hours(2)
+                )
+                .await,
+            BotCheckFailure::ExpiredFormToken
+        );
+    }
+
+    #[tokio::test]
+    async fn a_token_can_only_be_used_once() {
+        let protection = BotProtection::new(&settings("secret"), None);
+        let issued_at = Utc::now() - TimeDelta::minutes(1);
+        let token = protection.issue_form_token(issued_at);
+
+        assert_ok!(
+            protection
+                .check(&proof(token.clone()), SignupChannel::Json, Utc::now())
+                .await
+        );
+        assert_err_eq!(
+            protection
+                .check(&proof(token), SignupChannel::Json, Utc::now())
+                .await,
+            BotCheckFailure::InvalidFormToken
+        );
+        assert_ok!(
+            protection
+                .check(
+                    &proof(protection.issue_form_token(issued_at)),
+                    SignupChannel::Json,
+                    Utc::now()
+                )
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn a_token_is_not_used_up_by_a_failed_captcha() {
+        let verifier = Arc::new(MockCaptchaVerifier {
+            valid_response: "human",
+            seen: Mutex::new(Vec::new()),
+        });
+        let protection = BotProtection::new(&settings("secret"), Some(verifier));
+        let token = protection.issue_form_token(Utc::now() - TimeDelta::minutes(1));
+        let signup = |response: &str| SignupProof {
+            form_token: Some(token.clone()),
+            captcha_response: Some(response.into()),
+            ..SignupProof::default()
+        };
+
+        assert_err_eq!(
+            protection
+                .check(&signup("bot"), SignupChannel::Json, Utc::now())
+                .await,
+            BotCheckFailure::Captcha
+        );
+        assert_ok!(
+            protection
+                .check(&signup("human"), SignupChannel::Json, Utc::now())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn forged_and_missing_tokens_are_rejected() {
+        let protection = BotProtection::new(&settings("secret"), None);
+        let issued_at = Utc::now() - TimeDelta::minutes(1);
+        let foreign =
+            BotProtection::new(&settings("other secret"), None).issue_form_token(issued_at);
+        let token = protection.issue_form_token(issued_at);
+        let backdated = format!(
+            "{}.{}",
+            issued_at.timestamp() - 60,
+            token.split_once('.').unwrap().1
+        );
+
+        for forged in [foreign, backdated, "garbage".into(), "1.zz".into()] {
+            assert_err_eq!(
+                protection
+                    .check(&proof(forged), SignupChannel::Json, Utc::now())
+                    .await,
+                BotCheckFailure::InvalidFormToken
+            );
+        }
+        assert_err_eq!(
+            protection
+                .check(&SignupProof::default(), SignupChannel::Json, Utc::now())
+                .await,
+            BotCheckFailure::MissingFormToken
+        );
+    }
+
+    #[tokio::test]
+    async fn form_posts_and_json_signups_are_held_to_their_own_token_setting() {
+        let mut settings = settings("secret");
+        settings.bot_protection.require_form_token_for_json = false;
+        let protection = BotProtection::new(&settings, None);
+
+        assert_err_eq!(
+            protection
+                .check(&SignupProof::default(), SignupChannel::Form, Utc::now())
+                .await,
+            BotCheckFailure::MissingFormToken
+        );
+        assert_ok!(
+            protection
+                .check(&SignupProof::default(), SignupChannel::Json, Utc::now())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn a_filled_honeypot_is_rejected_before_anything_else() {
+        let protection = BotProtection::new(&settings("secret"), None);
+        let signup = SignupProof {
+            honeypot: Some("https://spam.example".into()),
+            ..SignupProof::default()
+        };
+
+        assert_err_eq!(
+            protection
+                .check(&signup, SignupChannel::Json, Utc::now())
+                .await,
+            BotCheckFailure::Honeypot
+        );
+    }
+
+    #[tokio::test]
+    async fn the_captcha_response_is_checked_by_the_verifier() {
+        let verifier = Arc::new(MockCaptchaVerifier {
+            valid_response: "human",
+            seen: Mutex::new(Vec::new()),
+        });
+        let mut settings = settings("secret");
+        settings.bot_protection.require_form_token_for_json = false;
+        let protection = BotProtection::new(&settings, Some(verifier.clone()));
+        let captcha = |response: Option<&str>| SignupProof {
+            captcha_response: response.map(Into::into),
+            ..SignupProof::default()
+        };
+
+        assert_ok!(
+            protection
+                .check(&captcha(Some("human")), SignupChannel::Json, Utc::now())
+                .await
+        );
+        assert_err_eq!(
+            protection
+                .check(&captcha(Some("bot")), SignupChannel::Json, Utc::now())
+                .await,
+            BotCheckFailure::Captcha
+        );
+        assert_err_eq!(
+            protection
+                .check(&captcha(None), SignupChannel::Json, Utc::now())
+                .await,
+            BotCheckFailure::Captcha
+        );
+        assert_eq!(*verifier.seen.lock().unwrap(), vec!["human", "bot"]);
+    }
+}
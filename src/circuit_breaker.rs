@@ -0,0 +1,222 @@
+//! Circuit breaker around an email provider, so an outage costs one timeout per probe
+//! instead of one per message.
+//!
+//! While closed, the outcomes of the last `window_size` sends are kept. Once at least
+//! `minimum_requests` of them are known and the share of failures reaches `failure_ratio`,
+//! the circuit opens and sends are refused for `open_seconds`. It then half-opens and lets up
+//! to `half_open_probes` sends through: a success closes the circuit, a failure opens it
+//! again.
+
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::configuration::CircuitBreakerSettings;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+pub struct CircuitBreaker {
+    settings: CircuitBreakerSettings,
+    state: Mutex<State>,
+}
+
+enum State {
+    /// Outcomes of the latest sends, `true` for failures.
+    Closed(VecDeque<bool>),
+    Open {
+        until: Instant,
+    },
+    HalfOpen {
+        probes: u32,
+        since: Instant,
+    },
+}
+
+impl CircuitBreaker {
+    pub fn new(settings: &CircuitBreakerSettings) -> Self {
+        Self {
+            settings: settings.clone(),
+            state: Mutex::new(State::Closed(VecDeque::new())),
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        match *self.state.lock().unwrap() {
+            State::Closed(_) => BreakerState::Closed,
+            State::Open { until } if until <= Instant::now() => BreakerState::HalfOpen,
+            State::Open { .. } => BreakerState::Open,
+            State::HalfOpen { .. } => BreakerState::HalfOpen,
+        }
+    }
+
+    /// Ask to send through the circuit, failing with the time until it lets a probe through.
+    /// Every allowed send must be followed by [`record_success`] or [`record_failure`].
+    ///
+    /// [`record_success`]: CircuitBreaker::record_success
+    /// [`record_failure`]: CircuitBreaker::record_failure
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let open_duration = self.open_duration();
+        let mut state = self.state.lock().unwrap();
+        match &mut *state {
+            State::Closed(_) => Ok(()),
+            State::Open { until } if *until > now => Err(*until - now),
+            State::Open { .. } => {
+                *state = State::HalfOpen {
+                    probes: 1,
+                    since: now,
+                };
+                Ok(())
+            }
+            // Probes that never reported back, such as cancelled sends, stop blocking new
+            // probes after a while.
+            State::HalfOpen { probes, since } => {
+                if now.duration_since(*since) >= open_duration {
+                    *probes = 0;
+                    *since = now;
+                }
+                if *probes < self.settings.half_open_probes.max(1) {
+                    *probes += 1;
+                    Ok(())
+                } else {
+                    Err(Duration::from_secs(1))
+                }
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        match &mut *state {
+            State::Closed(outcomes) => self.push_outcome(outcomes, false),
+            State::HalfOpen { .. } => {
+                tracing::info!("Closing the circuit of an email provider after a successful probe");
+                *state = State::Closed(VecDeque::new());
+            }
+            State::Open { .. } => {}
+        }
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let should_open = match &mut *state {
+            State::Closed(outcomes) => {
+                self.push_outcome(outcomes, true);
+                let failures = outcomes.iter().filter(|failed| **failed).count();
+                outcomes.len() >= self.settings.minimum_requests.max(1) as usize
+                    && failures as f64 >= self.settings.failure_ratio * outcomes.len() as f64
+            }
+            State::HalfOpen { .. } => true,
+            State::Open { .. } => false,
+        };
+        if should_open {
+            tracing::warn!(
+                open_seconds = self.settings.open_seconds,
+                "Opening the circuit of a failing email provider"
+            );
+            *state = State::Open {
+                until: Instant::now() + self.open_duration(),
+            };
+        }
+    }
+
+    fn push_outcome(&self, outcomes: &mut VecDeque<bool>, failed: bool) {
+        outcomes.push_back(failed);
+        while outcomes.len() > self.settings.window_size.max(1) as usize {
+            outcomes.pop_front();
+        }
+    }
+
+    fn open_duration(&self) -> Duration {
+        Duration::from_secs(self.settings.open_seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use crate::{
+        circuit_breaker::{BreakerState, CircuitBreaker},
+        configuration::CircuitBreakerSettings,
+    };
+
+    fn breaker(open_seconds: u64) -> CircuitBreaker {
+        CircuitBreaker::new(&CircuitBreakerSettings {
+            failure_ratio: 0.5,
+            minimum_requests: 4,
+            window_size: 10,
+            open_seconds,
+            half_open_probes: 1,
+        })
+    }
+
+    #[test]
+    fn the_circuit_stays_closed_until_enough_requests_were_seen() {
+        let breaker = breaker(30);
+
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert_ok!(breaker.try_acquire());
+    }
+
+    #[test]
+    fn the_circuit_opens_once_the_failure_ratio_is_reached() {
+        let breaker = breaker(30);
+
+        breaker.record_success();
+        breaker.record_success();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        breaker.record_failure();
+
+        assert_eq!(breaker.state(), BreakerState::Open);
+        let retry_after = assert_err!(breaker.try_acquire());
+        assert!(retry_after.as_secs() <= 30);
+    }
+
+    #[test]
+    fn a_half_open_circuit_lets_one_probe_through_and_closes_on_success() {
+        let breaker = breaker(0);
+        for _ in 0..4 {
+            breaker.record_failure();
+        }
+
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert_ok!(breaker.try_acquire());
+        breaker.record_success();
+
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn a_failed_probe_opens_the_circuit_again() {
+        let breaker = breaker(30);
+        for _ in 0..4 {
+            breaker.record_failure();
+        }
+        *breaker.state.lock().unwrap() = super::State::HalfOpen {
+            probes: 0,
+            since: std::time::Instant::now(),
+        };
+
+        assert_ok!(breaker.try_acquire());
+        assert_err!(breaker.try_acquire());
+        breaker.record_failure();
+
+        assert_eq!(breaker.state(), BreakerState::Open);
+    }
+}
@@ -0,0 +1,267 @@
+//! Checks of the settings that deserializing them cannot express. Every problem is collected,
+//! so a broken deployment is fixed in one round instead of one setting at a time.
+
+use std::fmt;
+
+use redis::IntoConnectionInfo;
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::configuration::Settings;
+
+/// Secrets signing or authenticating requests are at least this long.
+const MIN_SIGNING_SECRET_LENGTH: usize = 32;
+const MIN_TOKEN_LENGTH: usize = 16;
+
+/// A setting that failed validation.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    /// Path of the setting, such as `application.port`.
+    pub key: String,
+    pub message: String,
+    /// The file or `APP_` environment variable the value came from, if known.
+    pub source: Option<String>,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)?;
+        match &self.source {
+            Some(source) => write!(f, " (set in {})", source),
+            None => write!(f, " (default)"),
+        }
+    }
+}
+
+impl Settings {
+    /// Secrets still set to the placeholder committed in `placeholders`, the base configuration.
+    /// Deployed environments must get every secret from an `APP_` variable or a secret file.
+    pub fn placeholder_secrets(&self, placeholders: &config::Config) -> Vec<Problem> {
+        let mut checks = Checks::default();
+        for (key, secret) in [
+            ("application.hmac_secret", &self.application.hmac_secret),
+            ("application.admin_token", &self.application.admin_token),
+            ("application.webhook_token", &self.application.webhook_token),
+            ("database.password", &self.database.password),
+            (
+                "email_client.authorization_token",
+                &self.email_client.authorization_token,
+            ),
+        ] {
+            let placeholder = placeholders.get_string(key).ok();
+            checks.check(
+                placeholder.as_deref() != Some(secret.expose_secret().as_str()),
+                key,
+                "is still the placeholder from base.yaml",
+            );
+        }
+        checks.problems
+    }
+
+    /// Every problem with these settings. Their sources are left for the caller to fill in.
+    pub fn validate(&self) -> Vec<Problem> {
+        let mut checks = Checks::default();
+
+        let application = &self.application;
+        checks.port("application.port", application.port);
+        checks.url("application.base_url", &application.base_url);
+        checks.secret(
+            "application.hmac_secret",
+            &application.hmac_secret,
+            MIN_SIGNING_SECRET_LENGTH,
+        );
+        checks.secret(
+            "application.admin_token",
+            &application.admin_token,
+            MIN_TOKEN_LENGTH,
+        );
+        checks.secret(
+            "application.webhook_token",
+            &application.webhook_token,
+            MIN_TOKEN_LENGTH,
+        );
+        if let Some(logo_url) = &application.branding.logo_url {
+            checks.url("application.branding.logo_url", logo_url);
+        }
+        for (i, origin) in application.allowed_origins.iter().enumerate() {
+            checks.url(&format!("application.allowed_origins[{}]", i), origin);
+        }
+        if let Some(captcha) = &application.bot_protection.captcha {
+            checks.url(
+                "application.bot_protection.captcha.verify_url",
+                &captcha.verify_url,
+            );
+            checks.secret(
+                "application.bot_protection.captcha.secret_key",
+                &captcha.secret_key,
+                1,
+            );
+        }
+        for (key, limit) in [
+            (
+                "application.rate_limit.per_ip",
+                &application.rate_limit.per_ip,
+            ),
+            (
+                "application.rate_limit.per_email",
+                &application.rate_limit.per_email,
+            ),
+        ] {
+            checks.positive(&format!("{}.requests", key), limit.requests as f64);
+            checks.positive(
+                &format!("{}.window_seconds", key),
+                limit.window_seconds as f64,
+            );
+        }
+
+        checks.port("database.port", self.database.port);
+        checks.check(
+            !self.database.host.is_empty(),
+            "database.host",
+            "must not be empty",
+        );
+
+        let email_client = &self.email_client;
+        checks.url("email_client.base_url", &email_client.base_url);
+        if let Err(e) = email_client.sender() {
+            checks.problem("email_client.sender_email", e);
+        }
+        checks.secret(
+            "email_client.authorization_token",
+            &email_client.authorization_token,
+            1,
+        );
+        checks.positive(
+            "email_client.timeout_milliseconds",
+            email_client.timeout_milliseconds as f64,
+        );
+        checks.positive(
+            "email_client.throttle.messages_per_second",
+            email_client.throttle.messages_per_second,
+        );
+        for (i, limit) in email_client.throttle.per_domain.iter().enumerate() {
+            checks.positive(
+                &format!(
+                    "email_client.throttle.per_domain[{}].messages_per_second",
+                    i
+                ),
+                limit.messages_per_second,
+            );
+        }
+        let failure_ratio = email_client.circuit_breaker.failure_ratio;
+        checks.check(
+            failure_ratio > 0.0 && failure_ratio <= 1.0,
+            "email_client.circuit_breaker.failure_ratio",
+            "must be above 0 and at most 1",
+        );
+        if let Some(failover) = &email_client.failover {
+            checks.url("email_client.failover.base_url", &failover.base_url);
+            checks.secret(
+                "email_client.failover.authorization_token",
+                &failover.authorization_token,
+                1,
+            );
+        }
+
+        if let Err(e) = self
+            .redis_uri
+            .expose_secret()
+            .as_str()
+            .into_connection_info()
+        {
+            checks.problem("redis_uri", format!("is not a valid Redis URL: {}", e));
+        }
+        if let Some(endpoint) = &self.telemetry.otlp_endpoint {
+            checks.url("telemetry.otlp_endpoint", endpoint);
+        }
+
+        checks.problems
+    }
+}
+
+#[derive(Default)]
+struct Checks {
+    problems: Vec<Problem>,
+}
+
+impl Checks {
+    fn problem(&mut self, key: &str, message: impl Into<String>) {
+        self.problems.push(Problem {
+            key: key.into(),
+            message: message.into(),
+            source: None,
+        });
+    }
+
+    fn check(&mut self, ok: bool, key: &str, message: &str) {
+        if !ok {
+            self.problem(key, message);
+        }
+    }
+
+    fn url(&mut self, key: &str, value: &str) {
+        match Url::parse(value) {
+            Ok(url) if ["http", "https"].contains(&url.scheme()) => {}
+            Ok(_) => self.problem(key, format!("{:?} is not an HTTP or HTTPS URL", value)),
+            Err(e) => self.problem(key, format!("{:?} is not a valid URL: {}", value, e)),
+        }
+    }
+
+    fn secret(&mut self, key: &str, value: &Secret<String>, min_length: usize) {
+        if value.expose_secret().chars().count() < min_length {
+            self.problem(
+                key,
+                format!("must be at least {} characters long", min_length),
+            );
+        }
+    }
+
+    fn port(&mut self, key: &str, port: u16) {
+        self.check(port != 0, key, "must be a port between 1 and 65535");
+    }
+
+    fn positive(&mut self, key: &str, value: f64) {
+        self.check(value > 0.0, key, "must be greater than 0");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use crate::configuration::get_configuration;
+
+    #[test]
+    fn the_shipped_configuration_is_valid() {
+        let settings = get_configuration().unwrap();
+
+        assert_eq!(settings.validate(), vec![]);
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let mut settings = get_configuration().unwrap();
+        settings.application.port = 0;
+        settings.application.base_url = "not a url".into();
+        settings.application.hmac_secret = Secret::new("short".into());
+        settings.email_client.sender_email = "not-an-email".into();
+        settings.email_client.circuit_breaker.failure_ratio = 1.5;
+
+        let keys: Vec<_> = settings
+            .validate()
+            .into_iter()
+            .map(|problem| problem.key)
+            .collect();
+
+        assert_eq!(
+            keys,
+            [
+                "application.port",
+                "application.base_url",
+                "application.hmac_secret",
+                "email_client.sender_email",
+                "email_client.circuit_breaker.failure_ratio",
+            ]
+        );
+    }
+}
@@ -1,7 +1,15 @@
 mod new_subscriber;
+mod subscriber_attributes;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_tag;
+mod subscriber_timezone;
 
 pub use new_subscriber::NewSubscriber;
+pub use subscriber_attributes::{
+    AttributeDefinition, AttributeSchema, AttributeType, SubscriberAttributes,
+};
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscriber_tag::SubscriberTag;
+pub use subscriber_timezone::SubscriberTimezone;
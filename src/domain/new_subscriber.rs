@@ -1,6 +1,11 @@
-use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::domain::{
+    SubscriberAttributes, SubscriberEmail, SubscriberName, SubscriberTag, SubscriberTimezone,
+};
 
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    pub attributes: SubscriberAttributes,
+    pub tags: Vec<SubscriberTag>,
+    pub timezone: Option<SubscriberTimezone>,
 }
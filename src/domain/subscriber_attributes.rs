@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+/// The type a subscriber attribute value must have, as declared in `attribute_definitions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeType {
+    String,
+    Number,
+    Boolean,
+}
+
+impl AttributeType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttributeType::String => "string",
+            AttributeType::Number => "number",
+            AttributeType::Boolean => "boolean",
+        }
+    }
+
+    fn matches(&self, value: &Value) -> bool {
+        match self {
+            AttributeType::String => value.is_string(),
+            AttributeType::Number => value.is_number(),
+            AttributeType::Boolean => value.is_boolean(),
+        }
+    }
+}
+
+impl TryFrom<String> for AttributeType {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "string" => Ok(Self::String),
+            "number" => Ok(Self::Number),
+            "boolean" => Ok(Self::Boolean),
+            other => Err(format!(
+                "{} is not a supported attribute type. Use `string`, `number` or `boolean`.",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AttributeDefinition {
+    pub key: String,
+    pub value_type: AttributeType,
+    pub publicly_settable: bool,
+}
+
+impl AttributeDefinition {
+    /// Keys are restricted to lowercase identifiers so they can be referenced unquoted elsewhere.
+    pub fn parse(
+        key: String,
+        value_type: AttributeType,
+        publicly_settable: bool,
+    ) -> Result<AttributeDefinition, String> {
+        let starts_with_letter = key.starts_with(|c: char| c.is_ascii_lowercase());
+        let is_too_long = key.len() > 64;
+        let contains_forbidden_characters = key
+            .chars()
+            .any(|c| !c.is_ascii_lowercase() && !c.is_ascii_digit() && c != '_');
+
+        if !starts_with_letter || is_too_long || contains_forbidden_characters {
+            Err(format!("{} is not a valid attribute key.", key))
+        } else {
+            Ok(Self {
+                key,
+                value_type,
+                publicly_settable,
+            })
+        }
+    }
+}
+
+/// The attribute keys subscribers may carry, keyed by name.
+#[derive(Debug, Clone, Default)]
+pub struct AttributeSchema(HashMap<String, AttributeDefinition>);
+
+impl AttributeSchema {
+    pub fn new(definitions: impl IntoIterator<Item = AttributeDefinition>) -> Self {
+        Self(
+            definitions
+                .into_iter()
+                .map(|definition| (definition.key.clone(), definition))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<&AttributeDefinition> {
+        self.0.get(key)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SubscriberAttributes(Map<String, Value>);
+
+impl SubscriberAttributes {
+    /// Validate attributes set through the admin API: every key must be declared in the schema
+    /// and carry a value of the declared type.
+    pub fn parse(
+        attributes: Map<String, Value>,
+        schema: &AttributeSchema,
+    ) -> Result<SubscriberAttributes, String> {
+        Self::validate(attributes, schema, false)
+    }
+
+    /// Validate attributes submitted with a public signup, where only keys marked as
+    /// publicly settable are accepted.
+    pub fn parse_public(
+        attributes: Map<String, Value>,
+        schema: &AttributeSchema,
+    ) -> Result<SubscriberAttributes, String> {
+        Self::validate(attributes, schema, true)
+    }
+
+    fn validate(
+        attributes: Map<String, Value>,
+        schema: &AttributeSchema,
+        public_only: bool,
+    ) -> Result<SubscriberAttributes, String> {
+        for (key, value) in &attributes {
+            let definition = match schema.get(key) {
+                Some(definition) if !public_only || definition.publicly_settable => definition,
+                _ => return Err(format!("{} is not a settable attribute.", key)),
+            };
+            if !definition.value_type.matches(value) {
+                return Err(format!(
+                    "{} must be a {}.",
+                    key,
+                    definition.value_type.as_str()
+                ));
+            }
+        }
+
+        Ok(Self(attributes))
+    }
+
+    pub fn into_value(self) -> Value {
+        Value::Object(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use serde_json::{json, Map, Value};
+
+    use crate::domain::{
+        AttributeDefinition, AttributeSchema, AttributeType, SubscriberAttributes,
+    };
+
+    fn schema() -> AttributeSchema {
+        AttributeSchema::new([
+            AttributeDefinition {
+                key: "plan".into(),
+                value_type: AttributeType::String,
+                publicly_settable: false,
+            },
+            AttributeDefinition {
+                key: "source".into(),
+                value_type: AttributeType::String,
+                publicly_settable: true,
+            },
+            AttributeDefinition {
+                key: "seats".into(),
+                value_type: AttributeType::Number,
+                publicly_settable: false,
+            },
+        ])
+    }
+
+    fn attributes(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn attributes_matching_the_schema_are_accepted() {
+        let attrs = attributes(json!({"plan": "pro", "seats": 5, "source": "conference-2026"}));
+        assert_ok!(SubscriberAttributes::parse(attrs, &schema()));
+    }
+
+    #[test]
+    fn unknown_attributes_are_rejected() {
+        let attrs = attributes(json!({"favourite_colour": "green"}));
+        assert_err!(SubscriberAttributes::parse(attrs, &schema()));
+    }
+
+    #[test]
+    fn attributes_of_the_wrong_type_are_rejected() {
+        for attrs in [
+            json!({"plan": 1}),
+            json!({"seats": "five"}),
+            json!({"plan": null}),
+        ] {
+            assert_err!(SubscriberAttributes::parse(attributes(attrs), &schema()));
+        }
+    }
+
+    #[test]
+    fn public_signups_may_only_set_publicly_settable_attributes() {
+        let public = attributes(json!({"source": "conference-2026"}));
+        assert_ok!(SubscriberAttributes::parse_public(public, &schema()));
+
+        let private = attributes(json!({"plan": "pro"}));
+        assert_err!(SubscriberAttributes::parse_public(private, &schema()));
+    }
+
+    #[test]
+    fn attribute_keys_must_be_lowercase_identifiers() {
+        let parse =
+            |key: &str| AttributeDefinition::parse(key.into(), AttributeType::String, false);
+        assert_ok!(parse("signup_source_2"));
+        for invalid in ["", "Plan", "2fa", "plan-type", "plan.type"] {
+            assert_err!(parse(invalid));
+        }
+    }
+
+    #[test]
+    fn attribute_types_are_parsed_case_insensitively() {
+        assert_eq!(
+            AttributeType::try_from("Number".to_string()),
+            Ok(AttributeType::Number)
+        );
+        assert_err!(AttributeType::try_from("date".to_string()));
+    }
+}
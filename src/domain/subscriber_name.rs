@@ -49,7 +49,7 @@ mod tests {
         let name = "a".repeat(3);
         for invalid_char in &['/', '(', ')', '"', '<', '>', '\\', '{', '}'] {
             let mut name = name.clone();
-            name.push_str(&invalid_char.to_string());
+            name.push(*invalid_char);
             assert_err!(SubscriberName::parse(name));
         }
     }
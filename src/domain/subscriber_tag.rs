@@ -0,0 +1,61 @@
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SubscriberTag(String);
+
+impl SubscriberTag {
+    pub fn parse(s: String) -> Result<SubscriberTag, String> {
+        let tag = s.trim().to_lowercase();
+        let is_empty = tag.is_empty();
+        let is_too_long = tag.chars().count() > 64;
+        let allowed_punctuation = ['-', '_', '.', ':', '='];
+        let contains_forbidden_characters = tag
+            .chars()
+            .any(|c| !c.is_ascii_alphanumeric() && !allowed_punctuation.contains(&c));
+
+        if is_empty || is_too_long || contains_forbidden_characters {
+            Err(format!("{} is not a valid tag.", s))
+        } else {
+            Ok(Self(tag))
+        }
+    }
+}
+
+impl AsRef<str> for SubscriberTag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+
+    use crate::domain::SubscriberTag;
+
+    #[test]
+    fn tags_are_trimmed_and_lowercased() {
+        let tag = SubscriberTag::parse("  Source=Conference-2026 ".to_string()).unwrap();
+        assert_eq!(tag.as_ref(), "source=conference-2026");
+    }
+
+    #[test]
+    fn empty_tag_is_rejected() {
+        assert_err!(SubscriberTag::parse("".to_string()));
+        assert_err!(SubscriberTag::parse("   ".to_string()));
+    }
+
+    #[test]
+    fn tags_longer_than_64_characters_are_rejected() {
+        assert_err!(SubscriberTag::parse("a".repeat(65)));
+        assert_ok_eq!(
+            SubscriberTag::parse("a".repeat(64)).map(|t| t.as_ref().len()),
+            64
+        );
+    }
+
+    #[test]
+    fn tags_containing_an_invalid_character_are_rejected() {
+        for invalid in ["beta tester", "beta/tester", "<beta>", "beta,alpha"] {
+            assert_err!(SubscriberTag::parse(invalid.to_string()));
+        }
+    }
+}
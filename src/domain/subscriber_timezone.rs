@@ -0,0 +1,101 @@
+use chrono::{DateTime, NaiveDateTime, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// An IANA timezone name such as `Asia/Tokyo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscriberTimezone(Tz);
+
+impl SubscriberTimezone {
+    pub fn parse(s: String) -> Result<SubscriberTimezone, String> {
+        s.trim()
+            .parse::<Tz>()
+            .map(Self)
+            .map_err(|_| format!("{} is not a valid IANA timezone.", s))
+    }
+
+    /// The instant at which the wall clock in this timezone shows `local`.
+    ///
+    /// Ambiguous times (clocks going back) resolve to the earlier instant and times skipped
+    /// by a DST jump are moved forward by the size of the gap, which is at most an hour.
+    pub fn to_utc(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        self.0
+            .from_local_datetime(&local)
+            .earliest()
+            .or_else(|| {
+                self.0
+                    .from_local_datetime(&(local + TimeDelta::hours(1)))
+                    .earliest()
+            })
+            .map(|datetime| datetime.with_timezone(&Utc))
+            .unwrap_or_else(|| local.and_utc())
+    }
+}
+
+impl AsRef<str> for SubscriberTimezone {
+    fn as_ref(&self) -> &str {
+        self.0.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveDateTime, TimeZone, Utc};
+    use claims::{assert_err, assert_ok};
+
+    use crate::domain::SubscriberTimezone;
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn iana_names_are_accepted() {
+        for tz in [
+            "UTC",
+            "Asia/Tokyo",
+            "America/Argentina/Buenos_Aires",
+            " Europe/Berlin ",
+        ] {
+            assert_ok!(SubscriberTimezone::parse(tz.to_string()));
+        }
+    }
+
+    #[test]
+    fn unknown_names_and_raw_offsets_are_rejected() {
+        for tz in ["", "Mars/Olympus_Mons", "+09:00", "asia/tokyo"] {
+            assert_err!(SubscriberTimezone::parse(tz.to_string()));
+        }
+    }
+
+    #[test]
+    fn local_time_is_converted_to_utc() {
+        let tokyo = SubscriberTimezone::parse("Asia/Tokyo".into()).unwrap();
+        assert_eq!(
+            tokyo.to_utc(local(2026, 11, 2, 9, 0)),
+            Utc.with_ymd_and_hms(2026, 11, 2, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn times_skipped_by_daylight_saving_are_moved_forward() {
+        // Clocks in Berlin jump from 02:00 to 03:00 on 2026-03-29.
+        let berlin = SubscriberTimezone::parse("Europe/Berlin".into()).unwrap();
+        assert_eq!(
+            berlin.to_utc(local(2026, 3, 29, 2, 30)),
+            Utc.with_ymd_and_hms(2026, 3, 29, 1, 30, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn ambiguous_times_resolve_to_the_earlier_instant() {
+        // Clocks in Berlin go back from 03:00 to 02:00 on 2026-10-25.
+        let berlin = SubscriberTimezone::parse("Europe/Berlin".into()).unwrap();
+        assert_eq!(
+            berlin.to_utc(local(2026, 10, 25, 2, 30)),
+            Utc.with_ymd_and_hms(2026, 10, 25, 0, 30, 0).unwrap()
+        );
+    }
+}
@@ -1,13 +1,90 @@
-use crate::domain::SubscriberEmail;
-use reqwest::Client;
+use crate::{
+    circuit_breaker::{BreakerState, CircuitBreaker},
+    configuration::CircuitBreakerSettings,
+    domain::SubscriberEmail,
+    send_throttle::SendThrottle,
+    telemetry,
+};
+use chrono::{DateTime, Utc};
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    Client, StatusCode,
+};
 use secrecy::{ExposeSecret, Secret};
+use serde::Serialize;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How long to pause sending when the provider throttles us without saying for how long.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// The longest pause a provider's `Retry-After` can impose on every outgoing email.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
 
 #[derive(Clone)]
 pub struct EmailClient {
     http_client: Client,
     sender: SubscriberEmail,
+    /// Tried in order: the primary provider, then its failover if any.
+    providers: Vec<EmailProvider>,
+    circuit_breaker: Option<CircuitBreakerSettings>,
+    throttle: Option<Arc<SendThrottle>>,
+}
+
+#[derive(Clone)]
+struct EmailProvider {
+    name: &'static str,
     base_url: reqwest::Url,
     authorization_token: Secret<String>,
+    breaker: Option<Arc<CircuitBreaker>>,
+}
+
+/// State of the circuit of one provider, for health checks.
+#[derive(Serialize)]
+pub struct ProviderHealth {
+    pub name: &'static str,
+    pub state: BreakerState,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SendEmailError {
+    /// The provider asked us to slow down; carries the time to wait before sending again.
+    #[error("The email provider throttled us")]
+    Throttled(Duration),
+
+    /// Every provider is failing; carries the time until one of them accepts a probe.
+    #[error("The circuit of every email provider is open")]
+    CircuitOpen(Duration),
+
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+}
+
+impl SendEmailError {
+    /// Whether the error says the provider is down, rather than that it refused this email.
+    fn is_provider_failure(&self) -> bool {
+        match self {
+            SendEmailError::Request(e) => e.status().is_none_or(|s| s.is_server_error()),
+            SendEmailError::Throttled(_) | SendEmailError::CircuitOpen(_) => false,
+        }
+    }
+
+    /// Label of the error in metrics.
+    fn class(&self) -> &'static str {
+        match self {
+            SendEmailError::Throttled(_) => "throttled",
+            SendEmailError::CircuitOpen(_) => "circuit_open",
+            SendEmailError::Request(e) if e.is_timeout() => "timeout",
+            SendEmailError::Request(e) if e.is_connect() => "connect",
+            SendEmailError::Request(e) => match e.status() {
+                Some(status) if status.is_server_error() => "server_error",
+                Some(_) => "client_error",
+                None => "request",
+            },
+        }
+    }
 }
 
 impl EmailClient {
@@ -18,14 +95,87 @@ impl EmailClient {
         timeout: std::time::Duration,
     ) -> Self {
         let http_client = Client::builder().timeout(timeout).build().unwrap();
-        let base_url = reqwest::Url::parse(base_url).expect("Error while parsing url ->");
 
         Self {
             http_client,
-            base_url,
             sender,
+            providers: vec![EmailProvider::new(
+                "primary",
+                base_url,
+                authorization_token,
+                None,
+            )],
+            circuit_breaker: None,
+            throttle: None,
+        }
+    }
+
+    /// Wait for `throttle` before every send, and pause it whenever the provider answers
+    /// with `429 Too Many Requests`.
+    pub fn with_throttle(self, throttle: Arc<SendThrottle>) -> Self {
+        Self {
+            throttle: Some(throttle),
+            ..self
+        }
+    }
+
+    /// Put every provider behind its own circuit breaker, including a failover added before or
+    /// after this call.
+    pub fn with_circuit_breaker(mut self, settings: &CircuitBreakerSettings) -> Self {
+        for provider in &mut self.providers {
+            provider.breaker = Some(Arc::new(CircuitBreaker::new(settings)));
+        }
+        self.circuit_breaker = Some(settings.clone());
+        self
+    }
+
+    /// Send through a secondary provider with the same API whenever the previous one fails
+    /// or its circuit is open. It gets a circuit breaker of its own if the client has or later
+    /// gets them, whichever of this and [`with_circuit_breaker`] is called first.
+    ///
+    /// [`with_circuit_breaker`]: EmailClient::with_circuit_breaker
+    pub fn with_failover(mut self, base_url: &str, authorization_token: Secret<String>) -> Self {
+        let breaker = self
+            .circuit_breaker
+            .as_ref()
+            .map(|settings| Arc::new(CircuitBreaker::new(settings)));
+        self.providers.push(EmailProvider::new(
+            "failover",
+            base_url,
             authorization_token,
+            breaker,
+        ));
+        self
+    }
+
+    pub fn provider_health(&self) -> Vec<ProviderHealth> {
+        self.providers
+            .iter()
+            .map(|provider| ProviderHealth {
+                name: provider.name,
+                state: provider
+                    .breaker
+                    .as_ref()
+                    .map_or(BreakerState::Closed, |breaker| breaker.state()),
+            })
+            .collect()
+    }
+
+    /// Whether any provider answers HTTP requests at all, whatever the answer.
+    pub async fn check_reachable(&self) -> Result<(), reqwest::Error> {
+        let mut last_error = None;
+        for provider in &self.providers {
+            match self
+                .http_client
+                .head(provider.base_url.clone())
+                .send()
+                .await
+            {
+                Ok(_) => return Ok(()),
+                Err(e) => last_error = Some(e),
+            }
         }
+        Err(last_error.expect("There is always a primary provider."))
     }
 
     pub async fn send_email(
@@ -34,11 +184,7 @@ impl EmailClient {
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
-        let url = self
-            .base_url
-            .join("/email")
-            .expect("Error while sending email");
+    ) -> Result<(), SendEmailError> {
         let request_body = SendEmailRequest {
             from: self.sender.as_ref(),
             to: recipient.as_ref(),
@@ -46,21 +192,133 @@ impl EmailClient {
             html_body: html_content,
             text_body: text_content,
         };
-        let _builder = self
+        if let Some(throttle) = &self.throttle {
+            throttle.acquire(recipient).await;
+        }
+
+        let mut last_error = None;
+        for provider in &self.providers {
+            if let Some(breaker) = &provider.breaker {
+                if let Err(retry_after) = breaker.try_acquire() {
+                    metrics::counter!("email_sends_short_circuited_total", "provider" => provider.name)
+                        .increment(1);
+                    last_error = Some(match last_error {
+                        Some(SendEmailError::CircuitOpen(other)) => {
+                            SendEmailError::CircuitOpen(retry_after.min(other))
+                        }
+                        Some(e) => e,
+                        None => SendEmailError::CircuitOpen(retry_after),
+                    });
+                    continue;
+                }
+            }
+            let start = Instant::now();
+            let outcome = self.send_through(provider, &request_body).await;
+            metrics::counter!("email_send_attempts_total", "provider" => provider.name)
+                .increment(1);
+            metrics::histogram!("email_send_duration_seconds", "provider" => provider.name)
+                .record(start.elapsed().as_secs_f64());
+            if let Err(e) = &outcome {
+                metrics::counter!(
+                    "email_send_failures_total",
+                    "provider" => provider.name,
+                    "error_class" => e.class()
+                )
+                .increment(1);
+            }
+            let failed = outcome.as_ref().is_err_and(|e| e.is_provider_failure());
+            if let Some(breaker) = &provider.breaker {
+                if failed {
+                    breaker.record_failure();
+                } else {
+                    breaker.record_success();
+                }
+            }
+            match outcome {
+                Err(e) if failed => {
+                    tracing::warn!(
+                        provider = provider.name,
+                        error.message = %e,
+                        "Failed to send an email through a provider"
+                    );
+                    last_error = Some(e);
+                }
+                outcome => return outcome,
+            }
+        }
+        Err(last_error.expect("There is always a primary provider."))
+    }
+
+    async fn send_through(
+        &self,
+        provider: &EmailProvider,
+        request_body: &SendEmailRequest<'_>,
+    ) -> Result<(), SendEmailError> {
+        let url = provider
+            .base_url
+            .join("/email")
+            .expect("Error while sending email");
+        let mut headers = HeaderMap::new();
+        telemetry::inject_current_context(&mut headers);
+        let response = self
             .http_client
             .post(url)
+            .headers(headers)
             .header(
                 "X-Postmark-Server-Token",
-                self.authorization_token.expose_secret(),
+                provider.authorization_token.expose_secret(),
             )
-            .json(&request_body)
+            .json(request_body)
             .send()
-            .await?
-            .error_for_status()?;
+            .await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = retry_after(response.headers(), Utc::now());
+            if let Some(throttle) = &self.throttle {
+                throttle.pause(retry_after).await;
+            }
+            return Err(SendEmailError::Throttled(retry_after));
+        }
+        response.error_for_status()?;
         Ok(())
     }
 }
 
+/// How long the provider asks us to wait, from `Retry-After` in seconds or as an HTTP date,
+/// capped at [`MAX_RETRY_AFTER`] since the pause holds up every outgoing email.
+fn retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Duration {
+    let requested = headers
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .and_then(|value| match value.parse() {
+            Ok(seconds) => Some(Duration::from_secs(seconds)),
+            Err(_) => DateTime::parse_from_rfc2822(value).ok().map(|date| {
+                (date.with_timezone(&Utc) - now)
+                    .to_std()
+                    .unwrap_or_default()
+            }),
+        });
+    requested
+        .unwrap_or(DEFAULT_RETRY_AFTER)
+        .min(MAX_RETRY_AFTER)
+}
+
+impl EmailProvider {
+    fn new(
+        name: &'static str,
+        base_url: &str,
+        authorization_token: Secret<String>,
+        breaker: Option<Arc<CircuitBreaker>>,
+    ) -> Self {
+        Self {
+            name,
+            base_url: reqwest::Url::parse(base_url).expect("Error while parsing url ->"),
+            authorization_token,
+            breaker,
+        }
+    }
+}
+
 #[derive(serde::Serialize)]
 #[serde(rename_all = "PascalCase")]
 struct SendEmailRequest<'a> {
@@ -73,13 +331,18 @@ struct SendEmailRequest<'a> {
 
 #[cfg(test)]
 mod tests {
+    use crate::circuit_breaker::BreakerState;
+    use crate::configuration::CircuitBreakerSettings;
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{EmailClient, SendEmailError, DEFAULT_RETRY_AFTER, MAX_RETRY_AFTER};
+    use chrono::{DateTime, Utc};
     use claims::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::{Fake, Faker};
+    use reqwest::header::{HeaderMap, RETRY_AFTER};
     use secrecy::Secret;
+    use std::time::Duration;
     use tokio::test;
     use wiremock::matchers::{any, header, header_exists, method, path};
     use wiremock::Request;
@@ -197,4 +460,179 @@ mod tests {
 
         assert_err!(outcome);
     }
+
+    #[test]
+    async fn send_email_reports_how_long_the_provider_throttles_us() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "7"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(matches!(
+            outcome,
+            Err(SendEmailError::Throttled(retry_after))
+                if retry_after == std::time::Duration::from_secs(7)
+        ));
+    }
+
+    #[test]
+    async fn retry_after_is_read_as_seconds_or_a_date_and_capped() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2026 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+        let retry_after = |value: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(value) = value {
+                headers.insert(RETRY_AFTER, value.parse().unwrap());
+            }
+            super::retry_after(&headers, now)
+        };
+
+        assert_eq!(retry_after(Some("7")), Duration::from_secs(7));
+        assert_eq!(
+            retry_after(Some("Wed, 21 Oct 2026 07:29:30 GMT")),
+            Duration::from_secs(90)
+        );
+        assert_eq!(
+            retry_after(Some("Wed, 21 Oct 2026 07:00:00 GMT")),
+            Duration::ZERO
+        );
+        assert_eq!(retry_after(Some("86400")), MAX_RETRY_AFTER);
+        assert_eq!(retry_after(Some("soon")), DEFAULT_RETRY_AFTER);
+        assert_eq!(retry_after(None), DEFAULT_RETRY_AFTER);
+    }
+
+    #[test]
+    async fn the_failover_gets_a_circuit_breaker_whichever_is_configured_first() {
+        for failover_first in [false, true] {
+            let primary = MockServer::start().await;
+            let failover = MockServer::start().await;
+            let email_client = email_client(&primary.uri());
+            let email_client = if failover_first {
+                email_client
+                    .with_failover(&failover.uri(), Secret::new(Faker.fake()))
+                    .with_circuit_breaker(&circuit_breaker())
+            } else {
+                email_client
+                    .with_circuit_breaker(&circuit_breaker())
+                    .with_failover(&failover.uri(), Secret::new(Faker.fake()))
+            };
+            for server in [&primary, &failover] {
+                Mock::given(any())
+                    .respond_with(ResponseTemplate::new(503))
+                    .mount(server)
+                    .await;
+            }
+
+            for _ in 0..2 {
+                assert_err!(
+                    email_client
+                        .send_email(&email(), &subject(), &content(), &content())
+                        .await
+                );
+            }
+
+            let states: Vec<_> = email_client
+                .provider_health()
+                .into_iter()
+                .map(|health| health.state)
+                .collect();
+            assert_eq!(states, [BreakerState::Open, BreakerState::Open]);
+        }
+    }
+
+    fn circuit_breaker() -> CircuitBreakerSettings {
+        CircuitBreakerSettings {
+            failure_ratio: 0.5,
+            minimum_requests: 2,
+            window_size: 10,
+            open_seconds: 60,
+            half_open_probes: 1,
+        }
+    }
+
+    #[test]
+    async fn send_email_short_circuits_once_the_provider_keeps_failing() {
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(&mock_server.uri()).with_circuit_breaker(&circuit_breaker());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        for _ in 0..2 {
+            let _ = email_client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await;
+        }
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(matches!(outcome, Err(SendEmailError::CircuitOpen(_))));
+        assert_eq!(email_client.provider_health()[0].state, BreakerState::Open);
+    }
+
+    #[test]
+    async fn send_email_fails_over_when_the_primary_provider_fails() {
+        let primary = MockServer::start().await;
+        let failover = MockServer::start().await;
+        let email_client = email_client(&primary.uri())
+            .with_circuit_breaker(&circuit_breaker())
+            .with_failover(&failover.uri(), Secret::new(Faker.fake()));
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&primary)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&failover)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[test]
+    async fn send_email_does_not_fail_over_when_the_provider_rejects_the_email() {
+        let primary = MockServer::start().await;
+        let failover = MockServer::start().await;
+        let email_client = email_client(&primary.uri())
+            .with_circuit_breaker(&circuit_breaker())
+            .with_failover(&failover.uri(), Secret::new(Faker.fake()));
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&primary)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&failover)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
 }
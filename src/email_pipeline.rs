@@ -0,0 +1,174 @@
+//! Last step before an HTML body is handed to [`EmailClient::send_email`].
+//!
+//! Email clients drop `<style>` blocks and run no scripts, and unsafe markup sometimes gets
+//! pasted into an issue. Every outgoing HTML body therefore has its stylesheet rules inlined
+//! into `style` attributes, active content stripped, and relative links made absolute.
+//!
+//! [`EmailClient::send_email`]: crate::email_client::EmailClient::send_email
+
+use anyhow::Context;
+use css_inline::CSSInliner;
+use lol_html::{element, html_content::Element, rewrite_str, RewriteStrSettings};
+use reqwest::Url;
+
+/// Gmail clips messages whose HTML is larger than this.
+pub const GMAIL_CLIP_BYTES: usize = 102 * 1024;
+
+/// Elements removed along with their content.
+const ACTIVE_CONTENT: &str = "script, iframe, frame, frameset, object, embed, applet";
+
+/// Attributes holding a URL that is resolved against the base URL.
+const URL_ATTRIBUTES: [&str; 3] = ["href", "src", "background"];
+
+/// Inline stylesheets, sanitize and absolutize an HTML body.
+#[tracing::instrument(name = "Preparing HTML body", skip_all)]
+pub fn prepare_html(html: &str, base_url: &str) -> anyhow::Result<String> {
+    let base_url = Url::parse(base_url).context("The application base URL is invalid")?;
+
+    let inlined = CSSInliner::options()
+        .load_remote_stylesheets(false)
+        .build()
+        .inline(html)
+        .context("Failed to inline stylesheets")?;
+
+    let sanitized = rewrite_str(
+        &inlined,
+        RewriteStrSettings::new()
+            .append_element_content_handler(element!(ACTIVE_CONTENT, |el| {
+                el.remove();
+                Ok(())
+            }))
+            .append_element_content_handler(element!("*", |el| {
+                remove_event_handlers(el);
+                resolve_urls(el, &base_url)?;
+                Ok(())
+            })),
+    )
+    .context("Failed to sanitize HTML")?;
+
+    Ok(sanitized)
+}
+
+/// Warn if Gmail will clip `html`. Run on the body as it is sent, after tracking rewrote its
+/// links and added the pixel.
+pub fn warn_if_clipped(html: &str) {
+    if html.len() > GMAIL_CLIP_BYTES {
+        tracing::warn!(
+            size_in_bytes = html.len(),
+            "The HTML body exceeds {} bytes and will be clipped by Gmail.",
+            GMAIL_CLIP_BYTES
+        );
+    }
+}
+
+fn remove_event_handlers(el: &mut Element<'_, '_>) {
+    let handlers: Vec<String> = el
+        .attributes()
+        .iter()
+        .map(|attribute| attribute.name())
+        .filter(|name| name.starts_with("on"))
+        .collect();
+    for name in handlers {
+        el.remove_attribute(&name);
+    }
+}
+
+/// Make relative URLs absolute and drop URLs that run code when followed.
+fn resolve_urls(
+    el: &mut Element<'_, '_>,
+    base_url: &Url,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    for name in URL_ATTRIBUTES {
+        let Some(value) = el.get_attribute(name) else {
+            continue;
+        };
+        if value.starts_with('#') {
+            continue;
+        }
+        let resolved = match Url::parse(value.trim()) {
+            Ok(url) => url,
+            Err(_) => match base_url.join(value.trim()) {
+                Ok(url) => url,
+                Err(_) => continue,
+            },
+        };
+        if is_active(&resolved) {
+            el.remove_attribute(name);
+        } else if resolved.as_str() != value {
+            el.set_attribute(name, resolved.as_str())?;
+        }
+    }
+    Ok(())
+}
+
+/// `javascript:` and `vbscript:` URLs, and `data:` URLs of anything but a raster image,
+/// such as `data:text/html` or an SVG, which can carry scripts.
+fn is_active(url: &Url) -> bool {
+    match url.scheme() {
+        "javascript" | "vbscript" => true,
+        "data" => {
+            let media_type = url.path().trim_start().to_ascii_lowercase();
+            !media_type.starts_with("image/") || media_type.starts_with("image/svg")
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_pipeline::prepare_html;
+
+    const BASE_URL: &str = "https://news.example.com";
+
+    #[test]
+    fn stylesheet_rules_are_inlined() {
+        let html = "<html><head><style>p { color: red; }</style></head>\
+                    <body><p>Hi</p></body></html>";
+
+        let prepared = prepare_html(html, BASE_URL).unwrap();
+
+        assert!(prepared.contains("<p style=\"color: red;\">Hi</p>"));
+        assert!(!prepared.contains("<style"));
+    }
+
+    #[test]
+    fn scripts_iframes_and_event_handlers_are_stripped() {
+        let html = "<p onclick=\"steal()\" class=\"intro\">Hi</p>\
+                    <script>steal()</script><iframe src=\"https://evil.example\"></iframe>\
+                    <a href=\"javascript:steal()\">click</a>";
+
+        let prepared = prepare_html(html, BASE_URL).unwrap();
+
+        assert!(prepared.contains("<p class=\"intro\">Hi</p>"));
+        assert!(prepared.contains("<a>click</a>"));
+        for removed in ["onclick", "<script", "steal()", "<iframe", "javascript:"] {
+            assert!(!prepared.contains(removed), "{} was not removed", removed);
+        }
+    }
+
+    #[test]
+    fn urls_that_run_code_are_dropped_and_inline_images_kept() {
+        let html = "<a href=\"VBScript:MsgBox(1)\">vb</a>\
+                    <a href=\"data:text/html;base64,PHNjcmlwdD4=\">html</a>\
+                    <img src=\"data:image/svg+xml,%3Csvg%3E\">\
+                    <img src=\"data:image/png;base64,iVBORw0KGgo=\">";
+
+        let prepared = prepare_html(html, BASE_URL).unwrap();
+
+        assert!(prepared.contains("<a>vb</a><a>html</a><img>"));
+        assert!(prepared.contains("<img src=\"data:image/png;base64,iVBORw0KGgo=\">"));
+    }
+
+    #[test]
+    fn relative_urls_are_resolved_against_the_base_url() {
+        let html = "<a href=\"/posts/1\">post</a><img src=\"images/logo.png\">\
+                    <a href=\"https://other.example/x\">other</a><a href=\"mailto:hi@example.com\">mail</a>";
+
+        let prepared = prepare_html(html, BASE_URL).unwrap();
+
+        assert!(prepared.contains("href=\"https://news.example.com/posts/1\""));
+        assert!(prepared.contains("src=\"https://news.example.com/images/logo.png\""));
+        assert!(prepared.contains("href=\"https://other.example/x\""));
+        assert!(prepared.contains("href=\"mailto:hi@example.com\""));
+    }
+}
@@ -1,9 +1,14 @@
+use std::time::Duration;
+
 use axum::{
-    http::{header::WWW_AUTHENTICATE, StatusCode},
+    http::{
+        header::{RETRY_AFTER, WWW_AUTHENTICATE},
+        StatusCode,
+    },
     response::{IntoResponse, Response},
 };
 
-#[derive(thiserror::Error, Debug)]
+#[derive(thiserror::Error, Debug, Default)]
 pub enum Error {
     #[error("Authentication required")]
     Unauthorized,
@@ -12,8 +17,19 @@ pub enum Error {
     Forbidden,
 
     #[error("Request path not found")]
+    #[default]
     NotFound,
 
+    #[error("{0}")]
+    BadRequest(String),
+
+    #[error("{0}")]
+    Conflict(String),
+
+    /// Carries the time to wait before retrying.
+    #[error("Too many requests")]
+    TooManyRequests(Duration),
+
     #[error("An error occurred with the database")]
     Sqlx(#[from] sqlx::Error),
 
@@ -21,17 +37,14 @@ pub enum Error {
     Anyhow(#[from] anyhow::Error),
 }
 
-impl Default for Error {
-    fn default() -> Self {
-        Self::NotFound
-    }
-}
-
 impl Error {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::Unauthorized => StatusCode::UNAUTHORIZED,
             Self::NotFound => StatusCode::NOT_FOUND,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
             Self::Sqlx(_) | Self::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::Forbidden => StatusCode::FORBIDDEN,
         }
@@ -49,6 +62,17 @@ impl IntoResponse for Error {
                 .into_response();
         }
 
+        if let Self::TooManyRequests(retry_after) = self {
+            // Round up, so clients never retry before the window ends.
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            return (
+                self.status_code(),
+                [(RETRY_AFTER, seconds.max(1).to_string())],
+                self.to_string(),
+            )
+                .into_response();
+        }
+
         (self.status_code(), self.to_string()).into_response()
     }
 }
@@ -0,0 +1,358 @@
+use std::time::Duration;
+
+use serde_json::Value;
+use sqlx::{types::Uuid, PgPool, Postgres, Transaction};
+use tokio_util::sync::CancellationToken;
+use tracing::{field::display, Span};
+
+use crate::{
+    configuration::ApplicationSettings,
+    domain::SubscriberEmail,
+    email_client::{EmailClient, SendEmailError},
+    email_pipeline::{prepare_html, warn_if_clipped},
+    telemetry,
+    templates::{
+        load_templates, unsubscribe_url, RenderContext, RenderedEmail, SubscriberContext,
+        ISSUE_TEMPLATE,
+    },
+    tracking::Tracker,
+};
+
+/// Failed deliveries are retried with exponential backoff until this many attempts were made.
+const MAX_RETRIES: i16 = 5;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Deliver queued emails until `shutdown` is cancelled. A task being delivered at that point
+/// is finished first, so its lock is released and its outcome recorded.
+pub async fn run_worker_until_stopped(
+    connection_pool: PgPool,
+    email_client: EmailClient,
+    application: ApplicationSettings,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    while !shutdown.is_cancelled() {
+        let pause = match try_execute_task(&connection_pool, &email_client, &application).await {
+            Ok(ExecutionOutcome::EmptyQueue) => Duration::from_secs(10),
+            Err(_) => Duration::from_secs(1),
+            Ok(ExecutionOutcome::TaskCompleted) => continue,
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(pause) => {}
+            _ = shutdown.cancelled() => {}
+        }
+    }
+    Ok(())
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id = tracing::field::Empty, subscriber_email = tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    connection_pool: &PgPool,
+    email_client: &EmailClient,
+    application: &ApplicationSettings,
+) -> anyhow::Result<ExecutionOutcome> {
+    let Some((transaction, task)) = dequeue_task(connection_pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current()
+        .record("newsletter_issue_id", display(task.newsletter_issue_id))
+        .record(
+            "subscriber_email",
+            display(telemetry::RedactedEmail(&task.subscriber_email)),
+        );
+
+    let email = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+        Ok(email) => email,
+        // The parse error quotes the address, so it is left out of the logs.
+        Err(_) => {
+            tracing::error!(
+                "Skipping a confirmed subscriber. Their stored contact details are invalid."
+            );
+            complete_task(transaction, &task, DeliveryOutcome::Skipped).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    let Some(recipient) = get_recipient(connection_pool, &task.subscriber_email).await? else {
+        tracing::info!("Skipping a subscriber who is no longer confirmed.");
+        complete_task(transaction, &task, DeliveryOutcome::Skipped).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    };
+
+    let issue = get_issue(connection_pool, task.newsletter_issue_id).await?;
+    if let Some(traceparent) = &issue.traceparent {
+        telemetry::link_to_traceparent(&Span::current(), traceparent);
+    }
+    let rendered = match render_issue(connection_pool, &issue, recipient, application).await {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to render issue for a confirmed subscriber."
+            );
+            retry_task(transaction, &task).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    match email_client
+        .send_email(&email, &issue.title, &rendered.html, &rendered.text)
+        .await
+    {
+        Ok(()) => complete_task(transaction, &task, DeliveryOutcome::Sent).await?,
+        Err(SendEmailError::Throttled(retry_after)) => {
+            tracing::warn!("The email provider throttled a delivery. Postponing it.");
+            postpone_task(transaction, &task, retry_after).await?;
+        }
+        Err(SendEmailError::CircuitOpen(retry_after)) => {
+            tracing::warn!("Every email provider is failing. Postponing a delivery.");
+            postpone_task(transaction, &task, retry_after).await?;
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber."
+            );
+            retry_task(transaction, &task).await?;
+        }
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+/// Lock the next due task, skipping rows already locked by other workers.
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(pool: &PgPool) -> anyhow::Result<Option<(PgTransaction, DeliveryTask)>> {
+    let mut transaction = pool.begin().await?;
+    let task = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    Ok(task.map(|task| (transaction, task)))
+}
+
+/// How a delivery task left the queue, as recorded in `issue_deliveries`.
+enum DeliveryOutcome {
+    Sent,
+    /// Every attempt failed.
+    Failed,
+    /// The subscriber could not or should no longer be emailed.
+    Skipped,
+}
+
+impl DeliveryOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryOutcome::Sent => "sent",
+            DeliveryOutcome::Failed => "failed",
+            DeliveryOutcome::Skipped => "skipped",
+        }
+    }
+}
+
+/// Remove the task from the queue and record its outcome.
+#[tracing::instrument(skip_all)]
+async fn complete_task(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+    outcome: DeliveryOutcome,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries
+            (newsletter_issue_id, subscriber_email, outcome, attempts, completed_at)
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email)
+        DO UPDATE SET outcome = $3, attempts = $4, completed_at = now()
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        outcome.as_str(),
+        task.n_retries + 1
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Push the task back with exponential backoff, or drop it once it ran out of retries.
+#[tracing::instrument(skip_all)]
+async fn retry_task(mut transaction: PgTransaction, task: &DeliveryTask) -> anyhow::Result<()> {
+    let n_retries = task.n_retries + 1;
+    if n_retries >= MAX_RETRIES {
+        tracing::error!(
+            "Giving up on delivering issue after {} attempts.",
+            n_retries
+        );
+        return complete_task(transaction, task, DeliveryOutcome::Failed).await;
+    }
+
+    let backoff_seconds = 2_f64.powi(n_retries.into());
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = $3, execute_after = now() + make_interval(secs => $4)
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        n_retries,
+        backoff_seconds
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Push the task back without counting an attempt, since it was never made.
+#[tracing::instrument(skip_all)]
+async fn postpone_task(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+    delay: Duration,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET execute_after = now() + make_interval(secs => $3)
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        delay.as_secs_f64()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+struct NewsletterIssue {
+    id: Uuid,
+    tracking_enabled: bool,
+    traceparent: Option<String>,
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> anyhow::Result<NewsletterIssue> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT i.id, i.tracking_enabled, i.traceparent, r.title, r.text_content, r.html_content
+        FROM newsletter_issues i
+        JOIN newsletter_issue_revisions r
+            ON r.newsletter_issue_id = i.id AND r.revision = i.current_revision
+        WHERE i.id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(issue)
+}
+
+struct Recipient {
+    id: Uuid,
+    name: String,
+    email: String,
+    attributes: Value,
+    unsubscribe_token: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_recipient(pool: &PgPool, email: &str) -> anyhow::Result<Option<Recipient>> {
+    let recipient = sqlx::query_as!(
+        Recipient,
+        r#"
+        SELECT id, name, email, attributes, unsubscribe_token
+        FROM subscriptions
+        WHERE email = $1 AND status = 'confirmed'
+        "#,
+        email
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(recipient)
+}
+
+/// Render the issue content, with the stored layouts and partials, for one recipient and
+/// prepare its HTML body for sending, with tracking if the issue opted into it.
+#[tracing::instrument(skip_all)]
+async fn render_issue(
+    pool: &PgPool,
+    issue: &NewsletterIssue,
+    recipient: Recipient,
+    application: &ApplicationSettings,
+) -> anyhow::Result<RenderedEmail> {
+    let base_url = &application.base_url;
+    let mut templates = load_templates(pool).await?;
+    templates
+        .add_issue(&issue.html_content, &issue.text_content)
+        .map_err(anyhow::Error::msg)?;
+    let recipient_id = recipient.id;
+    let context = RenderContext {
+        unsubscribe_url: unsubscribe_url(base_url, &recipient.unsubscribe_token, Some(issue.id)),
+        subscriber: SubscriberContext {
+            name: recipient.name,
+            email: recipient.email,
+            attributes: recipient.attributes,
+        },
+    };
+    let rendered = templates
+        .render(ISSUE_TEMPLATE, &context)
+        .map_err(anyhow::Error::msg)?;
+    let mut html = prepare_html(&rendered.html, base_url)?;
+    if issue.tracking_enabled {
+        html = Tracker::new(base_url, &application.hmac_secret).add_tracking(
+            &html,
+            issue.id,
+            recipient_id,
+        )?;
+    }
+    warn_if_clipped(&html);
+    Ok(RenderedEmail {
+        html,
+        text: rendered.text,
+    })
+}
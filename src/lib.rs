@@ -1,63 +1,48 @@
 use std::sync::Arc;
 
-use axum::{
-    routing::{get, post},
-    serve::Serve,
-    Router,
-};
+use bot_protection::BotProtection;
+use configuration::ApplicationSettings;
 use email_client::EmailClient;
-use routes::{health_check, subscribe};
-use sqlx::PgPool;
-use tokio::net::TcpListener;
-use tower_http::{
-    cors::{Any, CorsLayer},
-    trace::{DefaultMakeSpan, DefaultOnFailure, DefaultOnRequest, DefaultOnResponse, TraceLayer},
-};
+use metrics_exporter_prometheus::PrometheusHandle;
+use rate_limit::RateLimiter;
+use redis::aio::ConnectionManager;
+use sqlx::{migrate::Migrator, PgPool};
 
+pub mod bot_protection;
+pub mod circuit_breaker;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_pipeline;
 pub mod error;
+pub mod html;
+pub mod issue_delivery_worker;
+pub mod markdown;
+pub mod prometheus;
+pub mod rate_limit;
 pub mod routes;
+pub mod scheduler;
+pub mod segments;
+pub mod send_throttle;
+pub mod shutdown;
 pub mod startup;
+pub mod telemetry;
+pub mod templates;
+pub mod tracking;
 
 pub type Result<T, E = error::Error> = std::result::Result<T, E>;
 
+/// Migrations embedded at build time; readiness checks compare the database against them.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
 #[derive(Clone)]
 pub struct ApiContext {
     connection_pool: PgPool,
     email_client: Arc<EmailClient>,
-}
-
-pub async fn run(
-    listener: TcpListener,
-    connection_pool: PgPool,
-    email_client: EmailClient,
-) -> anyhow::Result<Serve<Router, Router>> {
-    let app_context = ApiContext {
-        connection_pool,
-        email_client: Arc::new(email_client),
-    };
-
-    sqlx::migrate!().run(&app_context.connection_pool).await?;
-
-    let app_router = Router::new()
-        .route("/health_check", get(health_check))
-        .route("/subscriptions", post(subscribe))
-        .layer(
-            TraceLayer::new_for_http()
-                .make_span_with(DefaultMakeSpan::new().include_headers(true))
-                .on_request(DefaultOnRequest::new().level(tracing::Level::INFO))
-                .on_response(DefaultOnResponse::new().level(tracing::Level::INFO))
-                .on_failure(DefaultOnFailure::new().level(tracing::Level::ERROR)),
-        )
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods(Any)
-                .allow_headers(Any),
-        )
-        .with_state(app_context);
-
-    Ok(axum::serve(listener, app_router))
+    application: Arc<ApplicationSettings>,
+    bot_protection: Arc<BotProtection>,
+    rate_limiter: Arc<RateLimiter>,
+    /// Shared connection to Redis, `None` if it could not be reached at startup.
+    redis: Option<ConnectionManager>,
+    metrics: PrometheusHandle,
 }
@@ -1,38 +1,72 @@
 use anyhow::Context;
 use newsletter_deliverer::{
-    configuration::{get_configuration, DatabaseSettings},
-    run,
+    configuration::{get_configuration, LogFormat},
+    shutdown::ShutdownSignal,
+    startup::Application,
+    telemetry::{init_tracer_provider, log_subscriber_emails, otel_layer},
 };
-use sqlx::postgres::PgPoolOptions;
-use std::net::{Ipv4Addr, SocketAddr};
-use tokio::net::TcpListener;
-use tracing_subscriber::fmt::format::FmtSpan;
+use std::process::ExitCode;
+use tracing_subscriber::{fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt};
+
+const USAGE: &str = "Usage: newsletter-deliverer [config check]";
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
     dotenv::dotenv().ok();
 
-    tracing_subscriber::fmt()
-        .with_span_events(FmtSpan::FULL)
-        .init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => serve().await.map(|()| ExitCode::SUCCESS),
+        ["config", "check"] => Ok(check_configuration()),
+        _ => {
+            eprintln!("{}", USAGE);
+            Ok(ExitCode::from(2))
+        }
+    }
+}
 
-    let configuration = get_configuration().expect("Failed to read configuration.");
-    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, configuration.application_port));
-    let listener = TcpListener::bind(addr)
-        .await
-        .expect("Failed to bind to addr");
+/// Load and validate the configuration without starting anything.
+fn check_configuration() -> ExitCode {
+    match get_configuration() {
+        Ok(_) => {
+            println!("The configuration is valid.");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    let connection_pool = get_connection_pool(&configuration.database);
-    let email_client = configuration.email_client.client();
+async fn serve() -> anyhow::Result<()> {
+    let configuration = get_configuration().context("Failed to read configuration")?;
 
-    run(listener, connection_pool, email_client)
-        .await
-        .unwrap()
+    let tracer_provider =
+        init_tracer_provider(&configuration.telemetry).context("Failed to set up trace export")?;
+    let json_logs = configuration.telemetry.log_format == LogFormat::Json;
+    tracing_subscriber::registry()
+        .with(
+            (!json_logs).then(|| tracing_subscriber::fmt::layer().with_span_events(FmtSpan::FULL)),
+        )
+        .with(json_logs.then(|| {
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_span_events(FmtSpan::FULL)
+        }))
+        .with(tracer_provider.as_ref().map(otel_layer))
+        .init();
+    log_subscriber_emails(configuration.telemetry.log_subscriber_emails);
+
+    let shutdown = ShutdownSignal::listen().context("Failed to listen for shutdown signals")?;
+    let application = Application::build(configuration)
         .await
-        .context("Error running HTTP server")?;
+        .context("Failed to build the application")?;
+    application.run_until_stopped(shutdown.recv()).await;
+    if let Some(tracer_provider) = tracer_provider {
+        tracer_provider
+            .shutdown()
+            .context("Failed to export the last traces")?;
+    }
     Ok(())
 }
-
-pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
-    PgPoolOptions::new().connect_lazy_with(configuration.with_db())
-}
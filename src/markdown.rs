@@ -0,0 +1,300 @@
+//! Turns an issue written in Markdown into the HTML and plain-text bodies of an email.
+//!
+//! The HTML version is wrapped in a table-based layout and styled with inline `style`
+//! attributes only, since most email clients ignore `<style>` blocks. The plain-text version
+//! keeps the structure readable and lists link targets as numbered footnotes.
+//!
+//! Template tags such as `{{ subscriber.name }}` pass through untouched, so they are still
+//! rendered per recipient afterwards.
+
+use pulldown_cmark::{html, CowStr, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+
+use crate::html::escape;
+
+const BODY_STYLE: &str = "padding:32px;font-family:Helvetica,Arial,sans-serif;font-size:16px;\
+                          line-height:1.5;color:#222222;";
+const LINK_STYLE: &str = "color:#1a73e8;text-decoration:underline;";
+const CODE_STYLE: &str = "font-family:Menlo,Consolas,monospace;font-size:14px;\
+                          background-color:#f4f4f4;";
+
+fn parser(markdown: &str) -> Parser<'_> {
+    Parser::new_ext(markdown, Options::ENABLE_STRIKETHROUGH)
+}
+
+/// Render Markdown as a complete, email-safe HTML document.
+pub fn to_email_html(markdown: &str) -> String {
+    let mut content = String::new();
+    html::push_html(&mut content, StyledEvents::new(parser(markdown)));
+
+    format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width\"></head>\n\
+         <body style=\"margin:0;padding:0;background-color:#f4f4f4;\">\n\
+         <table role=\"presentation\" width=\"100%\" cellpadding=\"0\" cellspacing=\"0\" border=\"0\" \
+         style=\"background-color:#f4f4f4;\">\n\
+         <tr><td align=\"center\" style=\"padding:24px 12px;\">\n\
+         <table role=\"presentation\" width=\"600\" cellpadding=\"0\" cellspacing=\"0\" border=\"0\" \
+         style=\"max-width:600px;width:100%;background-color:#ffffff;\">\n\
+         <tr><td style=\"{}\">\n\
+         {}\
+         </td></tr>\n\
+         </table>\n\
+         </td></tr>\n\
+         </table>\n\
+         </body>\n\
+         </html>\n",
+        BODY_STYLE, content
+    )
+}
+
+/// Replaces the tags `push_html` would emit with inline-styled ones.
+struct StyledEvents<'a> {
+    events: Parser<'a>,
+}
+
+impl<'a> StyledEvents<'a> {
+    fn new(events: Parser<'a>) -> Self {
+        Self { events }
+    }
+
+    /// Consume the alt text of an image up to its end tag.
+    fn image_alt(&mut self) -> String {
+        let mut alt = String::new();
+        let mut depth = 0;
+        for event in self.events.by_ref() {
+            match event {
+                Event::Start(_) => depth += 1,
+                Event::End(TagEnd::Image) if depth == 0 => break,
+                Event::End(_) => depth -= 1,
+                Event::Text(text) | Event::Code(text) => alt.push_str(&text),
+                _ => {}
+            }
+        }
+        alt
+    }
+}
+
+impl<'a> Iterator for StyledEvents<'a> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Event<'a>> {
+        let html = match self.events.next()? {
+            Event::Start(Tag::Paragraph) => "<p style=\"margin:0 0 16px;\">".to_owned(),
+            Event::Start(Tag::Heading { level, .. }) => {
+                let font_size = match level {
+                    HeadingLevel::H1 => 28,
+                    HeadingLevel::H2 => 22,
+                    HeadingLevel::H3 => 18,
+                    _ => 16,
+                };
+                format!(
+                    "<{} style=\"margin:24px 0 12px;font-size:{}px;line-height:1.3;\">",
+                    level, font_size
+                )
+            }
+            Event::Start(Tag::BlockQuote(_)) => "<blockquote style=\"margin:0 0 16px;\
+                padding:0 0 0 12px;border-left:4px solid #dddddd;color:#555555;\">"
+                .to_owned(),
+            Event::End(TagEnd::BlockQuote(_)) => "</blockquote>\n".to_owned(),
+            Event::Start(Tag::CodeBlock(_)) => format!(
+                "<pre style=\"margin:0 0 16px;padding:12px;white-space:pre-wrap;{}\"><code>",
+                CODE_STYLE
+            ),
+            Event::End(TagEnd::CodeBlock) => "</code></pre>\n".to_owned(),
+            Event::Start(Tag::List(Some(start))) => format!(
+                "<ol start=\"{}\" style=\"margin:0 0 16px;padding-left:24px;\">",
+                start
+            ),
+            Event::Start(Tag::List(None)) => {
+                "<ul style=\"margin:0 0 16px;padding-left:24px;\">".to_owned()
+            }
+            Event::Start(Tag::Item) => "<li style=\"margin:0 0 4px;\">".to_owned(),
+            Event::Start(Tag::Link {
+                dest_url, title, ..
+            }) => {
+                let title = if title.is_empty() {
+                    String::new()
+                } else {
+                    format!(" title=\"{}\"", escape(&title))
+                };
+                format!(
+                    "<a href=\"{}\"{} style=\"{}\">",
+                    escape(&dest_url),
+                    title,
+                    LINK_STYLE
+                )
+            }
+            Event::Start(Tag::Image { dest_url, .. }) => format!(
+                "<img src=\"{}\" alt=\"{}\" style=\"display:block;max-width:100%;height:auto;border:0;\">",
+                escape(&dest_url),
+                escape(&self.image_alt())
+            ),
+            Event::Code(code) => format!(
+                "<code style=\"padding:0 4px;{}\">{}</code>",
+                CODE_STYLE,
+                escape(&code)
+            ),
+            Event::Rule => {
+                "<hr style=\"border:none;border-top:1px solid #dddddd;margin:24px 0;\">\n"
+                    .to_owned()
+            }
+            event => return Some(event),
+        };
+        Some(Event::Html(CowStr::from(html)))
+    }
+}
+
+/// Render Markdown as plain text, with links turned into numbered footnotes.
+pub fn to_plain_text(markdown: &str) -> String {
+    let mut text = String::new();
+    let mut links: Vec<String> = Vec::new();
+    // Ordered lists hold their next item number.
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut heading_start = None;
+    let mut quote_starts = Vec::new();
+    let mut link_starts = Vec::new();
+
+    for event in parser(markdown) {
+        match event {
+            Event::Start(Tag::Heading { .. }) => heading_start = Some(text.len()),
+            Event::End(TagEnd::Heading(level)) => {
+                if let Some(start) = heading_start.take() {
+                    let width = text[start..].chars().count();
+                    match level {
+                        HeadingLevel::H1 => text.push_str(&format!("\n{}", "=".repeat(width))),
+                        HeadingLevel::H2 => text.push_str(&format!("\n{}", "-".repeat(width))),
+                        _ => {}
+                    }
+                }
+                text.push_str("\n\n");
+            }
+            Event::End(TagEnd::Paragraph) | Event::End(TagEnd::CodeBlock) => {
+                end_line(&mut text);
+                if lists.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::Start(Tag::BlockQuote(_)) => quote_starts.push(text.len()),
+            Event::End(TagEnd::BlockQuote(_)) => {
+                if let Some(start) = quote_starts.pop() {
+                    let quoted = text.split_off(start);
+                    for line in quoted.trim_end().lines() {
+                        text.push_str(format!("> {}", line).trim_end());
+                        text.push('\n');
+                    }
+                    text.push('\n');
+                }
+            }
+            Event::Start(Tag::List(start)) => {
+                end_line(&mut text);
+                lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                end_line(&mut text);
+                if lists.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::Start(Tag::Item) => {
+                end_line(&mut text);
+                text.push_str(&"   ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        text.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => text.push_str("- "),
+                }
+            }
+            Event::Start(Tag::Link { dest_url, .. })
+            | Event::Start(Tag::Image { dest_url, .. }) => {
+                link_starts.push((text.len(), dest_url.into_string()));
+            }
+            Event::End(TagEnd::Link) | Event::End(TagEnd::Image) => {
+                if let Some((start, url)) = link_starts.pop() {
+                    // Autolinks already show their target.
+                    if text[start..] != url {
+                        links.push(url);
+                        text.push_str(&format!(" [{}]", links.len()));
+                    }
+                }
+            }
+            Event::Text(value) | Event::Code(value) => text.push_str(&value),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::Rule => text.push_str("----------\n\n"),
+            _ => {}
+        }
+    }
+
+    let mut text = text.trim_end().to_owned();
+    if !links.is_empty() {
+        text.push_str("\n\n");
+        for (index, url) in links.iter().enumerate() {
+            text.push_str(&format!("[{}] {}\n", index + 1, url));
+        }
+    }
+    let mut text = text.trim_end().to_owned();
+    text.push('\n');
+    text
+}
+
+fn end_line(text: &mut String) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markdown::{to_email_html, to_plain_text};
+
+    #[test]
+    fn html_is_wrapped_in_a_table_layout_with_inline_styles() {
+        let html = to_email_html("# Hello\n\nRead [the post](https://example.com/post).");
+
+        assert!(html.contains("<table role=\"presentation\""));
+        assert!(html.contains("<h1 style=\""));
+        assert!(html.contains(
+            "<a href=\"https://example.com/post\" style=\"color:#1a73e8;text-decoration:underline;\">the post</a>"
+        ));
+        assert!(!html.contains("<style"));
+    }
+
+    #[test]
+    fn template_tags_survive_markdown_rendering() {
+        let markdown =
+            "{% if subscriber.attributes.plan == \"pro\" %}Hi {{ subscriber.name }}{% endif %}";
+
+        assert!(to_email_html(markdown).contains(markdown));
+        assert_eq!(to_plain_text(markdown), format!("{}\n", markdown));
+    }
+
+    #[test]
+    fn links_become_numbered_footnotes_in_plain_text() {
+        let text = to_plain_text(
+            "See [the docs](https://example.com/docs) and [the blog](https://example.com/blog), \
+             or <https://example.com>.",
+        );
+
+        assert_eq!(
+            text,
+            "See the docs [1] and the blog [2], or https://example.com.\n\n\
+             [1] https://example.com/docs\n\
+             [2] https://example.com/blog\n"
+        );
+    }
+
+    #[test]
+    fn plain_text_keeps_headings_lists_and_quotes_readable() {
+        let text = to_plain_text(
+            "# Weekly\n\nThree things:\n\n1. One\n2. Two\n   - nested\n3. Three\n\n> Quoted\n\n---\n\nBye",
+        );
+
+        assert_eq!(
+            text,
+            "Weekly\n======\n\nThree things:\n\n1. One\n2. Two\n   - nested\n3. Three\n\n> Quoted\n\n----------\n\nBye\n"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Prometheus metrics, served at `GET /metrics`.
+//!
+//! Counters and histograms are recorded where things happen, through the `metrics` macros.
+//! Gauges of the database pool, the delivery queue and the email provider circuits are
+//! sampled when Prometheus scrapes us.
+
+use std::{sync::OnceLock, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header::CONTENT_TYPE,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics::{describe_counter, describe_gauge, describe_histogram, Unit};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use sqlx::PgPool;
+
+use crate::{circuit_breaker::BreakerState, email_client::EmailClient, ApiContext};
+
+/// Buckets of every `*_duration_seconds` histogram, from 5ms to 30s.
+const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the process-wide recorder on first use; later calls, such as from every app
+/// spawned by the tests, share it.
+pub fn install_recorder() -> PrometheusHandle {
+    HANDLE
+        .get_or_init(|| {
+            let handle = PrometheusBuilder::new()
+                .set_buckets_for_metric(
+                    Matcher::Suffix("_duration_seconds".into()),
+                    DURATION_BUCKETS,
+                )
+                .expect("The duration buckets are not empty.")
+                .install_recorder()
+                .expect("Failed to install the Prometheus recorder.");
+            describe_metrics();
+            handle
+        })
+        .clone()
+}
+
+fn describe_metrics() {
+    describe_counter!("http_requests_total", "HTTP requests by route and status.");
+    describe_histogram!(
+        "http_request_duration_seconds",
+        Unit::Seconds,
+        "Time to respond to HTTP requests, by route and status."
+    );
+    describe_counter!(
+        "subscriptions_total",
+        "Signups by outcome: accepted, invalid or duplicate."
+    );
+    describe_counter!(
+        "email_send_attempts_total",
+        "Requests made to an email provider."
+    );
+    describe_counter!(
+        "email_send_failures_total",
+        "Failed requests to an email provider, by error class."
+    );
+    describe_counter!(
+        "email_sends_short_circuited_total",
+        "Sends not attempted through a provider because its circuit was open."
+    );
+    describe_histogram!(
+        "email_send_duration_seconds",
+        Unit::Seconds,
+        "Time an email provider took to answer."
+    );
+    describe_gauge!(
+        "email_provider_circuit_state",
+        "Circuit of an email provider: 0 closed, 1 half-open, 2 open."
+    );
+    describe_gauge!(
+        "issue_delivery_queue_depth",
+        "Delivery tasks waiting in the queue."
+    );
+    describe_gauge!(
+        "issue_delivery_queue_oldest_task_age_seconds",
+        Unit::Seconds,
+        "Time since the oldest task in the delivery queue was enqueued."
+    );
+    describe_gauge!(
+        "db_pool_connections",
+        "Database connections held by the pool, by state."
+    );
+    describe_gauge!(
+        "db_pool_max_connections",
+        "Most database connections the pool may open."
+    );
+}
+
+/// Count and time every request by its route template, such as `/admin/newsletters/:id`.
+pub async fn track_http_metrics(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".into());
+    let method = request.method().to_string();
+
+    let response = next.run(request).await;
+
+    let labels = [
+        ("method", method),
+        ("route", route),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    metrics::counter!("http_requests_total", &labels).increment(1);
+    metrics::histogram!("http_request_duration_seconds", &labels)
+        .record(start.elapsed().as_secs_f64());
+    response
+}
+
+pub async fn metrics_endpoint(ctx: State<ApiContext>) -> crate::Result<impl IntoResponse> {
+    record_pool_usage(&ctx.connection_pool);
+    record_queue(&ctx.connection_pool).await?;
+    record_circuits(&ctx.email_client);
+    Ok((
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        ctx.metrics.render(),
+    ))
+}
+
+fn record_pool_usage(pool: &PgPool) {
+    let idle = pool.num_idle() as f64;
+    metrics::gauge!("db_pool_connections", "state" => "idle").set(idle);
+    metrics::gauge!("db_pool_connections", "state" => "in_use").set(pool.size() as f64 - idle);
+    metrics::gauge!("db_pool_max_connections").set(pool.options().get_max_connections() as f64);
+}
+
+async fn record_queue(pool: &PgPool) -> crate::Result<()> {
+    let queue = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) AS "depth!",
+            COALESCE(EXTRACT(EPOCH FROM now() - MIN(enqueued_at))::float8, 0) AS "oldest_task_age!"
+        FROM issue_delivery_queue
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+    metrics::gauge!("issue_delivery_queue_depth").set(queue.depth as f64);
+    metrics::gauge!("issue_delivery_queue_oldest_task_age_seconds").set(queue.oldest_task_age);
+    Ok(())
+}
+
+fn record_circuits(email_client: &EmailClient) {
+    for provider in email_client.provider_health() {
+        let state = match provider.state {
+            BreakerState::Closed => 0.0,
+            BreakerState::HalfOpen => 1.0,
+            BreakerState::Open => 2.0,
+        };
+        metrics::gauge!("email_provider_circuit_state", "provider" => provider.name).set(state);
+    }
+}
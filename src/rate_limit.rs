@@ -0,0 +1,337 @@
+//! Rate limiting of the public signup endpoints, per client IP and per target email
+//! address.
+//!
+//! Counters live in Redis so every application instance shares them. If Redis cannot be
+//! reached, each instance falls back to counting in memory, which still stops a single
+//! client hammering a single instance.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, Request, State},
+    http::{header::CONTENT_TYPE, HeaderMap},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+use redis::aio::ConnectionManager;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    configuration::{RateLimitSettings, WindowLimit},
+    error::Error,
+    ApiContext,
+};
+
+/// Request bodies are buffered to find the target email address; signups are tiny.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+const REDIS_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct RateLimiter {
+    trusted_proxies: Vec<IpNet>,
+    per_ip: WindowLimit,
+    per_email: WindowLimit,
+    redis: Option<ConnectionManager>,
+    memory: MemoryStore,
+}
+
+impl RateLimiter {
+    pub fn in_memory(settings: &RateLimitSettings) -> Self {
+        Self::new(settings, None)
+    }
+
+    /// Count in Redis, or in memory only if `redis` is `None` or fails.
+    pub fn new(settings: &RateLimitSettings, redis: Option<ConnectionManager>) -> Self {
+        Self {
+            trusted_proxies: settings.trusted_proxies.clone(),
+            per_ip: settings.per_ip.clone(),
+            per_email: settings.per_email.clone(),
+            redis,
+            memory: MemoryStore::default(),
+        }
+    }
+
+    /// The address of the client, taken from `X-Forwarded-For` when the request came
+    /// through trusted proxies. The chain is walked from the right, since only the entries
+    /// added by our own proxies can be trusted: the client is the first untrusted address,
+    /// or the last address reached before an entry that does not parse. Anything further left
+    /// was written by the client and is never used.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer;
+        if !self.is_trusted(client) {
+            return client;
+        }
+        let forwarded: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        for entry in forwarded.into_iter().rev() {
+            let Ok(ip) = entry.trim().parse() else {
+                break;
+            };
+            client = ip;
+            if !self.is_trusted(client) {
+                break;
+            }
+        }
+        client
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Count a request from `ip`, failing with the time to wait once over the limit.
+    pub async fn check_ip(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check("ip", &ip.to_string(), &self.per_ip).await
+    }
+
+    /// Count a request targeting `email`, failing with the time to wait once over the limit.
+    /// Addresses are hashed, so they do not end up in Redis.
+    pub async fn check_email(&self, email: &str) -> Result<(), Duration> {
+        let digest = Sha256::digest(email.trim().to_lowercase().as_bytes());
+        self.check("email", &hex::encode(digest), &self.per_email)
+            .await
+    }
+
+    async fn check(&self, scope: &str, key: &str, limit: &WindowLimit) -> Result<(), Duration> {
+        let window = limit.window_seconds.max(1);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("The system clock is set before 1970.")
+            .as_secs();
+        let window_index = now / window;
+        let window_end = (window_index + 1) * window;
+        let key = format!("rate_limit:{}:{}:{}", scope, key, window_index);
+
+        let count = match self.count_in_redis(&key, window).await {
+            Some(count) => count,
+            None => self.memory.increment(&key, window_end, now),
+        };
+        if count > limit.requests {
+            return Err(Duration::from_secs(window_end - now));
+        }
+        Ok(())
+    }
+
+    async fn count_in_redis(&self, key: &str, window_seconds: u64) -> Option<u64> {
+        let mut connection = self.redis.clone()?;
+        let result: redis::RedisResult<(u64,)> = redis::pipe()
+            .atomic()
+            .incr(key, 1)
+            .expire(key, window_seconds as i64)
+            .ignore()
+            .query_async(&mut connection)
+            .await;
+        match result {
+            Ok((count,)) => Some(count),
+            Err(e) => {
+                tracing::warn!(
+                    error.message = %e,
+                    "Failed to count a request in Redis. Counting it in memory."
+                );
+                None
+            }
+        }
+    }
+}
+
+pub(crate) async fn connect_to_redis(
+    redis_uri: &Secret<String>,
+) -> anyhow::Result<ConnectionManager> {
+    let client = redis::Client::open(redis_uri.expose_secret().as_str())?;
+    let connection =
+        tokio::time::timeout(REDIS_CONNECT_TIMEOUT, client.get_connection_manager()).await??;
+    Ok(connection)
+}
+
+/// Fixed-window counters of one application instance, with the time their window ends.
+#[derive(Default)]
+struct MemoryStore {
+    counters: Mutex<HashMap<String, (u64, u64)>>,
+}
+
+impl MemoryStore {
+    /// Increment the counter of `key`, whose window ends at `window_end`, returning the new
+    /// count. Counters of windows that ended are dropped along the way.
+    fn increment(&self, key: &str, window_end: u64, now: u64) -> u64 {
+        let mut counters = self.counters.lock().unwrap();
+        counters.retain(|_, (end, _)| *end > now);
+        let (_, count) = counters.entry(key.to_owned()).or_insert((window_end, 0));
+        *count += 1;
+        *count
+    }
+}
+
+/// Rate limit a route per client IP and, if the JSON or form body has an `email` field, per
+/// target email address.
+pub async fn rate_limit(
+    State(ctx): State<ApiContext>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limiter = &ctx.rate_limiter;
+    let client_ip = limiter.client_ip(peer.ip(), request.headers());
+    if let Err(retry_after) = limiter.check_ip(client_ip).await {
+        tracing::info!(%client_ip, "Rate limited a client");
+        return Error::TooManyRequests(retry_after).into_response();
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = match to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            return Error::BadRequest("The request body is too large.".into()).into_response()
+        }
+    };
+    if let Some(email) = target_email(&parts.headers, &bytes) {
+        if let Err(retry_after) = limiter.check_email(&email).await {
+            tracing::info!("Rate limited requests targeting an email address");
+            return Error::TooManyRequests(retry_after).into_response();
+        }
+    }
+
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
+#[derive(serde::Deserialize)]
+struct EmailField {
+    email: String,
+}
+
+fn target_email(headers: &HeaderMap, body: &[u8]) -> Option<String> {
+    let is_form = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    let field: EmailField = if is_form {
+        serde_urlencoded::from_bytes(body).ok()?
+    } else {
+        serde_json::from_slice(body).ok()?
+    };
+    Some(field.email).filter(|email| !email.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use axum::http::{HeaderMap, HeaderValue};
+    use claims::{assert_err, assert_ok};
+
+    use crate::{
+        configuration::{RateLimitSettings, WindowLimit},
+        rate_limit::RateLimiter,
+    };
+
+    fn limiter(trusted_proxies: &[&str]) -> RateLimiter {
+        RateLimiter::in_memory(&RateLimitSettings {
+            trusted_proxies: trusted_proxies
+                .iter()
+                .map(|net| net.parse().unwrap())
+                .collect(),
+            per_ip: WindowLimit {
+                requests: 2,
+                window_seconds: 3600,
+            },
+            per_email: WindowLimit {
+                requests: 1,
+                window_seconds: 3600,
+            },
+        })
+    }
+
+    fn forwarded_for(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let limiter = limiter(&["10.0.0.0/8"]);
+
+        let client = limiter.client_ip(ip("203.0.113.7"), &forwarded_for("198.51.100.1"));
+
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn the_first_untrusted_address_from_the_right_is_the_client() {
+        let limiter = limiter(&["10.0.0.0/8"]);
+
+        let client = limiter.client_ip(
+            ip("10.0.0.2"),
+            &forwarded_for("192.0.2.66, 198.51.100.1, 10.0.0.1"),
+        );
+
+        assert_eq!(client, ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn the_leftmost_address_is_the_client_when_the_whole_chain_is_trusted() {
+        let limiter = limiter(&["10.0.0.0/8"]);
+
+        let client = limiter.client_ip(ip("10.0.0.2"), &forwarded_for("10.1.1.1, 10.0.0.1"));
+
+        assert_eq!(client, ip("10.1.1.1"));
+    }
+
+    #[test]
+    fn entries_left_of_the_client_cannot_hide_it() {
+        let limiter = limiter(&["10.0.0.0/8"]);
+
+        let client = limiter.client_ip(
+            ip("10.0.0.2"),
+            &forwarded_for("junk, 198.51.100.1, 10.0.0.1"),
+        );
+
+        assert_eq!(client, ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn an_unparseable_entry_stops_the_walk_at_the_last_proxy() {
+        let limiter = limiter(&["10.0.0.0/8"]);
+
+        let client = limiter.client_ip(ip("10.0.0.2"), &forwarded_for("junk, 10.0.0.1"));
+
+        assert_eq!(client, ip("10.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_limit_are_rejected_until_the_window_ends() {
+        let limiter = limiter(&[]);
+
+        assert_ok!(limiter.check_ip(ip("192.0.2.1")).await);
+        assert_ok!(limiter.check_ip(ip("192.0.2.1")).await);
+        let retry_after = limiter.check_ip(ip("192.0.2.1")).await.unwrap_err();
+
+        assert!(retry_after.as_secs() <= 3600);
+        assert_ok!(limiter.check_ip(ip("192.0.2.2")).await);
+    }
+
+    #[tokio::test]
+    async fn email_limits_ignore_case_and_whitespace() {
+        let limiter = limiter(&[]);
+
+        assert_ok!(limiter.check_email("ursula@example.com").await);
+
+        assert_err!(limiter.check_email(" Ursula@Example.com").await);
+    }
+}
@@ -0,0 +1,113 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+
+use crate::{
+    domain::{AttributeDefinition, AttributeType, SubscriberTag},
+    error::Error,
+    ApiContext,
+};
+
+#[derive(Serialize)]
+pub struct AttributeDefinitionBody {
+    pub key: String,
+    pub value_type: String,
+    pub publicly_settable: bool,
+}
+
+#[derive(Deserialize)]
+pub struct AttributeDefinitionRequest {
+    pub value_type: String,
+    #[serde(default)]
+    pub publicly_settable: bool,
+}
+
+#[derive(Deserialize)]
+pub struct TagRequest {
+    #[serde(default)]
+    pub publicly_settable: bool,
+}
+
+pub async fn list_attribute_definitions(
+    ctx: State<ApiContext>,
+) -> crate::Result<Json<Vec<AttributeDefinitionBody>>> {
+    let rows = sqlx::query_as!(
+        AttributeDefinitionBody,
+        r#"SELECT key, value_type, publicly_settable FROM attribute_definitions ORDER BY key"#
+    )
+    .fetch_all(&ctx.connection_pool)
+    .await?;
+
+    Ok(Json(rows))
+}
+
+#[tracing::instrument(name = "Saving attribute definition", skip(ctx, request))]
+pub async fn put_attribute_definition(
+    ctx: State<ApiContext>,
+    Path(key): Path<String>,
+    Json(request): Json<AttributeDefinitionRequest>,
+) -> crate::Result<StatusCode> {
+    let value_type: AttributeType = request.value_type.try_into().map_err(Error::BadRequest)?;
+    let definition = AttributeDefinition::parse(key, value_type, request.publicly_settable)
+        .map_err(Error::BadRequest)?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO attribute_definitions (key, value_type, publicly_settable)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (key) DO UPDATE
+            SET value_type = EXCLUDED.value_type, publicly_settable = EXCLUDED.publicly_settable
+            "#,
+        definition.key,
+        definition.value_type.as_str(),
+        definition.publicly_settable
+    )
+    .execute(&ctx.connection_pool)
+    .await?;
+
+    Ok(StatusCode::OK)
+}
+
+#[tracing::instrument(name = "Deleting attribute definition", skip(ctx))]
+pub async fn delete_attribute_definition(
+    ctx: State<ApiContext>,
+    Path(key): Path<String>,
+) -> crate::Result<StatusCode> {
+    let deleted = sqlx::query!(r#"DELETE FROM attribute_definitions WHERE key = $1"#, key)
+        .execute(&ctx.connection_pool)
+        .await?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[tracing::instrument(name = "Saving tag", skip(ctx, request))]
+pub async fn put_tag(
+    ctx: State<ApiContext>,
+    Path(name): Path<String>,
+    Json(request): Json<TagRequest>,
+) -> crate::Result<StatusCode> {
+    let tag = SubscriberTag::parse(name).map_err(Error::BadRequest)?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO tags (id, name, publicly_settable)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO UPDATE SET publicly_settable = EXCLUDED.publicly_settable
+            "#,
+        Uuid::new_v4(),
+        tag.as_ref(),
+        request.publicly_settable
+    )
+    .execute(&ctx.connection_pool)
+    .await?;
+
+    Ok(StatusCode::OK)
+}
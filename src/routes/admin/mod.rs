@@ -0,0 +1,95 @@
+use axum::{
+    extract::{Request, State},
+    http::header::AUTHORIZATION,
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, post, put},
+    Router,
+};
+use secrecy::ExposeSecret;
+
+use crate::{error::Error, ApiContext};
+
+mod attributes;
+mod newsletter_status;
+mod newsletters;
+mod segments;
+mod subscribers;
+mod templates;
+
+pub use attributes::*;
+pub use newsletter_status::*;
+pub use newsletters::*;
+pub use segments::*;
+pub use subscribers::*;
+pub use templates::*;
+
+pub fn router(ctx: ApiContext) -> Router<ApiContext> {
+    Router::new()
+        .route("/attributes", get(list_attribute_definitions))
+        .route(
+            "/attributes/:key",
+            put(put_attribute_definition).delete(delete_attribute_definition),
+        )
+        .route("/tags/:name", put(put_tag))
+        .route("/newsletters", post(create_newsletter))
+        .route(
+            "/newsletters/:id",
+            get(get_newsletter).put(update_newsletter),
+        )
+        .route("/newsletters/:id/revisions", get(list_newsletter_revisions))
+        .route("/newsletters/:id/buckets", get(get_newsletter_buckets))
+        .route("/newsletters/:id/stats", get(get_newsletter_stats))
+        .route("/newsletters/:id/status", get(get_newsletter_status))
+        .route(
+            "/newsletters/:id/status/stream",
+            get(stream_newsletter_status),
+        )
+        .route("/newsletters/:id/schedule", put(schedule_newsletter))
+        .route("/newsletters/:id/cancel", post(cancel_newsletter))
+        .route("/newsletters/:id/test", post(send_test_newsletter))
+        .route("/segments", get(list_segments))
+        .route("/segments/preview", post(preview_segment))
+        .route("/segments/:name", put(put_segment).delete(delete_segment))
+        .route("/segments/:name/preview", get(preview_saved_segment))
+        .route("/subscribers/:id", get(get_subscriber))
+        .route(
+            "/subscribers/:id/attributes",
+            put(put_subscriber_attributes),
+        )
+        .route("/subscribers/:id/tags", put(put_subscriber_tags))
+        .route("/subscribers/:id/timezone", put(put_subscriber_timezone))
+        .route("/templates", get(list_templates))
+        .route(
+            "/templates/:name",
+            get(get_template).put(put_template).delete(delete_template),
+        )
+        .route_layer(middleware::from_fn_with_state(ctx, require_admin_token))
+}
+
+/// Reject any request that does not carry `Authorization: Token <admin_token>`.
+async fn require_admin_token(
+    State(ctx): State<ApiContext>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Token "))
+        .ok_or(Error::Unauthorized)?;
+
+    if !constant_time_eq(
+        token.as_bytes(),
+        ctx.application.admin_token.expose_secret().as_bytes(),
+    ) {
+        return Err(Error::Unauthorized);
+    }
+
+    Ok(next.run(request).await)
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,161 @@
+use std::{convert::Infallible, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    response::sse::{Event, KeepAlive, Sse},
+    Json,
+};
+use futures::{stream, Stream};
+use serde::Serialize;
+use sqlx::{types::Uuid, PgPool};
+use tokio::time::Instant;
+
+use crate::{error::Error, ApiContext};
+
+/// How often a status stream checks for progress.
+const STATUS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A status stream ends after this long, and the client reconnects if it still cares.
+const MAX_STREAM_LIFETIME: Duration = Duration::from_secs(15 * 60);
+
+/// Delivery progress of an issue. Every recipient is counted once under `queued`,
+/// `retrying`, `sent`, `failed` or `skipped`; `bounced`, `complained` and `unsubscribed`
+/// count feedback received about the issue after it was sent.
+#[derive(Serialize, PartialEq)]
+pub struct NewsletterStatusBody {
+    pub status: String,
+    pub recipients: i32,
+    /// Waiting for their first delivery attempt.
+    pub queued: i64,
+    /// Waiting for another attempt after a failed one.
+    pub retrying: i64,
+    pub sent: i64,
+    /// Gave up after the last retry.
+    pub failed: i64,
+    /// No longer confirmed, or with an invalid address, by the time of delivery.
+    pub skipped: i64,
+    pub bounced: i64,
+    pub complained: i64,
+    pub unsubscribed: i64,
+}
+
+impl NewsletterStatusBody {
+    /// Whether a stream stops after reporting this status. Nothing changes anymore once an
+    /// issue was sent or cancelled, and a scheduled issue may sit for days before it starts,
+    /// so clients reconnect around its send time instead of holding a stream open.
+    fn ends_stream(&self) -> bool {
+        ["sent", "draft", "scheduled"].contains(&self.status.as_str())
+    }
+}
+
+pub async fn get_newsletter_status(
+    ctx: State<ApiContext>,
+    Path(issue_id): Path<Uuid>,
+) -> crate::Result<Json<NewsletterStatusBody>> {
+    Ok(Json(fetch_status(&ctx.connection_pool, issue_id).await?))
+}
+
+/// Server-Sent Events stream of `status` events, one whenever the delivery status changes.
+/// The stream ends once the issue is sent or cancelled, right after the first event for
+/// drafts and scheduled issues, and after [`MAX_STREAM_LIFETIME`] at the latest.
+pub async fn stream_newsletter_status(
+    ctx: State<ApiContext>,
+    Path(issue_id): Path<Uuid>,
+) -> crate::Result<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    let initial = fetch_status(&ctx.connection_pool, issue_id).await?;
+    let pool = ctx.connection_pool.clone();
+    let deadline = Instant::now() + MAX_STREAM_LIFETIME;
+
+    let events = stream::unfold(StreamState::Changed(initial), move |state| {
+        let pool = pool.clone();
+        async move {
+            let status = match state {
+                StreamState::Finished => return None,
+                StreamState::Changed(status) => status,
+                StreamState::Unchanged(last) => loop {
+                    if Instant::now() + STATUS_POLL_INTERVAL > deadline {
+                        return None;
+                    }
+                    tokio::time::sleep(STATUS_POLL_INTERVAL).await;
+                    match fetch_status(&pool, issue_id).await {
+                        Ok(status) if status != last => break status,
+                        Ok(_) => {}
+                        Err(e) => {
+                            tracing::error!(
+                                error.cause_chain = ?e,
+                                error.message = %e,
+                                "Failed to fetch the delivery status of a streamed issue"
+                            );
+                            return None;
+                        }
+                    }
+                },
+            };
+            let event = Event::default()
+                .event("status")
+                .json_data(&status)
+                .expect("Status bodies always serialize to JSON.");
+            let next = if status.ends_stream() {
+                StreamState::Finished
+            } else {
+                StreamState::Unchanged(status)
+            };
+            Some((Ok(event), next))
+        }
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+enum StreamState {
+    Changed(NewsletterStatusBody),
+    Unchanged(NewsletterStatusBody),
+    Finished,
+}
+
+async fn fetch_status(pool: &PgPool, issue_id: Uuid) -> crate::Result<NewsletterStatusBody> {
+    sqlx::query_as!(
+        NewsletterStatusBody,
+        r#"
+        SELECT i.status, i.recipients,
+            (
+                SELECT COUNT(*) FROM issue_delivery_queue
+                WHERE newsletter_issue_id = i.id AND n_retries = 0
+            ) AS "queued!",
+            (
+                SELECT COUNT(*) FROM issue_delivery_queue
+                WHERE newsletter_issue_id = i.id AND n_retries > 0
+            ) AS "retrying!",
+            (
+                SELECT COUNT(*) FROM issue_deliveries
+                WHERE newsletter_issue_id = i.id AND outcome = 'sent'
+            ) AS "sent!",
+            (
+                SELECT COUNT(*) FROM issue_deliveries
+                WHERE newsletter_issue_id = i.id AND outcome = 'failed'
+            ) AS "failed!",
+            (
+                SELECT COUNT(*) FROM issue_deliveries
+                WHERE newsletter_issue_id = i.id AND outcome = 'skipped'
+            ) AS "skipped!",
+            (
+                SELECT COUNT(*) FROM issue_feedback_events
+                WHERE newsletter_issue_id = i.id AND kind = 'bounced'
+            ) AS "bounced!",
+            (
+                SELECT COUNT(*) FROM issue_feedback_events
+                WHERE newsletter_issue_id = i.id AND kind = 'complained'
+            ) AS "complained!",
+            (
+                SELECT COUNT(*) FROM issue_feedback_events
+                WHERE newsletter_issue_id = i.id AND kind = 'unsubscribed'
+            ) AS "unsubscribed!"
+        FROM newsletter_issues i
+        WHERE i.id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::NotFound)
+}
@@ -0,0 +1,666 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    types::{
+        chrono::{DateTime, NaiveDateTime, Utc},
+        Uuid,
+    },
+    PgPool, Postgres, Transaction,
+};
+
+use crate::{
+    domain::{SubscriberEmail, SubscriberTimezone},
+    email_pipeline::{prepare_html, warn_if_clipped},
+    error::Error,
+    markdown,
+    routes::admin::{fetch_saved_segment, parse_segment},
+    scheduler::{earliest_local_send_time, start_delivery, IssueToSend},
+    telemetry,
+    templates::{load_templates, EmailTemplates, RenderContext, ISSUE_TEMPLATE},
+    ApiContext,
+};
+
+/// Content of an issue as submitted by an editor. An issue is written either in Markdown,
+/// from which both bodies are generated, or as HTML plus plain text. Either generated body
+/// can still be overridden by passing it alongside the Markdown.
+#[derive(Deserialize)]
+pub struct NewsletterContentRequest {
+    pub title: String,
+    pub markdown_content: Option<String>,
+    pub html_content: Option<String>,
+    pub text_content: Option<String>,
+}
+
+/// Content of one revision of an issue.
+struct NewsletterContent {
+    title: String,
+    markdown_content: Option<String>,
+    html_content: String,
+    text_content: String,
+}
+
+impl NewsletterContentRequest {
+    fn parse(self) -> Result<NewsletterContent, String> {
+        if self.title.trim().is_empty() {
+            return Err("The issue title must not be empty.".into());
+        }
+        let (html_content, text_content) =
+            match &self.markdown_content {
+                Some(markdown) => (
+                    self.html_content
+                        .unwrap_or_else(|| markdown::to_email_html(markdown)),
+                    self.text_content
+                        .unwrap_or_else(|| markdown::to_plain_text(markdown)),
+                ),
+                None => match (self.html_content, self.text_content) {
+                    (Some(html_content), Some(text_content)) => (html_content, text_content),
+                    _ => return Err(
+                        "Provide `markdown_content`, or both `html_content` and `text_content`."
+                            .into(),
+                    ),
+                },
+            };
+        Ok(NewsletterContent {
+            title: self.title,
+            markdown_content: self.markdown_content,
+            html_content,
+            text_content,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NewsletterRequest {
+    #[serde(flatten)]
+    pub content: NewsletterContentRequest,
+    /// Segment expression selecting the recipients; every confirmed subscriber when neither
+    /// it nor `saved_segment` is given.
+    pub segment: Option<String>,
+    /// Name of a saved segment selecting the recipients, resolved to its expression when the
+    /// issue starts sending.
+    pub saved_segment: Option<String>,
+    /// Rewrite links and embed a pixel to record opens and clicks.
+    #[serde(default)]
+    pub tracking_enabled: bool,
+}
+
+/// Edit of an issue. The content is replaced as a whole, while settings that are left out
+/// keep their stored values. Giving either `segment` or `saved_segment` replaces both, and
+/// `null` clears them so the issue goes to every confirmed subscriber.
+#[derive(Deserialize)]
+pub struct NewsletterUpdateRequest {
+    #[serde(flatten)]
+    pub content: NewsletterContentRequest,
+    #[serde(default, deserialize_with = "deserialize_present")]
+    pub segment: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_present")]
+    pub saved_segment: Option<Option<String>>,
+    pub tracking_enabled: Option<bool>,
+}
+
+/// Tells a field set to `null` (`Some(None)`) from a field left out (`None`).
+fn deserialize_present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// When to send an issue: at an instant (`scheduled_at`), at a wall-clock time in each
+/// subscriber's timezone (`local_send_at`), or right away when neither is given.
+#[derive(Deserialize)]
+pub struct ScheduleRequest {
+    pub scheduled_at: Option<DateTime<Utc>>,
+    pub local_send_at: Option<NaiveDateTime>,
+    /// Timezone assumed for subscribers without one, `UTC` by default.
+    pub fallback_timezone: Option<String>,
+}
+
+struct Schedule {
+    scheduled_at: DateTime<Utc>,
+    local_send_at: Option<NaiveDateTime>,
+    fallback_timezone: Option<String>,
+}
+
+impl ScheduleRequest {
+    fn parse(self, now: DateTime<Utc>) -> Result<Schedule, String> {
+        match (self.scheduled_at, self.local_send_at) {
+            (Some(_), Some(_)) => {
+                Err("Set either `scheduled_at` or `local_send_at`, not both.".into())
+            }
+            (scheduled_at, None) => match self.fallback_timezone {
+                Some(_) => Err("`fallback_timezone` only applies to `local_send_at`.".into()),
+                None => Ok(Schedule {
+                    scheduled_at: scheduled_at.unwrap_or(now),
+                    local_send_at: None,
+                    fallback_timezone: None,
+                }),
+            },
+            (None, Some(local_send_at)) => {
+                let fallback_timezone = match self.fallback_timezone {
+                    Some(timezone) => SubscriberTimezone::parse(timezone)?,
+                    None => SubscriberTimezone::parse("UTC".into())?,
+                };
+                Ok(Schedule {
+                    scheduled_at: earliest_local_send_time(local_send_at),
+                    local_send_at: Some(local_send_at),
+                    fallback_timezone: Some(fallback_timezone.as_ref().to_owned()),
+                })
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct NewsletterIssueBody {
+    pub id: Uuid,
+    pub title: String,
+    pub segment: Option<String>,
+    pub saved_segment: Option<String>,
+    pub status: String,
+    pub current_revision: i32,
+    pub scheduled_at: Option<DateTime<Utc>>,
+    pub local_send_at: Option<NaiveDateTime>,
+    pub fallback_timezone: Option<String>,
+    pub enqueued_at: Option<DateTime<Utc>>,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub tracking_enabled: bool,
+    /// Subscribers the issue has been enqueued for so far.
+    pub recipients: i32,
+}
+
+#[derive(Serialize)]
+pub struct NewsletterRevisionBody {
+    pub revision: i32,
+    pub title: String,
+    pub markdown_content: Option<String>,
+    pub text_content: String,
+    pub html_content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct DeliveryBucketBody {
+    pub timezone: String,
+    pub send_at: DateTime<Utc>,
+    pub enqueued_at: Option<DateTime<Utc>>,
+    pub recipients: Option<i32>,
+    /// Deliveries of this bucket still waiting in the queue.
+    pub remaining: i64,
+}
+
+#[derive(Serialize)]
+pub struct NewsletterStatsBody {
+    pub recipients: i32,
+    pub unique_opens: i64,
+    pub open_rate: f64,
+    pub unique_clicks: i64,
+    /// Share of recipients who clicked at least one link.
+    pub click_through_rate: f64,
+    pub links: Vec<LinkStatsBody>,
+}
+
+#[derive(Serialize)]
+pub struct LinkStatsBody {
+    pub url: String,
+    pub clicks: i64,
+    pub unique_clicks: i64,
+}
+
+#[derive(Deserialize)]
+pub struct TestSendRequest {
+    pub recipients: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct TestSendBody {
+    pub revision: i32,
+    pub sent: usize,
+}
+
+/// Test sends go to a handful of reviewers, not to an audience.
+const MAX_TEST_RECIPIENTS: usize = 10;
+
+/// Create an issue as a draft holding its first revision.
+#[tracing::instrument(name = "Creating newsletter draft", skip(ctx, request))]
+pub async fn create_newsletter(
+    ctx: State<ApiContext>,
+    Json(request): Json<NewsletterRequest>,
+) -> crate::Result<(StatusCode, Json<NewsletterIssueBody>)> {
+    let content = request.content.parse().map_err(Error::BadRequest)?;
+    compile_issue(
+        &ctx.connection_pool,
+        &content.html_content,
+        &content.text_content,
+    )
+    .await?
+    .check(ISSUE_TEMPLATE, &ctx.application.base_url)
+    .map_err(Error::BadRequest)?;
+    check_audience(
+        &ctx.connection_pool,
+        request.segment.as_deref(),
+        request.saved_segment.as_deref(),
+    )
+    .await?;
+
+    let issue_id = Uuid::new_v4();
+    let mut transaction = ctx.connection_pool.begin().await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            id, segment, saved_segment, tracking_enabled, status, current_revision, created_at
+        )
+        VALUES ($1, $2, $3, $4, 'draft', 1, now())
+        "#,
+        issue_id,
+        request.segment,
+        request.saved_segment,
+        request.tracking_enabled
+    )
+    .execute(&mut *transaction)
+    .await?;
+    insert_revision(&mut transaction, issue_id, 1, &content).await?;
+    transaction.commit().await?;
+
+    let issue = fetch_issue(&ctx.connection_pool, issue_id).await?;
+    Ok((StatusCode::CREATED, Json(issue)))
+}
+
+pub async fn get_newsletter(
+    ctx: State<ApiContext>,
+    Path(issue_id): Path<Uuid>,
+) -> crate::Result<Json<NewsletterIssueBody>> {
+    Ok(Json(fetch_issue(&ctx.connection_pool, issue_id).await?))
+}
+
+/// Store an edit as a new revision, which becomes the content that gets sent.
+/// Only drafts and issues that have not started sending can be edited.
+#[tracing::instrument(name = "Editing newsletter issue", skip(ctx, request))]
+pub async fn update_newsletter(
+    ctx: State<ApiContext>,
+    Path(issue_id): Path<Uuid>,
+    Json(request): Json<NewsletterUpdateRequest>,
+) -> crate::Result<Json<NewsletterIssueBody>> {
+    let content = request.content.parse().map_err(Error::BadRequest)?;
+    compile_issue(
+        &ctx.connection_pool,
+        &content.html_content,
+        &content.text_content,
+    )
+    .await?
+    .check(ISSUE_TEMPLATE, &ctx.application.base_url)
+    .map_err(Error::BadRequest)?;
+
+    let mut transaction = ctx.connection_pool.begin().await?;
+    let current_revision = lock_editable_issue(&mut transaction, issue_id).await?;
+    let stored = sqlx::query!(
+        r#"SELECT segment, saved_segment, tracking_enabled FROM newsletter_issues WHERE id = $1"#,
+        issue_id
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    let (segment, saved_segment) = match (request.segment, request.saved_segment) {
+        (None, None) => (stored.segment, stored.saved_segment),
+        (segment, saved_segment) => (segment.flatten(), saved_segment.flatten()),
+    };
+    check_audience(
+        &ctx.connection_pool,
+        segment.as_deref(),
+        saved_segment.as_deref(),
+    )
+    .await?;
+    let tracking_enabled = request.tracking_enabled.unwrap_or(stored.tracking_enabled);
+
+    let revision = current_revision + 1;
+    insert_revision(&mut transaction, issue_id, revision, &content).await?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET segment = $2, saved_segment = $3, tracking_enabled = $4, current_revision = $5
+        WHERE id = $1
+        "#,
+        issue_id,
+        segment,
+        saved_segment,
+        tracking_enabled,
+        revision
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(Json(fetch_issue(&ctx.connection_pool, issue_id).await?))
+}
+
+/// Every revision of an issue, oldest first.
+pub async fn list_newsletter_revisions(
+    ctx: State<ApiContext>,
+    Path(issue_id): Path<Uuid>,
+) -> crate::Result<Json<Vec<NewsletterRevisionBody>>> {
+    fetch_issue(&ctx.connection_pool, issue_id).await?;
+    let revisions = sqlx::query_as!(
+        NewsletterRevisionBody,
+        r#"
+        SELECT revision, title, markdown_content, text_content, html_content, created_at
+        FROM newsletter_issue_revisions
+        WHERE newsletter_issue_id = $1
+        ORDER BY revision
+        "#,
+        issue_id
+    )
+    .fetch_all(&ctx.connection_pool)
+    .await?;
+
+    Ok(Json(revisions))
+}
+
+/// Send the current revision to the given addresses only, leaving the issue untouched.
+#[tracing::instrument(name = "Sending test newsletter", skip(ctx, request))]
+pub async fn send_test_newsletter(
+    ctx: State<ApiContext>,
+    Path(issue_id): Path<Uuid>,
+    Json(request): Json<TestSendRequest>,
+) -> crate::Result<Json<TestSendBody>> {
+    if request.recipients.is_empty() || request.recipients.len() > MAX_TEST_RECIPIENTS {
+        return Err(Error::BadRequest(format!(
+            "A test is sent to between 1 and {} recipients.",
+            MAX_TEST_RECIPIENTS
+        )));
+    }
+    let recipients = request
+        .recipients
+        .into_iter()
+        .map(SubscriberEmail::parse)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::BadRequest)?;
+
+    let revision = sqlx::query_as!(
+        NewsletterRevisionBody,
+        r#"
+        SELECT r.revision, r.title, r.markdown_content, r.text_content, r.html_content,
+            r.created_at
+        FROM newsletter_issues i
+        JOIN newsletter_issue_revisions r
+            ON r.newsletter_issue_id = i.id AND r.revision = i.current_revision
+        WHERE i.id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(&ctx.connection_pool)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let templates = compile_issue(
+        &ctx.connection_pool,
+        &revision.html_content,
+        &revision.text_content,
+    )
+    .await?;
+    for recipient in &recipients {
+        let context = RenderContext::sample(recipient.as_ref(), &ctx.application.base_url);
+        let rendered = templates
+            .render(ISSUE_TEMPLATE, &context)
+            .map_err(Error::BadRequest)?;
+        let html = prepare_html(&rendered.html, &ctx.application.base_url)?;
+        warn_if_clipped(&html);
+        ctx.email_client
+            .send_email(recipient, &revision.title, &html, &rendered.text)
+            .await
+            .with_context(|| format!("Failed to send a test issue to {}", recipient.as_ref()))?;
+    }
+
+    Ok(Json(TestSendBody {
+        revision: revision.revision,
+        sent: recipients.len(),
+    }))
+}
+
+/// Opens and clicks recorded for an issue sent with tracking enabled.
+pub async fn get_newsletter_stats(
+    ctx: State<ApiContext>,
+    Path(issue_id): Path<Uuid>,
+) -> crate::Result<Json<NewsletterStatsBody>> {
+    let issue = fetch_issue(&ctx.connection_pool, issue_id).await?;
+    let totals = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM issue_opens WHERE newsletter_issue_id = $1) AS "unique_opens!",
+            (
+                SELECT COUNT(DISTINCT subscriber_id) FROM issue_clicks
+                WHERE newsletter_issue_id = $1
+            ) AS "unique_clicks!"
+        "#,
+        issue_id
+    )
+    .fetch_one(&ctx.connection_pool)
+    .await?;
+    let links = sqlx::query_as!(
+        LinkStatsBody,
+        r#"
+        SELECT url, SUM(clicks) AS "clicks!", COUNT(*) AS "unique_clicks!"
+        FROM issue_clicks
+        WHERE newsletter_issue_id = $1
+        GROUP BY url
+        ORDER BY 2 DESC, url
+        "#,
+        issue_id
+    )
+    .fetch_all(&ctx.connection_pool)
+    .await?;
+
+    let rate = |count: i64| {
+        if issue.recipients == 0 {
+            0.0
+        } else {
+            count as f64 / f64::from(issue.recipients)
+        }
+    };
+    Ok(Json(NewsletterStatsBody {
+        recipients: issue.recipients,
+        unique_opens: totals.unique_opens,
+        open_rate: rate(totals.unique_opens),
+        unique_clicks: totals.unique_clicks,
+        click_through_rate: rate(totals.unique_clicks),
+        links,
+    }))
+}
+
+/// Delivery progress of a local-time issue, per subscriber timezone.
+pub async fn get_newsletter_buckets(
+    ctx: State<ApiContext>,
+    Path(issue_id): Path<Uuid>,
+) -> crate::Result<Json<Vec<DeliveryBucketBody>>> {
+    fetch_issue(&ctx.connection_pool, issue_id).await?;
+    let buckets = sqlx::query_as!(
+        DeliveryBucketBody,
+        r#"
+        SELECT b.timezone, b.send_at, b.enqueued_at, b.recipients,
+            (
+                SELECT COUNT(*) FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = b.newsletter_issue_id
+                    AND q.timezone_bucket = b.timezone
+            ) AS "remaining!"
+        FROM issue_delivery_buckets b
+        WHERE b.newsletter_issue_id = $1
+        ORDER BY b.send_at, b.timezone
+        "#,
+        issue_id
+    )
+    .fetch_all(&ctx.connection_pool)
+    .await?;
+
+    Ok(Json(buckets))
+}
+
+/// Schedule a draft, or move the send time of an issue that has not started sending yet.
+/// An issue whose send time has already passed starts sending right away.
+#[tracing::instrument(name = "Scheduling newsletter issue", skip(ctx, request))]
+pub async fn schedule_newsletter(
+    ctx: State<ApiContext>,
+    Path(issue_id): Path<Uuid>,
+    Json(request): Json<ScheduleRequest>,
+) -> crate::Result<Json<NewsletterIssueBody>> {
+    let now = Utc::now();
+    let schedule = request.parse(now).map_err(Error::BadRequest)?;
+
+    let mut transaction = ctx.connection_pool.begin().await?;
+    lock_editable_issue(&mut transaction, issue_id).await?;
+    let issue = sqlx::query_as!(
+        IssueToSend,
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_at = $2, local_send_at = $3, fallback_timezone = $4, status = 'scheduled',
+            traceparent = $5
+        WHERE id = $1
+        RETURNING id, segment, saved_segment, local_send_at, fallback_timezone
+        "#,
+        issue_id,
+        schedule.scheduled_at,
+        schedule.local_send_at,
+        schedule.fallback_timezone,
+        telemetry::current_traceparent()
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    if schedule.scheduled_at <= now {
+        start_delivery(&mut transaction, &issue).await?;
+    }
+    transaction.commit().await?;
+
+    Ok(Json(fetch_issue(&ctx.connection_pool, issue_id).await?))
+}
+
+/// Take a scheduled issue off the schedule, turning it back into a draft.
+#[tracing::instrument(name = "Cancelling newsletter issue", skip(ctx))]
+pub async fn cancel_newsletter(
+    ctx: State<ApiContext>,
+    Path(issue_id): Path<Uuid>,
+) -> crate::Result<Json<NewsletterIssueBody>> {
+    let mut transaction = ctx.connection_pool.begin().await?;
+    lock_editable_issue(&mut transaction, issue_id).await?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'draft', scheduled_at = NULL, local_send_at = NULL, fallback_timezone = NULL
+        WHERE id = $1
+        "#,
+        issue_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(Json(fetch_issue(&ctx.connection_pool, issue_id).await?))
+}
+
+/// Check the recipients of an issue: a valid expression, or the name of a saved segment.
+async fn check_audience(
+    pool: &PgPool,
+    segment: Option<&str>,
+    saved_segment: Option<&str>,
+) -> crate::Result<()> {
+    match (segment, saved_segment) {
+        (Some(_), Some(_)) => Err(Error::BadRequest(
+            "Set either `segment` or `saved_segment`, not both.".into(),
+        )),
+        (Some(expression), None) => parse_segment(pool, expression).await.map(|_| ()),
+        (None, Some(name)) => match fetch_saved_segment(pool, name).await {
+            Err(Error::NotFound) => Err(Error::BadRequest(format!(
+                "There is no saved segment named `{}`.",
+                name
+            ))),
+            result => result.map(|_| ()),
+        },
+        (None, None) => Ok(()),
+    }
+}
+
+/// Compile the content of an issue along with the stored templates it may extend or include.
+async fn compile_issue(
+    pool: &PgPool,
+    html_content: &str,
+    text_content: &str,
+) -> crate::Result<EmailTemplates> {
+    let mut templates = load_templates(pool).await?;
+    templates
+        .add_issue(html_content, text_content)
+        .map_err(Error::BadRequest)?;
+    Ok(templates)
+}
+
+/// Lock an issue that has not started sending yet and return its current revision.
+/// Issues the scheduler already picked up are a conflict.
+async fn lock_editable_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> crate::Result<i32> {
+    let issue = sqlx::query!(
+        r#"SELECT status, current_revision FROM newsletter_issues WHERE id = $1 FOR UPDATE"#,
+        issue_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    match issue.status.as_str() {
+        "draft" | "scheduled" => Ok(issue.current_revision),
+        status => Err(Error::Conflict(format!(
+            "The issue can no longer be changed, its status is `{}`.",
+            status
+        ))),
+    }
+}
+
+async fn insert_revision(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    revision: i32,
+    content: &NewsletterContent,
+) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_revisions (
+            newsletter_issue_id, revision, title, markdown_content, text_content, html_content,
+            created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, now())
+        "#,
+        issue_id,
+        revision,
+        content.title,
+        content.markdown_content,
+        content.text_content,
+        content.html_content
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn fetch_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> crate::Result<NewsletterIssueBody> {
+    sqlx::query_as!(
+        NewsletterIssueBody,
+        r#"
+        SELECT i.id, r.title, i.segment, i.saved_segment, i.status, i.current_revision, i.scheduled_at,
+            i.local_send_at, i.fallback_timezone, i.enqueued_at, i.sent_at, i.tracking_enabled,
+            i.recipients
+        FROM newsletter_issues i
+        JOIN newsletter_issue_revisions r
+            ON r.newsletter_issue_id = i.id AND r.revision = i.current_revision
+        WHERE i.id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::NotFound)
+}
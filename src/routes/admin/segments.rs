@@ -0,0 +1,158 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    types::{chrono::Utc, Uuid},
+    PgPool, Postgres, QueryBuilder,
+};
+
+use crate::{
+    error::Error,
+    routes::fetch_attribute_schema,
+    segments::{Segment, SegmentName},
+    ApiContext,
+};
+
+#[derive(Serialize)]
+pub struct SegmentBody {
+    pub name: String,
+    pub expression: String,
+}
+
+#[derive(Deserialize)]
+pub struct SegmentRequest {
+    pub expression: String,
+}
+
+#[derive(Serialize)]
+pub struct SegmentPreview {
+    pub matching_subscribers: i64,
+}
+
+pub async fn list_segments(ctx: State<ApiContext>) -> crate::Result<Json<Vec<SegmentBody>>> {
+    let segments = sqlx::query_as!(
+        SegmentBody,
+        r#"SELECT name, expression FROM segments ORDER BY name"#
+    )
+    .fetch_all(&ctx.connection_pool)
+    .await?;
+
+    Ok(Json(segments))
+}
+
+#[tracing::instrument(name = "Saving segment", skip(ctx, request))]
+pub async fn put_segment(
+    ctx: State<ApiContext>,
+    Path(name): Path<String>,
+    Json(request): Json<SegmentRequest>,
+) -> crate::Result<StatusCode> {
+    let name = SegmentName::parse(name).map_err(Error::BadRequest)?;
+    parse_segment(&ctx.connection_pool, &request.expression).await?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO segments (id, name, expression, created_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (name) DO UPDATE SET expression = EXCLUDED.expression
+            "#,
+        Uuid::new_v4(),
+        name.as_ref(),
+        request.expression,
+        Utc::now()
+    )
+    .execute(&ctx.connection_pool)
+    .await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Delete a segment no issue still to be sent goes to.
+#[tracing::instrument(name = "Deleting segment", skip(ctx))]
+pub async fn delete_segment(
+    ctx: State<ApiContext>,
+    Path(name): Path<String>,
+) -> crate::Result<StatusCode> {
+    let mut transaction = ctx.connection_pool.begin().await?;
+    let in_use = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM newsletter_issues
+        WHERE saved_segment = $1 AND status IN ('draft', 'scheduled')
+        ORDER BY created_at
+        FOR UPDATE
+        "#,
+        name
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    if !in_use.is_empty() {
+        let issues: Vec<_> = in_use.iter().map(Uuid::to_string).collect();
+        return Err(Error::Conflict(format!(
+            "Issues still to be sent use the segment: {}",
+            issues.join(", ")
+        )));
+    }
+    let deleted = sqlx::query!(r#"DELETE FROM segments WHERE name = $1"#, name)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(Error::NotFound);
+    }
+    transaction.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Count the subscribers an ad-hoc expression matches, without saving it.
+pub async fn preview_segment(
+    ctx: State<ApiContext>,
+    Json(request): Json<SegmentRequest>,
+) -> crate::Result<Json<SegmentPreview>> {
+    let segment = parse_segment(&ctx.connection_pool, &request.expression).await?;
+    let matching_subscribers = count_matching_subscribers(&ctx.connection_pool, &segment).await?;
+
+    Ok(Json(SegmentPreview {
+        matching_subscribers,
+    }))
+}
+
+/// Count the subscribers a saved segment currently matches.
+pub async fn preview_saved_segment(
+    ctx: State<ApiContext>,
+    Path(name): Path<String>,
+) -> crate::Result<Json<SegmentPreview>> {
+    let segment = fetch_saved_segment(&ctx.connection_pool, &name).await?;
+    let matching_subscribers = count_matching_subscribers(&ctx.connection_pool, &segment).await?;
+
+    Ok(Json(SegmentPreview {
+        matching_subscribers,
+    }))
+}
+
+/// Parse an expression and check it against the current attribute schema.
+pub(crate) async fn parse_segment(pool: &PgPool, expression: &str) -> crate::Result<Segment> {
+    let segment = Segment::parse(expression).map_err(Error::BadRequest)?;
+    let schema = fetch_attribute_schema(pool).await?;
+    segment.check(&schema).map_err(Error::BadRequest)?;
+    Ok(segment)
+}
+
+pub(crate) async fn fetch_saved_segment(pool: &PgPool, name: &str) -> crate::Result<Segment> {
+    let saved = sqlx::query!(r#"SELECT expression FROM segments WHERE name = $1"#, name)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    parse_segment(pool, &saved.expression).await
+}
+
+#[tracing::instrument(name = "Counting subscribers matching a segment", skip(pool))]
+async fn count_matching_subscribers(pool: &PgPool, segment: &Segment) -> crate::Result<i64> {
+    let mut query = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM subscriptions s WHERE ");
+    segment.push_sql(&mut query);
+
+    Ok(query.build_query_scalar().fetch_one(pool).await?)
+}
@@ -0,0 +1,138 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::types::Uuid;
+
+use crate::{
+    domain::{SubscriberAttributes, SubscriberTimezone},
+    error::Error,
+    routes::{fetch_attribute_schema, parse_tags, replace_subscriber_tags},
+    ApiContext,
+};
+
+#[derive(Serialize)]
+pub struct SubscriberBody {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub attributes: Value,
+    pub tags: Vec<String>,
+    pub timezone: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SubscriberTagsRequest {
+    pub tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SubscriberTimezoneRequest {
+    pub timezone: Option<String>,
+}
+
+pub async fn get_subscriber(
+    ctx: State<ApiContext>,
+    Path(subscriber_id): Path<Uuid>,
+) -> crate::Result<Json<SubscriberBody>> {
+    let subscriber = sqlx::query_as!(
+        SubscriberBody,
+        r#"
+            SELECT s.id, s.email, s.name, s.attributes,
+                ARRAY(
+                    SELECT t.name FROM tags t
+                    JOIN subscription_tags st ON st.tag_id = t.id
+                    WHERE st.subscriber_id = s.id
+                    ORDER BY t.name
+                ) AS "tags!",
+                s.timezone
+            FROM subscriptions s
+            WHERE s.id = $1
+            "#,
+        subscriber_id
+    )
+    .fetch_optional(&ctx.connection_pool)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    Ok(Json(subscriber))
+}
+
+#[tracing::instrument(name = "Replacing subscriber attributes", skip(ctx, attributes))]
+pub async fn put_subscriber_attributes(
+    ctx: State<ApiContext>,
+    Path(subscriber_id): Path<Uuid>,
+    Json(attributes): Json<Map<String, Value>>,
+) -> crate::Result<StatusCode> {
+    let schema = fetch_attribute_schema(&ctx.connection_pool).await?;
+    let attributes = SubscriberAttributes::parse(attributes, &schema).map_err(Error::BadRequest)?;
+
+    let updated = sqlx::query!(
+        r#"UPDATE subscriptions SET attributes = $1 WHERE id = $2"#,
+        attributes.into_value(),
+        subscriber_id
+    )
+    .execute(&ctx.connection_pool)
+    .await?
+    .rows_affected();
+    if updated == 0 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(StatusCode::OK)
+}
+
+#[tracing::instrument(name = "Setting subscriber tags", skip(ctx, request))]
+pub async fn put_subscriber_tags(
+    ctx: State<ApiContext>,
+    Path(subscriber_id): Path<Uuid>,
+    Json(request): Json<SubscriberTagsRequest>,
+) -> crate::Result<StatusCode> {
+    let tags = parse_tags(request.tags).map_err(Error::BadRequest)?;
+
+    let mut transaction = ctx.connection_pool.begin().await?;
+    let exists = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    if exists.is_none() {
+        return Err(Error::NotFound);
+    }
+    replace_subscriber_tags(&mut transaction, subscriber_id, &tags).await?;
+    transaction.commit().await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Set or clear the timezone used for local-time delivery.
+#[tracing::instrument(name = "Setting subscriber timezone", skip(ctx, request))]
+pub async fn put_subscriber_timezone(
+    ctx: State<ApiContext>,
+    Path(subscriber_id): Path<Uuid>,
+    Json(request): Json<SubscriberTimezoneRequest>,
+) -> crate::Result<StatusCode> {
+    let timezone = request
+        .timezone
+        .map(SubscriberTimezone::parse)
+        .transpose()
+        .map_err(Error::BadRequest)?;
+
+    let updated = sqlx::query!(
+        r#"UPDATE subscriptions SET timezone = $1 WHERE id = $2"#,
+        timezone.as_ref().map(AsRef::as_ref),
+        subscriber_id
+    )
+    .execute(&ctx.connection_pool)
+    .await?
+    .rows_affected();
+    if updated == 0 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,163 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+
+use crate::{
+    error::Error,
+    templates::{load_templates, EmailTemplates, StoredTemplate, TemplateName, ISSUE_TEMPLATE},
+    ApiContext,
+};
+
+#[derive(Serialize)]
+pub struct TemplateSummary {
+    pub name: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct TemplateBody {
+    pub name: String,
+    pub html_source: String,
+    pub text_source: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct TemplateRequest {
+    pub html_source: String,
+    pub text_source: String,
+}
+
+pub async fn list_templates(ctx: State<ApiContext>) -> crate::Result<Json<Vec<TemplateSummary>>> {
+    let templates = sqlx::query_as!(
+        TemplateSummary,
+        r#"SELECT name, updated_at FROM email_templates ORDER BY name"#
+    )
+    .fetch_all(&ctx.connection_pool)
+    .await?;
+
+    Ok(Json(templates))
+}
+
+pub async fn get_template(
+    ctx: State<ApiContext>,
+    Path(name): Path<String>,
+) -> crate::Result<Json<TemplateBody>> {
+    let template = sqlx::query_as!(
+        TemplateBody,
+        r#"SELECT name, html_source, text_source, updated_at FROM email_templates WHERE name = $1"#,
+        name
+    )
+    .fetch_optional(&ctx.connection_pool)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    Ok(Json(template))
+}
+
+/// Save a template once it compiles and every stored template still renders with it.
+#[tracing::instrument(name = "Saving email template", skip(ctx, request))]
+pub async fn put_template(
+    ctx: State<ApiContext>,
+    Path(name): Path<String>,
+    Json(request): Json<TemplateRequest>,
+) -> crate::Result<StatusCode> {
+    let name = TemplateName::parse(name).map_err(Error::BadRequest)?;
+
+    let mut templates = load_templates(&ctx.connection_pool).await?;
+    templates
+        .add(StoredTemplate {
+            name: name.as_ref().to_owned(),
+            html_source: request.html_source.clone(),
+            text_source: request.text_source.clone(),
+        })
+        .map_err(Error::BadRequest)?;
+    check_all(&templates, &ctx.application.base_url).map_err(Error::BadRequest)?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO email_templates (name, html_source, text_source, updated_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (name) DO UPDATE
+            SET html_source = EXCLUDED.html_source,
+                text_source = EXCLUDED.text_source,
+                updated_at = EXCLUDED.updated_at
+            "#,
+        name.as_ref(),
+        request.html_source,
+        request.text_source,
+        Utc::now()
+    )
+    .execute(&ctx.connection_pool)
+    .await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Delete a template no other template, and no issue still to be sent, extends or includes.
+#[tracing::instrument(name = "Deleting email template", skip(ctx))]
+pub async fn delete_template(
+    ctx: State<ApiContext>,
+    Path(name): Path<String>,
+) -> crate::Result<StatusCode> {
+    let base_url = &ctx.application.base_url;
+    let mut with_template = load_templates(&ctx.connection_pool).await?;
+    let mut without_template = load_templates(&ctx.connection_pool).await?;
+    without_template.remove(&name);
+    check_all(&without_template, base_url).map_err(Error::Conflict)?;
+
+    // Issues that fail to render with the template as well are not held against it.
+    let unsent_issues = sqlx::query!(
+        r#"
+        SELECT i.id, r.title, r.html_content, r.text_content
+        FROM newsletter_issues i
+        JOIN newsletter_issue_revisions r
+            ON r.newsletter_issue_id = i.id AND r.revision = i.current_revision
+        WHERE i.status <> 'sent'
+        ORDER BY i.created_at
+        "#
+    )
+    .fetch_all(&ctx.connection_pool)
+    .await?;
+    let mut dependents = Vec::new();
+    for issue in unsent_issues {
+        let renders = |templates: &mut EmailTemplates| {
+            templates
+                .add_issue(&issue.html_content, &issue.text_content)
+                .and_then(|()| templates.check(ISSUE_TEMPLATE, base_url))
+                .is_ok()
+        };
+        if renders(&mut with_template) && !renders(&mut without_template) {
+            dependents.push(format!("`{}` ({})", issue.title, issue.id));
+        }
+    }
+    if !dependents.is_empty() {
+        return Err(Error::Conflict(format!(
+            "Issues still to be sent use the template: {}",
+            dependents.join(", ")
+        )));
+    }
+
+    let deleted = sqlx::query!(r#"DELETE FROM email_templates WHERE name = $1"#, name)
+        .execute(&ctx.connection_pool)
+        .await?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(StatusCode::OK)
+}
+
+fn check_all(templates: &EmailTemplates, base_url: &str) -> Result<(), String> {
+    for name in templates.names() {
+        templates
+            .check(&name, base_url)
+            .map_err(|e| format!("Template `{}`: {}", name, e))?;
+    }
+    Ok(())
+}
@@ -1,5 +1,140 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use axum::{extract::State, Json};
 use hyper::StatusCode;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{circuit_breaker::BreakerState, email_client::ProviderHealth, ApiContext, MIGRATOR};
+
+/// Longest a single readiness check may take before its dependency counts as down.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
 
-pub async fn health_check() -> StatusCode {
+/// The process is up and serving requests; dependencies are not checked.
+pub async fn liveness() -> StatusCode {
     StatusCode::OK
 }
+
+#[derive(Serialize)]
+pub struct ReadinessBody {
+    pub ready: bool,
+    pub components: BTreeMap<&'static str, ComponentHealth>,
+}
+
+#[derive(Serialize)]
+pub struct ComponentHealth {
+    pub up: bool,
+    /// Whether the instance is reported not ready while this component is down.
+    pub required: bool,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Check every dependency, responding 503 when a required one is down.
+pub async fn readiness(ctx: State<ApiContext>) -> (StatusCode, Json<ReadinessBody>) {
+    let health = &ctx.application.health;
+    let (database, migrations, redis, email_provider) = tokio::join!(
+        check(true, async {
+            sqlx::query("SELECT 1")
+                .execute(&ctx.connection_pool)
+                .await?;
+            Ok(())
+        }),
+        check(true, check_migrations(&ctx.connection_pool)),
+        check(health.require_redis, async {
+            let mut connection = ctx
+                .redis
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("Redis could not be reached at startup"))?;
+            redis::cmd("PING")
+                .query_async::<String>(&mut connection)
+                .await?;
+            Ok(())
+        }),
+        async {
+            if !health.check_email_provider {
+                return None;
+            }
+            Some(
+                check(true, async {
+                    Ok(ctx.email_client.check_reachable().await?)
+                })
+                .await,
+            )
+        },
+    );
+
+    let mut components = BTreeMap::from([
+        ("database", database),
+        ("migrations", migrations),
+        ("redis", redis),
+    ]);
+    if let Some(email_provider) = email_provider {
+        components.insert("email_provider", email_provider);
+    }
+    let ready = components
+        .values()
+        .all(|component| component.up || !component.required);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(ReadinessBody { ready, components }))
+}
+
+async fn check(required: bool, check: impl Future<Output = anyhow::Result<()>>) -> ComponentHealth {
+    let start = Instant::now();
+    let outcome = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(outcome) => outcome,
+        Err(_) => Err(anyhow::anyhow!("Timed out after {:?}", CHECK_TIMEOUT)),
+    };
+    ComponentHealth {
+        up: outcome.is_ok(),
+        required,
+        latency_ms: start.elapsed().as_secs_f64() * 1000.0,
+        error: outcome.err().map(|e| e.to_string()),
+    }
+}
+
+/// Fail unless every migration this build embeds was applied successfully.
+async fn check_migrations(pool: &PgPool) -> anyhow::Result<()> {
+    let applied: HashSet<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .collect();
+    let pending = MIGRATOR
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .count();
+    if pending > 0 {
+        anyhow::bail!("{} migrations are not applied", pending);
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct EmailHealthBody {
+    pub providers: Vec<ProviderHealth>,
+}
+
+/// Circuit breaker state of every email provider; 503 while all of their circuits are open.
+pub async fn email_health(ctx: State<ApiContext>) -> (StatusCode, Json<EmailHealthBody>) {
+    let providers = ctx.email_client.provider_health();
+    let status = if providers
+        .iter()
+        .all(|provider| provider.state == BreakerState::Open)
+    {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    (status, Json(EmailHealthBody { providers }))
+}
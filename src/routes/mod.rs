@@ -1,5 +1,14 @@
+pub mod admin;
 mod health_check;
+mod pages;
 mod subscriptions;
+mod tracking;
+mod webhooks;
+mod widget;
 
 pub use health_check::*;
+pub use pages::*;
 pub use subscriptions::*;
+pub use tracking::*;
+pub use webhooks::*;
+pub use widget::*;
@@ -0,0 +1,142 @@
+//! Hosted subscription pages, for sites that link to us or post a plain HTML form to
+//! `POST /subscriptions` instead of calling the JSON API.
+
+use axum::{
+    extract::{Query, State},
+    response::Html,
+};
+use chrono::Utc;
+use serde::Deserialize;
+
+use crate::{
+    bot_protection::HONEYPOT_FIELD, configuration::BrandingSettings, html::escape, ApiContext,
+};
+
+/// Set when a form signup was rejected and redirected back to the signup page, so the
+/// message is shown and the form keeps what was typed.
+#[derive(Deserialize)]
+pub struct SubscribePageParameters {
+    pub error: Option<String>,
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+pub async fn subscribe_page(
+    ctx: State<ApiContext>,
+    Query(parameters): Query<SubscribePageParameters>,
+) -> Html<String> {
+    let branding = &ctx.application.branding;
+    let error = parameters
+        .error
+        .map(|error| format!("<p class=\"error\">{}</p>", escape(&error)))
+        .unwrap_or_default();
+    let captcha = ctx
+        .bot_protection
+        .captcha_site_key()
+        .map(|site_key| {
+            format!(
+                "<script src=\"https://challenges.cloudflare.com/turnstile/v0/api.js\" async defer></script>\n\
+                 <div class=\"cf-turnstile\" data-sitekey=\"{}\"></div>\n",
+                escape(site_key)
+            )
+        })
+        .unwrap_or_default();
+    let body = format!(
+        r#"<h1>Subscribe to {name}</h1>
+{error}<form method="post" action="/subscriptions">
+<label>Name<input type="text" name="name" value="{name_value}" required></label>
+<label>Email<input type="email" name="email" value="{email_value}" required></label>
+<label class="honeypot" aria-hidden="true">Website<input type="text" name="{honeypot}" tabindex="-1" autocomplete="off"></label>
+<input type="hidden" name="timezone" id="timezone">
+<input type="hidden" name="form_token" value="{form_token}">
+{captcha}<button type="submit">Subscribe</button>
+</form>
+<script>
+document.getElementById("timezone").value = Intl.DateTimeFormat().resolvedOptions().timeZone || "";
+</script>"#,
+        name = escape(&branding.name),
+        error = error,
+        name_value = escape(parameters.name.as_deref().unwrap_or_default()),
+        email_value = escape(parameters.email.as_deref().unwrap_or_default()),
+        honeypot = HONEYPOT_FIELD,
+        form_token = ctx.bot_protection.issue_form_token(Utc::now()),
+        captcha = captcha,
+    );
+    page(branding, "Subscribe", &body)
+}
+
+pub async fn check_your_inbox_page(ctx: State<ApiContext>) -> Html<String> {
+    message_page(
+        &ctx.application.branding,
+        "Check your inbox",
+        "Thanks for subscribing! Our next issue will land in your inbox.",
+    )
+}
+
+pub async fn confirmed_page(ctx: State<ApiContext>) -> Html<String> {
+    message_page(
+        &ctx.application.branding,
+        "Subscription confirmed",
+        "Your subscription is confirmed. Welcome aboard!",
+    )
+}
+
+pub async fn unsubscribed_page(ctx: State<ApiContext>) -> Html<String> {
+    message_page(
+        &ctx.application.branding,
+        "You have been unsubscribed",
+        "You will not receive any more issues. Sorry to see you go!",
+    )
+}
+
+fn message_page(branding: &BrandingSettings, title: &str, message: &str) -> Html<String> {
+    let body = format!("<h1>{}</h1>\n<p>{}</p>", escape(title), escape(message));
+    page(branding, title, &body)
+}
+
+/// Wrap a page body in the branded layout.
+fn page(branding: &BrandingSettings, title: &str, body: &str) -> Html<String> {
+    let logo = branding
+        .logo_url
+        .as_ref()
+        .map(|url| {
+            format!(
+                "<img class=\"logo\" src=\"{}\" alt=\"{}\">",
+                escape(url),
+                escape(&branding.name)
+            )
+        })
+        .unwrap_or_default();
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title} - {name}</title>
+<style>
+body {{ margin: 0; padding: 48px 16px; font-family: Helvetica, Arial, sans-serif; color: #222222; background: #f4f4f4; }}
+main {{ max-width: 480px; margin: 0 auto; padding: 32px; background: #ffffff; }}
+.logo {{ display: block; max-height: 48px; margin-bottom: 24px; }}
+label {{ display: block; margin-bottom: 16px; }}
+input {{ display: block; width: 100%; box-sizing: border-box; margin-top: 4px; padding: 8px; }}
+button {{ padding: 10px 20px; border: none; color: #ffffff; background: {accent}; cursor: pointer; }}
+a {{ color: {accent}; }}
+.error {{ padding: 12px; color: #8a1f11; background: #fbe3e4; }}
+.honeypot {{ position: absolute; left: -10000px; }}
+</style>
+</head>
+<body>
+<main>
+{logo}{body}
+</main>
+</body>
+</html>
+"#,
+        title = escape(title),
+        name = escape(&branding.name),
+        accent = escape(&branding.accent_color),
+        logo = logo,
+        body = body,
+    ))
+}
@@ -1,10 +1,24 @@
-use axum::{extract::State, response::IntoResponse, Json};
+use axum::{
+    extract::{FromRequest, Query, Request, State},
+    http::header::{CACHE_CONTROL, CONTENT_TYPE},
+    response::{IntoResponse, Redirect, Response},
+    Form, Json,
+};
 use hyper::StatusCode;
-use serde::Deserialize;
-use sqlx::types::{chrono::Utc, Uuid};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{
+    types::{chrono::Utc, Uuid},
+    PgPool, Postgres, Transaction,
+};
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    bot_protection::{BotCheckFailure, SignupChannel, SignupProof},
+    domain::{
+        AttributeDefinition, AttributeSchema, NewSubscriber, SubscriberAttributes, SubscriberEmail,
+        SubscriberName, SubscriberTag, SubscriberTimezone,
+    },
     error::Error,
     ApiContext,
 };
@@ -13,33 +27,304 @@ use crate::{
 pub struct Subscription {
     pub name: String,
     pub email: String,
+    #[serde(default)]
+    pub attributes: Map<String, Value>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub timezone: Option<String>,
+    /// See [`HONEYPOT_FIELD`](crate::bot_protection::HONEYPOT_FIELD).
+    #[serde(rename = "website")]
+    pub honeypot: Option<String>,
+    pub form_token: Option<String>,
+    pub captcha_response: Option<String>,
 }
 
-impl TryFrom<Subscription> for NewSubscriber {
-    type Error = String;
+impl Subscription {
+    fn take_proof(&mut self) -> SignupProof {
+        SignupProof {
+            honeypot: self.honeypot.take(),
+            form_token: self.form_token.take(),
+            captcha_response: self.captcha_response.take(),
+        }
+    }
 
-    fn try_from(value: Subscription) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(NewSubscriber { email, name })
+    /// Validate a public signup. Only attributes marked as publicly settable are accepted.
+    fn parse(self, schema: &AttributeSchema) -> Result<NewSubscriber, String> {
+        let name = SubscriberName::parse(self.name)?;
+        let email = SubscriberEmail::parse(self.email)?;
+        let attributes = SubscriberAttributes::parse_public(self.attributes, schema)?;
+        let tags = parse_tags(self.tags)?;
+        let timezone = self.timezone.map(SubscriberTimezone::parse).transpose()?;
+        Ok(NewSubscriber {
+            email,
+            name,
+            attributes,
+            tags,
+            timezone,
+        })
     }
 }
 
-pub async fn subscribe(
-    ctx: State<ApiContext>,
-    Json(payload): Json<Subscription>,
-) -> impl IntoResponse {
-    let new_subscriber = match payload.try_into() {
+/// A signup posted by a plain HTML form. Missing fields are read as empty, so they get the
+/// same friendly error message as invalid ones.
+#[derive(Deserialize)]
+pub struct SubscriptionForm {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub email: String,
+    #[serde(default)]
+    pub timezone: String,
+    /// See [`HONEYPOT_FIELD`](crate::bot_protection::HONEYPOT_FIELD).
+    #[serde(rename = "website")]
+    pub honeypot: Option<String>,
+    pub form_token: Option<String>,
+    /// Turnstile adds its response to the form under its own name.
+    #[serde(alias = "cf-turnstile-response")]
+    pub captcha_response: Option<String>,
+}
+
+impl SubscriptionForm {
+    fn take_proof(&mut self) -> SignupProof {
+        SignupProof {
+            honeypot: self.honeypot.take(),
+            form_token: self.form_token.take(),
+            captcha_response: self.captcha_response.take(),
+        }
+    }
+
+    /// Validate a form signup, with an error message meant for the person filling it in.
+    fn parse(self) -> Result<NewSubscriber, &'static str> {
+        let name = SubscriberName::parse(self.name).map_err(|_| "Please enter your name.")?;
+        let email = SubscriberEmail::parse(self.email)
+            .map_err(|_| "Please enter a valid email address.")?;
+        // Browsers may not report a timezone, or one we do not know about.
+        let timezone = SubscriberTimezone::parse(self.timezone).ok();
+        Ok(NewSubscriber {
+            email,
+            name,
+            attributes: SubscriberAttributes::default(),
+            tags: Vec::new(),
+            timezone,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct FormTokenBody {
+    pub form_token: String,
+    pub captcha_site_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UnsubscribeParameters {
+    pub token: String,
+    /// The issue whose link was followed.
+    pub issue: Option<Uuid>,
+}
+
+pub(crate) fn parse_tags(tags: Vec<String>) -> Result<Vec<SubscriberTag>, String> {
+    let mut parsed = tags
+        .into_iter()
+        .map(SubscriberTag::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    parsed.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+    parsed.dedup();
+    Ok(parsed)
+}
+
+/// Sign up with a JSON body, or with a form post that is redirected to the hosted pages.
+pub async fn subscribe(ctx: State<ApiContext>, request: Request) -> Response {
+    let is_form = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+
+    if is_form {
+        match Form::<SubscriptionForm>::from_request(request, &ctx).await {
+            Ok(Form(form)) => subscribe_with_form(ctx, form).await.into_response(),
+            Err(rejection) => rejection.into_response(),
+        }
+    } else {
+        match Json::<Subscription>::from_request(request, &ctx).await {
+            Ok(Json(payload)) => subscribe_with_json(ctx, payload).await.into_response(),
+            Err(rejection) => rejection.into_response(),
+        }
+    }
+}
+
+async fn subscribe_with_json(ctx: State<ApiContext>, mut payload: Subscription) -> StatusCode {
+    match ctx
+        .bot_protection
+        .check(&payload.take_proof(), SignupChannel::Json, Utc::now())
+        .await
+    {
+        Ok(()) => {}
+        Err(BotCheckFailure::Honeypot) => {
+            tracing::info!("Dropping a signup that filled in the honeypot.");
+            return StatusCode::OK;
+        }
+        Err(_) => {
+            record_outcome("invalid");
+            return StatusCode::BAD_REQUEST;
+        }
+    }
+    let schema = match fetch_attribute_schema(&ctx.connection_pool).await {
+        Ok(schema) => schema,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let new_subscriber = match payload.parse(&schema) {
         Ok(subscriber) => subscriber,
-        Err(_) => return StatusCode::BAD_REQUEST,
+        Err(_) => {
+            record_outcome("invalid");
+            return StatusCode::BAD_REQUEST;
+        }
     };
 
     match insert_subscriber(new_subscriber, ctx).await {
-        Ok(_) => StatusCode::OK,
+        Ok(_) => {
+            record_outcome("accepted");
+            StatusCode::OK
+        }
+        Err(Error::BadRequest(_)) => {
+            record_outcome("invalid");
+            StatusCode::BAD_REQUEST
+        }
+        Err(e) if is_duplicate(&e) => {
+            record_outcome("duplicate");
+            StatusCode::CONFLICT
+        }
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
     }
 }
 
+#[tracing::instrument(name = "Subscribing with a form", skip(ctx, form))]
+async fn subscribe_with_form(ctx: State<ApiContext>, mut form: SubscriptionForm) -> Redirect {
+    let base_url = ctx.application.base_url.clone();
+    let (name, email) = (form.name.clone(), form.email.clone());
+    let failure = |message: &str| {
+        Redirect::to(&subscribe_page_url(
+            &base_url,
+            &[("error", message), ("name", &name), ("email", &email)],
+        ))
+    };
+
+    let success = Redirect::to(&format!(
+        "{}/subscribe/check-your-inbox",
+        base_url.trim_end_matches('/')
+    ));
+
+    match ctx
+        .bot_protection
+        .check(&form.take_proof(), SignupChannel::Form, Utc::now())
+        .await
+    {
+        Ok(()) => {}
+        Err(BotCheckFailure::Honeypot) => {
+            tracing::info!("Dropping a signup that filled in the honeypot.");
+            return success;
+        }
+        Err(failure_reason) => {
+            record_outcome("invalid");
+            return failure(failure_reason.message());
+        }
+    }
+    let new_subscriber = match form.parse() {
+        Ok(subscriber) => subscriber,
+        Err(message) => {
+            record_outcome("invalid");
+            return failure(message);
+        }
+    };
+    match insert_subscriber(new_subscriber, ctx.clone()).await {
+        Ok(()) => {
+            record_outcome("accepted");
+            success
+        }
+        Err(e) if is_duplicate(&e) => {
+            record_outcome("duplicate");
+            failure("That email address is already subscribed.")
+        }
+        Err(e) => {
+            tracing::error!(error.message = %e, "Failed to save a form signup");
+            failure("Something went wrong on our side. Please try again in a moment.")
+        }
+    }
+}
+
+/// Count a signup in the `subscriptions_total` metric.
+fn record_outcome(outcome: &'static str) {
+    metrics::counter!("subscriptions_total", "outcome" => outcome).increment(1);
+}
+
+fn is_duplicate(e: &Error) -> bool {
+    match e {
+        Error::Sqlx(e) => e
+            .as_database_error()
+            .is_some_and(|e| e.is_unique_violation()),
+        _ => false,
+    }
+}
+
+fn subscribe_page_url(base_url: &str, parameters: &[(&str, &str)]) -> String {
+    let mut url = Url::parse(&format!("{}/subscribe", base_url.trim_end_matches('/')))
+        .expect("The application base URL is invalid.");
+    url.query_pairs_mut().extend_pairs(parameters);
+    url.into()
+}
+
+/// A fresh form token for signup forms not served by us, such as the widget.
+pub async fn form_token(ctx: State<ApiContext>) -> impl IntoResponse {
+    (
+        [(CACHE_CONTROL, "no-store")],
+        Json(FormTokenBody {
+            form_token: ctx.bot_protection.issue_form_token(Utc::now()),
+            captcha_site_key: ctx.bot_protection.captcha_site_key().map(Into::into),
+        }),
+    )
+}
+
+/// Target of the `unsubscribe_url` rendered into every email. Redirects to the hosted
+/// "unsubscribed" page.
+#[tracing::instrument(name = "Unsubscribing a subscriber", skip(ctx, parameters))]
+pub async fn unsubscribe(
+    ctx: State<ApiContext>,
+    Query(parameters): Query<UnsubscribeParameters>,
+) -> Result<Redirect, Error> {
+    let updated = sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed' WHERE unsubscribe_token = $1"#,
+        parameters.token
+    )
+    .execute(&ctx.connection_pool)
+    .await?
+    .rows_affected();
+    if updated == 0 {
+        return Err(Error::NotFound);
+    }
+
+    if let Some(issue_id) = parameters.issue {
+        sqlx::query!(
+            r#"
+            INSERT INTO issue_feedback_events (newsletter_issue_id, subscriber_email, kind, received_at)
+            SELECT i.id, s.email, 'unsubscribed', now()
+            FROM subscriptions s, newsletter_issues i
+            WHERE s.unsubscribe_token = $1 AND i.id = $2
+            ON CONFLICT DO NOTHING
+            "#,
+            parameters.token,
+            issue_id
+        )
+        .execute(&ctx.connection_pool)
+        .await?;
+    }
+
+    Ok(Redirect::to(&format!(
+        "{}/subscribe/unsubscribed",
+        ctx.application.base_url.trim_end_matches('/')
+    )))
+}
+
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(ctx, new_subscriber)
@@ -48,22 +333,112 @@ async fn insert_subscriber(
     new_subscriber: NewSubscriber,
     ctx: State<ApiContext>,
 ) -> Result<(), Error> {
+    let subscriber_id = Uuid::new_v4();
+    let mut transaction = ctx.connection_pool.begin().await?;
     sqlx::query!(
         r#"
-            INSERT INTO subscriptions (id, email, name, subscribed_at)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO subscriptions (id, email, name, subscribed_at, attributes, timezone)
+            VALUES ($1, $2, $3, $4, $5, $6)
             "#,
-        Uuid::new_v4(),
+        subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        new_subscriber.attributes.into_value(),
+        new_subscriber.timezone.as_ref().map(AsRef::as_ref)
     )
-    .execute(&ctx.connection_pool)
+    .execute(&mut *transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
     })?;
 
+    let tag_names: Vec<String> = new_subscriber
+        .tags
+        .iter()
+        .map(|t| t.as_ref().to_owned())
+        .collect();
+    let tagged = sqlx::query!(
+        r#"
+            INSERT INTO subscription_tags (subscriber_id, tag_id)
+            SELECT $1, id FROM tags WHERE name = ANY($2) AND publicly_settable
+            "#,
+        subscriber_id,
+        &tag_names
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    if tagged != tag_names.len() as u64 {
+        return Err(Error::BadRequest(
+            "Only existing public tags may be set on signup.".into(),
+        ));
+    }
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Fetching the subscriber attribute schema", skip(pool))]
+pub(crate) async fn fetch_attribute_schema(pool: &PgPool) -> Result<AttributeSchema, Error> {
+    let rows =
+        sqlx::query!(r#"SELECT key, value_type, publicly_settable FROM attribute_definitions"#)
+            .fetch_all(pool)
+            .await?;
+
+    let definitions = rows
+        .into_iter()
+        .map(|row| {
+            let value_type = row.value_type.try_into().map_err(anyhow::Error::msg)?;
+            Ok(AttributeDefinition {
+                key: row.key,
+                value_type,
+                publicly_settable: row.publicly_settable,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(AttributeSchema::new(definitions))
+}
+
+/// Replace the tags of a subscriber, creating any tag that does not exist yet.
+#[tracing::instrument(name = "Replacing subscriber tags", skip(transaction, tags))]
+pub(crate) async fn replace_subscriber_tags(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    tags: &[SubscriberTag],
+) -> Result<(), Error> {
+    let tag_names: Vec<String> = tags.iter().map(|t| t.as_ref().to_owned()).collect();
+    let tag_ids: Vec<Uuid> = tags.iter().map(|_| Uuid::new_v4()).collect();
+
+    sqlx::query!(
+        r#"
+            INSERT INTO tags (id, name)
+            SELECT * FROM UNNEST($1::uuid[], $2::text[])
+            ON CONFLICT (name) DO NOTHING
+            "#,
+        &tag_ids,
+        &tag_names
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM subscription_tags WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+            INSERT INTO subscription_tags (subscriber_id, tag_id)
+            SELECT $1, id FROM tags WHERE name = ANY($2)
+            "#,
+        subscriber_id,
+        &tag_names
+    )
+    .execute(&mut **transaction)
+    .await?;
+
     Ok(())
 }
@@ -0,0 +1,113 @@
+use axum::{
+    extract::{Query, State},
+    http::header::{CACHE_CONTROL, CONTENT_TYPE},
+    response::{IntoResponse, Redirect},
+};
+use reqwest::Url;
+use serde::Deserialize;
+use sqlx::{types::Uuid, PgPool};
+
+use crate::{error::Error, tracking::Tracker, tracking::TRACKING_PIXEL, ApiContext};
+
+#[derive(Deserialize)]
+pub struct OpenParameters {
+    pub issue: Uuid,
+    pub subscriber: Uuid,
+    pub signature: String,
+}
+
+#[derive(Deserialize)]
+pub struct ClickParameters {
+    pub issue: Uuid,
+    pub subscriber: Uuid,
+    pub url: String,
+    pub signature: String,
+}
+
+/// Serve the tracking pixel and record the open.
+#[tracing::instrument(name = "Tracking an open", skip(ctx, parameters))]
+pub async fn track_open(
+    ctx: State<ApiContext>,
+    Query(parameters): Query<OpenParameters>,
+) -> crate::Result<impl IntoResponse> {
+    let tracker = Tracker::new(&ctx.application.base_url, &ctx.application.hmac_secret);
+    if !tracker.verify_open(
+        parameters.issue,
+        parameters.subscriber,
+        &parameters.signature,
+    ) {
+        return Err(Error::NotFound);
+    }
+
+    record_open(
+        &ctx.connection_pool,
+        parameters.issue,
+        parameters.subscriber,
+    )
+    .await?;
+
+    Ok((
+        [(CONTENT_TYPE, "image/gif"), (CACHE_CONTROL, "no-store")],
+        TRACKING_PIXEL,
+    ))
+}
+
+/// Record the click and redirect to the original link. A click also counts as an open,
+/// since many email clients block the tracking pixel.
+#[tracing::instrument(name = "Tracking a click", skip(ctx, parameters))]
+pub async fn track_click(
+    ctx: State<ApiContext>,
+    Query(parameters): Query<ClickParameters>,
+) -> crate::Result<Redirect> {
+    let tracker = Tracker::new(&ctx.application.base_url, &ctx.application.hmac_secret);
+    if !tracker.verify_click(
+        parameters.issue,
+        parameters.subscriber,
+        &parameters.url,
+        &parameters.signature,
+    ) {
+        return Err(Error::NotFound);
+    }
+    // `Location` only gets a re-parsed URL, whose serialization is always a valid header.
+    let target = Url::parse(&parameters.url)
+        .map_err(|_| Error::BadRequest("The link is not a valid URL".into()))?;
+
+    record_open(
+        &ctx.connection_pool,
+        parameters.issue,
+        parameters.subscriber,
+    )
+    .await?;
+    sqlx::query!(
+        r#"
+            INSERT INTO issue_clicks (newsletter_issue_id, subscriber_id, url, first_clicked_at)
+            SELECT $1, id, $3, now() FROM subscriptions WHERE id = $2
+            ON CONFLICT (newsletter_issue_id, subscriber_id, url)
+            DO UPDATE SET clicks = issue_clicks.clicks + 1
+            "#,
+        parameters.issue,
+        parameters.subscriber,
+        parameters.url
+    )
+    .execute(&ctx.connection_pool)
+    .await?;
+
+    Ok(Redirect::to(target.as_str()))
+}
+
+/// Subscribers deleted since the issue went out are not recorded.
+async fn record_open(pool: &PgPool, issue_id: Uuid, subscriber_id: Uuid) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+            INSERT INTO issue_opens (newsletter_issue_id, subscriber_id, first_opened_at)
+            SELECT $1, id, now() FROM subscriptions WHERE id = $2
+            ON CONFLICT (newsletter_issue_id, subscriber_id)
+            DO UPDATE SET opens = issue_opens.opens + 1
+            "#,
+        issue_id,
+        subscriber_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
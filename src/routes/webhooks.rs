@@ -0,0 +1,67 @@
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use hyper::StatusCode;
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+
+use crate::{error::Error, routes::admin::constant_time_eq, ApiContext};
+
+#[derive(Deserialize)]
+pub struct WebhookParameters {
+    pub token: String,
+}
+
+/// The fields we use of a Postmark webhook payload. Every record type carries the
+/// recipient address.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PostmarkEvent {
+    pub record_type: String,
+    pub email: String,
+}
+
+/// Record bounces and spam complaints against the issue last delivered to the address.
+/// Other record types and unknown addresses are acknowledged and ignored, so Postmark does
+/// not retry them.
+#[tracing::instrument(
+    name = "Receiving a Postmark webhook",
+    skip(ctx, parameters, event),
+    fields(record_type = %event.record_type)
+)]
+pub async fn postmark_webhook(
+    ctx: State<ApiContext>,
+    Query(parameters): Query<WebhookParameters>,
+    Json(event): Json<PostmarkEvent>,
+) -> crate::Result<StatusCode> {
+    if !constant_time_eq(
+        parameters.token.as_bytes(),
+        ctx.application.webhook_token.expose_secret().as_bytes(),
+    ) {
+        return Err(Error::Unauthorized);
+    }
+
+    let kind = match event.record_type.as_str() {
+        "Bounce" => "bounced",
+        "SpamComplaint" => "complained",
+        _ => return Ok(StatusCode::NO_CONTENT),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_feedback_events (newsletter_issue_id, subscriber_email, kind, received_at)
+        SELECT newsletter_issue_id, subscriber_email, $2, now()
+        FROM issue_deliveries
+        WHERE subscriber_email = $1 AND outcome = 'sent'
+        ORDER BY completed_at DESC
+        LIMIT 1
+        ON CONFLICT DO NOTHING
+        "#,
+        event.email,
+        kind
+    )
+    .execute(&ctx.connection_pool)
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
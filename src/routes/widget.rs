@@ -0,0 +1,50 @@
+//! Embeddable signup widget. Static sites include it with a single `<script>` tag; it loads
+//! its config from us and posts signups to `POST /subscriptions`.
+//!
+//! The script is versioned through its path, so a version can be cached for a long time and
+//! breaking changes ship under a new path.
+
+use axum::{
+    extract::State,
+    http::header::{CACHE_CONTROL, CONTENT_TYPE},
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+
+use crate::ApiContext;
+
+const WIDGET_V1: &str = include_str!("../../static/widget/v1.js");
+
+#[derive(Serialize)]
+pub struct WidgetConfigBody {
+    pub name: String,
+    pub accent_color: String,
+    pub subscribe_url: String,
+}
+
+pub async fn widget_v1_script() -> impl IntoResponse {
+    (
+        [
+            (CONTENT_TYPE, "text/javascript; charset=utf-8"),
+            (CACHE_CONTROL, "public, max-age=86400"),
+        ],
+        WIDGET_V1,
+    )
+}
+
+/// Branding changes should reach every site quickly, so the config is cached briefly.
+pub async fn widget_v1_config(ctx: State<ApiContext>) -> impl IntoResponse {
+    let branding = &ctx.application.branding;
+    (
+        [(CACHE_CONTROL, "public, max-age=300")],
+        Json(WidgetConfigBody {
+            name: branding.name.clone(),
+            accent_color: branding.accent_color.clone(),
+            subscribe_url: format!(
+                "{}/subscriptions",
+                ctx.application.base_url.trim_end_matches('/')
+            ),
+        }),
+    )
+}
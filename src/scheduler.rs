@@ -0,0 +1,389 @@
+//! Moves scheduled newsletter issues into the delivery queue once they are due, and marks
+//! them as sent once the queue has drained.
+//!
+//! Issues scheduled at a local wall-clock time are fanned out into one delivery bucket
+//! per subscriber timezone, and each bucket is enqueued once its own send time is reached.
+//! Subscribers who confirm or change timezone during the fan-out are picked up on the next
+//! pass; once the last bucket is enqueued, the recipients of the issue are final.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use sqlx::{types::Uuid, Connection, PgPool, Postgres, QueryBuilder, Transaction};
+use tokio_util::sync::CancellationToken;
+
+use crate::{domain::SubscriberTimezone, segments::Segment};
+
+/// Key of the transaction-level advisory lock held while enqueueing due issues,
+/// so only one application instance runs a scheduling pass at a time.
+const SCHEDULER_LOCK_KEY: i64 = 0x6e6c_7363_6864_6c72;
+
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The largest UTC offset in use (Pacific/Kiritimati).
+const MAX_UTC_OFFSET_HOURS: i64 = 14;
+
+pub async fn run_scheduler_until_stopped(
+    connection_pool: PgPool,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    while !shutdown.is_cancelled() {
+        if let Err(e) = enqueue_due_issues(&connection_pool).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to enqueue due newsletter issues"
+            );
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(SCHEDULER_INTERVAL) => {}
+            _ = shutdown.cancelled() => {}
+        }
+    }
+    Ok(())
+}
+
+/// The first instant at which `local_send_at` is reached in any timezone. Local-time issues
+/// are scheduled at that instant, when their timezone buckets are laid out.
+pub fn earliest_local_send_time(local_send_at: NaiveDateTime) -> DateTime<Utc> {
+    local_send_at.and_utc() - TimeDelta::hours(MAX_UTC_OFFSET_HOURS)
+}
+
+/// An issue whose delivery is about to start.
+pub(crate) struct IssueToSend {
+    pub id: Uuid,
+    pub segment: Option<String>,
+    /// Name of a saved segment, resolved into `segment` once delivery starts.
+    pub saved_segment: Option<String>,
+    pub local_send_at: Option<NaiveDateTime>,
+    pub fallback_timezone: Option<String>,
+}
+
+/// Start delivering every issue whose send time has passed and enqueue every timezone
+/// bucket that became due. Sending issues with nothing left to deliver are marked as sent.
+/// An issue that cannot be started, say because its saved segment was deleted, is moved back
+/// to the drafts without holding up the others.
+///
+/// Returns the number of issues started plus the number of buckets enqueued, or zero if
+/// another instance currently holds the scheduler lock.
+#[tracing::instrument(name = "Enqueueing due newsletter issues", skip(connection_pool))]
+pub async fn enqueue_due_issues(connection_pool: &PgPool) -> anyhow::Result<usize> {
+    let mut transaction = connection_pool.begin().await?;
+    let locked = sqlx::query_scalar!("SELECT pg_try_advisory_xact_lock($1)", SCHEDULER_LOCK_KEY)
+        .fetch_one(&mut *transaction)
+        .await?;
+    if locked != Some(true) {
+        return Ok(0);
+    }
+
+    let due_issues = sqlx::query_as!(
+        IssueToSend,
+        r#"
+        SELECT id, segment, saved_segment, local_send_at, fallback_timezone
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND scheduled_at <= now()
+        ORDER BY scheduled_at
+        FOR UPDATE
+        "#
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let mut started = 0;
+    for issue in &due_issues {
+        let mut savepoint = transaction.begin().await?;
+        match start_delivery(&mut savepoint, issue).await {
+            Ok(()) => {
+                savepoint.commit().await?;
+                started += 1;
+            }
+            Err(e) => {
+                savepoint.rollback().await?;
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    newsletter_issue_id = %issue.id,
+                    "Failed to start delivering an issue. It was moved back to the drafts."
+                );
+                return_to_drafts(&mut transaction, issue.id).await?;
+            }
+        }
+    }
+
+    // Until its last bucket is enqueued, an issue also reaches subscribers who confirmed or
+    // changed timezone after its delivery started: new timezones get a bucket of their own,
+    // and buckets already enqueued pick up the subscribers who joined them since.
+    let issues_still_fanning_out = sqlx::query_as!(
+        IssueToSend,
+        r#"
+        SELECT id, segment, saved_segment, local_send_at, fallback_timezone
+        FROM newsletter_issues i
+        WHERE status = 'sending'
+            AND EXISTS (
+                SELECT 1 FROM issue_delivery_buckets b
+                WHERE b.newsletter_issue_id = i.id AND b.enqueued_at IS NULL
+            )
+        "#
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    for issue in &issues_still_fanning_out {
+        if let Some(local_send_at) = issue.local_send_at {
+            create_delivery_buckets(&mut transaction, issue, local_send_at).await?;
+        }
+    }
+
+    let buckets = sqlx::query!(
+        r#"
+        SELECT b.newsletter_issue_id, b.timezone, b.enqueued_at IS NULL AS "due!",
+            i.segment, i.fallback_timezone
+        FROM issue_delivery_buckets b
+        JOIN newsletter_issues i ON i.id = b.newsletter_issue_id
+        WHERE b.send_at <= now() AND i.status = 'sending'
+            AND (
+                b.enqueued_at IS NULL
+                OR i.id = ANY($1)
+            )
+        ORDER BY b.send_at
+        FOR UPDATE OF b
+        "#,
+        &issues_still_fanning_out
+            .iter()
+            .map(|issue| issue.id)
+            .collect::<Vec<_>>()
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    for bucket in &buckets {
+        let bucket_filter = TimezoneBucket {
+            timezone: &bucket.timezone,
+            fallback_timezone: bucket.fallback_timezone.as_deref().unwrap_or("UTC"),
+        };
+        let recipients = enqueue_recipients(
+            &mut transaction,
+            bucket.newsletter_issue_id,
+            bucket.segment.as_deref(),
+            Some(bucket_filter),
+        )
+        .await?;
+        sqlx::query!(
+            r#"
+            UPDATE issue_delivery_buckets
+            SET recipients = COALESCE(recipients, 0) + $3,
+                enqueued_at = COALESCE(enqueued_at, now())
+            WHERE newsletter_issue_id = $1 AND timezone = $2
+            "#,
+            bucket.newsletter_issue_id,
+            bucket.timezone,
+            i32::try_from(recipients)?
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+    let due_buckets = buckets.iter().filter(|bucket| bucket.due).count();
+
+    mark_sent_issues(&mut transaction).await?;
+    transaction.commit().await?;
+
+    Ok(started + due_buckets)
+}
+
+async fn return_to_drafts(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'draft', scheduled_at = NULL, local_send_at = NULL, fallback_timezone = NULL
+        WHERE id = $1
+        "#,
+        issue_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// An issue is sent once every bucket was enqueued and the worker emptied the queue.
+async fn mark_sent_issues(transaction: &mut Transaction<'_, Postgres>) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues i
+        SET status = 'sent', sent_at = now()
+        WHERE i.status = 'sending'
+            AND NOT EXISTS (
+                SELECT 1 FROM issue_delivery_buckets b
+                WHERE b.newsletter_issue_id = i.id AND b.enqueued_at IS NULL
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM issue_delivery_queue q WHERE q.newsletter_issue_id = i.id
+            )
+        "#
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Enqueue the issue right away, or lay out its timezone buckets if it is sent at a local
+/// time, and mark it as sending. Must run in the transaction that locked the issue row.
+#[tracing::instrument(name = "Starting issue delivery", skip_all, fields(newsletter_issue_id = %issue.id))]
+pub(crate) async fn start_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue: &IssueToSend,
+) -> anyhow::Result<()> {
+    let resolved;
+    let issue = match &issue.saved_segment {
+        Some(name) => {
+            let expression = sqlx::query_scalar!(
+                r#"
+                UPDATE newsletter_issues i SET segment = s.expression
+                FROM segments s
+                WHERE i.id = $1 AND s.name = $2
+                RETURNING s.expression
+                "#,
+                issue.id,
+                name
+            )
+            .fetch_optional(&mut **transaction)
+            .await?
+            .with_context(|| format!("The saved segment `{}` no longer exists", name))?;
+            resolved = IssueToSend {
+                id: issue.id,
+                segment: Some(expression),
+                saved_segment: None,
+                local_send_at: issue.local_send_at,
+                fallback_timezone: issue.fallback_timezone.clone(),
+            };
+            &resolved
+        }
+        None => issue,
+    };
+    match issue.local_send_at {
+        None => {
+            enqueue_recipients(transaction, issue.id, issue.segment.as_deref(), None).await?;
+        }
+        Some(local_send_at) => create_delivery_buckets(transaction, issue, local_send_at).await?,
+    }
+
+    sqlx::query!(
+        r#"UPDATE newsletter_issues SET status = 'sending', enqueued_at = now() WHERE id = $1"#,
+        issue.id
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Create one bucket per distinct timezone among the recipients that has none yet, due when
+/// the wall clock in that timezone reaches the local send time. Subscribers without a
+/// timezone are grouped under the fallback timezone of the issue.
+async fn create_delivery_buckets(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue: &IssueToSend,
+    local_send_at: NaiveDateTime,
+) -> anyhow::Result<()> {
+    let fallback_timezone = issue.fallback_timezone.as_deref().unwrap_or("UTC");
+    let fallback =
+        SubscriberTimezone::parse(fallback_timezone.to_owned()).map_err(anyhow::Error::msg)?;
+
+    let mut query = QueryBuilder::<Postgres>::new("SELECT DISTINCT COALESCE(s.timezone, ");
+    query.push_bind(fallback_timezone);
+    query.push(") FROM subscriptions s WHERE s.status = 'confirmed'");
+    push_segment_filter(&mut query, issue.segment.as_deref())?;
+    let timezones: Vec<String> = query
+        .build_query_scalar()
+        .fetch_all(&mut **transaction)
+        .await?;
+
+    let send_times: Vec<DateTime<Utc>> = timezones
+        .iter()
+        .map(|timezone| {
+            SubscriberTimezone::parse(timezone.clone())
+                .unwrap_or(fallback)
+                .to_utc(local_send_at)
+        })
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_buckets (newsletter_issue_id, timezone, send_at)
+        SELECT $1, * FROM UNNEST($2::text[], $3::timestamptz[])
+        ON CONFLICT DO NOTHING
+        "#,
+        issue.id,
+        &timezones,
+        &send_times
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+struct TimezoneBucket<'a> {
+    timezone: &'a str,
+    fallback_timezone: &'a str,
+}
+
+/// Insert one delivery task per confirmed subscriber matching the segment, restricted to a
+/// single timezone bucket if given, and add them to the recipients of the issue.
+/// Returns the number of tasks enqueued.
+async fn enqueue_recipients(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    segment: Option<&str>,
+    bucket: Option<TimezoneBucket<'_>>,
+) -> anyhow::Result<u64> {
+    let mut query = QueryBuilder::<Postgres>::new(
+        "INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email, timezone_bucket) \
+         SELECT ",
+    );
+    query.push_bind(newsletter_issue_id);
+    query.push(", s.email, ");
+    query.push_bind(bucket.as_ref().map(|b| b.timezone.to_owned()));
+    query.push(" FROM subscriptions s WHERE s.status = 'confirmed'");
+    if let Some(bucket) = &bucket {
+        query.push(" AND COALESCE(s.timezone, ");
+        query.push_bind(bucket.fallback_timezone.to_owned());
+        query.push(") = ");
+        query.push_bind(bucket.timezone.to_owned());
+    }
+    push_segment_filter(&mut query, segment)?;
+    // A subscriber who moved timezones between two buckets still gets the issue only once,
+    // whether the task of the earlier bucket is still queued or already completed.
+    query.push(
+        " AND NOT EXISTS (SELECT 1 FROM issue_deliveries d \
+         WHERE d.newsletter_issue_id = ",
+    );
+    query.push_bind(newsletter_issue_id);
+    query.push(" AND d.subscriber_email = s.email) ON CONFLICT DO NOTHING");
+
+    let enqueued = query
+        .build()
+        .execute(&mut **transaction)
+        .await?
+        .rows_affected();
+    sqlx::query!(
+        r#"UPDATE newsletter_issues SET recipients = recipients + $2 WHERE id = $1"#,
+        newsletter_issue_id,
+        i32::try_from(enqueued)?
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(enqueued)
+}
+
+fn push_segment_filter(
+    query: &mut QueryBuilder<'_, Postgres>,
+    segment: Option<&str>,
+) -> anyhow::Result<()> {
+    if let Some(expression) = segment {
+        let segment = Segment::parse(expression).map_err(anyhow::Error::msg)?;
+        query.push(" AND ");
+        segment.push_sql(query);
+    }
+    Ok(())
+}
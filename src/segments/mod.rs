@@ -0,0 +1,363 @@
+//! A small filter language for targeting a subset of subscribers, e.g.
+//! `confirmed AND tag:beta AND subscribed_at > 2026-01-01`.
+//!
+//! Expressions are parsed into a [`Segment`] and compiled into a parameterized
+//! `WHERE` clause over `subscriptions s`; user input never ends up in the SQL text.
+
+use std::fmt;
+
+use sqlx::{
+    types::chrono::{DateTime, Utc},
+    Postgres, QueryBuilder,
+};
+
+use crate::domain::{AttributeSchema, AttributeType};
+
+mod parser;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    And(Box<Segment>, Box<Segment>),
+    Or(Box<Segment>, Box<Segment>),
+    Not(Box<Segment>),
+    Predicate(Predicate),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    Confirmed,
+    Tag(String),
+    Compare {
+        field: Field,
+        operator: Operator,
+        value: Literal,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    SubscribedAt,
+    Email,
+    Name,
+    Status,
+    Attribute(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    NotEq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    String(String),
+    Number(f64),
+    Boolean(bool),
+    Timestamp(DateTime<Utc>),
+}
+
+impl Segment {
+    pub fn parse(expression: &str) -> Result<Segment, String> {
+        parser::parse(expression)
+    }
+
+    /// Check that every field is compared with a value of a matching type and that
+    /// every referenced attribute is declared in the schema.
+    pub fn check(&self, schema: &AttributeSchema) -> Result<(), String> {
+        match self {
+            Segment::And(left, right) | Segment::Or(left, right) => {
+                left.check(schema)?;
+                right.check(schema)
+            }
+            Segment::Not(inner) => inner.check(schema),
+            Segment::Predicate(predicate) => predicate.check(schema),
+        }
+    }
+
+    /// Append this segment as a boolean SQL expression over the `subscriptions` table aliased as `s`.
+    pub fn push_sql(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Segment::And(left, right) => {
+                builder.push("(");
+                left.push_sql(builder);
+                builder.push(" AND ");
+                right.push_sql(builder);
+                builder.push(")");
+            }
+            Segment::Or(left, right) => {
+                builder.push("(");
+                left.push_sql(builder);
+                builder.push(" OR ");
+                right.push_sql(builder);
+                builder.push(")");
+            }
+            Segment::Not(inner) => {
+                builder.push("NOT ");
+                inner.push_sql(builder);
+            }
+            Segment::Predicate(predicate) => predicate.push_sql(builder),
+        }
+    }
+}
+
+impl Predicate {
+    fn check(&self, schema: &AttributeSchema) -> Result<(), String> {
+        let (field, operator, value) = match self {
+            Predicate::Confirmed | Predicate::Tag(_) => return Ok(()),
+            Predicate::Compare {
+                field,
+                operator,
+                value,
+            } => (field, *operator, value),
+        };
+        let is_equality = matches!(operator, Operator::Eq | Operator::NotEq);
+
+        let is_valid = match (field, value) {
+            (Field::SubscribedAt, Literal::Timestamp(_)) => true,
+            (Field::Email | Field::Name | Field::Status, Literal::String(_)) => is_equality,
+            (Field::Attribute(key), value) => {
+                let definition = schema
+                    .get(key)
+                    .ok_or_else(|| format!("{} is not a declared attribute.", key))?;
+                match (definition.value_type, value) {
+                    (AttributeType::String, Literal::String(_)) => true,
+                    (AttributeType::Number, Literal::Number(_)) => true,
+                    (AttributeType::Boolean, Literal::Boolean(_)) => is_equality,
+                    _ => false,
+                }
+            }
+            _ => false,
+        };
+
+        if is_valid {
+            Ok(())
+        } else {
+            Err(format!(
+                "`{} {} {}` compares values of incompatible types.",
+                field, operator, value
+            ))
+        }
+    }
+
+    fn push_sql(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Predicate::Confirmed => {
+                builder.push("s.status = 'confirmed'");
+            }
+            Predicate::Tag(tag) => {
+                builder.push(
+                    "EXISTS (SELECT 1 FROM subscription_tags st JOIN tags t ON t.id = st.tag_id \
+                     WHERE st.subscriber_id = s.id AND t.name = ",
+                );
+                builder.push_bind(tag.clone());
+                builder.push(")");
+            }
+            Predicate::Compare {
+                field: Field::Attribute(key),
+                operator,
+                value,
+            } => push_attribute_comparison(builder, key, *operator, value),
+            Predicate::Compare {
+                field,
+                operator,
+                value,
+            } => {
+                let column = match field {
+                    Field::SubscribedAt => "s.subscribed_at",
+                    Field::Email => "s.email",
+                    Field::Name => "s.name",
+                    Field::Status => "s.status",
+                    Field::Attribute(_) => unreachable!(),
+                };
+                builder.push(format_args!("{} {} ", column, operator));
+                match value {
+                    Literal::Timestamp(timestamp) => builder.push_bind(*timestamp),
+                    Literal::String(s) => builder.push_bind(s.clone()),
+                    Literal::Number(n) => builder.push_bind(*n),
+                    Literal::Boolean(b) => builder.push_bind(*b),
+                };
+            }
+        }
+    }
+}
+
+/// Attributes live in a JSONB column, so values are compared as JSONB. Ordering comparisons
+/// are guarded by `jsonb_typeof` because JSONB orders values of different types against each other,
+/// and `!=` matches subscribers missing the attribute altogether.
+fn push_attribute_comparison(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    key: &str,
+    operator: Operator,
+    value: &Literal,
+) {
+    let json_value = match value {
+        Literal::String(s) => serde_json::Value::from(s.clone()),
+        Literal::Number(n) => serde_json::Value::from(*n),
+        Literal::Boolean(b) => serde_json::Value::from(*b),
+        Literal::Timestamp(t) => serde_json::Value::from(t.to_rfc3339()),
+    };
+
+    builder.push("(");
+    if !matches!(operator, Operator::Eq | Operator::NotEq) {
+        builder.push("jsonb_typeof(s.attributes -> ");
+        builder.push_bind(key.to_owned());
+        builder.push(") = jsonb_typeof(");
+        builder.push_bind(json_value.clone());
+        builder.push(") AND ");
+    }
+    builder.push("s.attributes -> ");
+    builder.push_bind(key.to_owned());
+    match operator {
+        Operator::NotEq => builder.push(" IS DISTINCT FROM "),
+        operator => builder.push(format_args!(" {} ", operator)),
+    };
+    builder.push_bind(json_value);
+    builder.push(")");
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Field::SubscribedAt => f.write_str("subscribed_at"),
+            Field::Email => f.write_str("email"),
+            Field::Name => f.write_str("name"),
+            Field::Status => f.write_str("status"),
+            Field::Attribute(key) => write!(f, "attr.{}", key),
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operator::Eq => "=",
+            Operator::NotEq => "!=",
+            Operator::Gt => ">",
+            Operator::Gte => ">=",
+            Operator::Lt => "<",
+            Operator::Lte => "<=",
+        })
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::String(s) => write!(f, "{:?}", s),
+            Literal::Number(n) => n.fmt(f),
+            Literal::Boolean(b) => b.fmt(f),
+            Literal::Timestamp(t) => f.write_str(&t.to_rfc3339()),
+        }
+    }
+}
+
+/// A name under which a segment expression is saved.
+#[derive(Debug, Clone)]
+pub struct SegmentName(String);
+
+impl SegmentName {
+    pub fn parse(s: String) -> Result<SegmentName, String> {
+        let is_empty = s.is_empty();
+        let is_too_long = s.len() > 64;
+        let contains_forbidden_characters = s
+            .chars()
+            .any(|c| !c.is_ascii_lowercase() && !c.is_ascii_digit() && c != '-' && c != '_');
+
+        if is_empty || is_too_long || contains_forbidden_characters {
+            Err(format!("{} is not a valid segment name.", s))
+        } else {
+            Ok(Self(s))
+        }
+    }
+}
+
+impl AsRef<str> for SegmentName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use sqlx::{Postgres, QueryBuilder};
+
+    use crate::domain::{AttributeDefinition, AttributeSchema, AttributeType};
+    use crate::segments::Segment;
+
+    fn schema() -> AttributeSchema {
+        AttributeSchema::new([
+            AttributeDefinition {
+                key: "plan".into(),
+                value_type: AttributeType::String,
+                publicly_settable: false,
+            },
+            AttributeDefinition {
+                key: "seats".into(),
+                value_type: AttributeType::Number,
+                publicly_settable: false,
+            },
+        ])
+    }
+
+    fn sql(expression: &str) -> String {
+        let mut builder = QueryBuilder::<Postgres>::new("");
+        Segment::parse(expression).unwrap().push_sql(&mut builder);
+        builder.into_sql()
+    }
+
+    #[test]
+    fn values_are_bound_as_parameters() {
+        assert_eq!(
+            sql("confirmed AND (tag:beta OR email = \"x'; DROP TABLE subscriptions; --\")"),
+            "(s.status = 'confirmed' AND (EXISTS (SELECT 1 FROM subscription_tags st \
+             JOIN tags t ON t.id = st.tag_id WHERE st.subscriber_id = s.id AND t.name = $1) \
+             OR s.email = $2))"
+        );
+    }
+
+    #[test]
+    fn attribute_ordering_is_guarded_by_type() {
+        assert_eq!(
+            sql("NOT attr.seats > 5"),
+            "NOT (jsonb_typeof(s.attributes -> $1) = jsonb_typeof($2) AND s.attributes -> $3 > $4)"
+        );
+        assert_eq!(
+            sql("attr.plan != \"pro\""),
+            "(s.attributes -> $1 IS DISTINCT FROM $2)"
+        );
+    }
+
+    #[test]
+    fn well_typed_segments_pass_the_check() {
+        for expression in [
+            "confirmed AND tag:beta AND subscribed_at > 2026-01-01",
+            "attr.plan = \"pro\" OR attr.seats >= 10",
+            "status != \"unsubscribed\"",
+        ] {
+            assert_ok!(Segment::parse(expression).unwrap().check(&schema()));
+        }
+    }
+
+    #[test]
+    fn ill_typed_segments_fail_the_check() {
+        for expression in [
+            "subscribed_at > 5",
+            "email > \"a\"",
+            "attr.seats = \"ten\"",
+            "attr.plan > 2026-01-01",
+            "attr.unknown = 1",
+        ] {
+            assert_err!(
+                Segment::parse(expression).unwrap().check(&schema()),
+                "{} should fail the check",
+                expression
+            );
+        }
+    }
+}
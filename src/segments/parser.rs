@@ -0,0 +1,381 @@
+use sqlx::types::chrono::{DateTime, NaiveDate, Utc};
+
+use crate::segments::{Field, Literal, Operator, Predicate, Segment};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LeftParen,
+    RightParen,
+    Operator(Operator),
+    Word(String),
+    Quoted(String),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LeftParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RightParen);
+            }
+            '=' => {
+                chars.next();
+                tokens.push(Token::Operator(Operator::Eq));
+            }
+            '!' | '>' | '<' => {
+                chars.next();
+                let followed_by_eq = chars.next_if_eq(&'=').is_some();
+                let operator = match (c, followed_by_eq) {
+                    ('!', true) => Operator::NotEq,
+                    ('>', false) => Operator::Gt,
+                    ('>', true) => Operator::Gte,
+                    ('<', false) => Operator::Lt,
+                    ('<', true) => Operator::Lte,
+                    _ => return Err("`!` must be followed by `=`.".into()),
+                };
+                tokens.push(Token::Operator(operator));
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => value.push(escaped),
+                            None => return Err("Unterminated string literal.".into()),
+                        },
+                        Some(q) if q == c => break,
+                        Some(other) => value.push(other),
+                        None => return Err("Unterminated string literal.".into()),
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            c if is_word_char(c) => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|&c| is_word_char(c)) {
+                    word.push(c);
+                }
+                tokens.push(Token::Word(word));
+            }
+            other => return Err(format!("Unexpected character `{}`.", other)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || ['_', '-', '.', ':', '+'].contains(&c)
+}
+
+/// Recursive descent parser for:
+///
+/// ```text
+/// expr      := and ("OR" and)*
+/// and       := unary ("AND" unary)*
+/// unary     := "NOT" unary | "(" expr ")" | predicate
+/// predicate := "confirmed" | "tag:" TAG | FIELD OPERATOR VALUE
+/// ```
+///
+/// Nesting and the number of conditions are bounded, since parsing, compiling and dropping
+/// a segment all recurse over its tree.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+    conditions: usize,
+}
+
+/// How deep `NOT` and parentheses may nest.
+const MAX_DEPTH: usize = 64;
+const MAX_CONDITIONS: usize = 1000;
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn next_is_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expression(&mut self) -> Result<Segment, String> {
+        let mut segment = self.and()?;
+        while self.next_is_keyword("OR") {
+            segment = Segment::Or(Box::new(segment), Box::new(self.and()?));
+        }
+        Ok(segment)
+    }
+
+    fn and(&mut self) -> Result<Segment, String> {
+        let mut segment = self.unary()?;
+        while self.next_is_keyword("AND") {
+            segment = Segment::And(Box::new(segment), Box::new(self.unary()?));
+        }
+        Ok(segment)
+    }
+
+    fn unary(&mut self) -> Result<Segment, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!(
+                "Conditions nest more than {} levels deep.",
+                MAX_DEPTH
+            ));
+        }
+        let segment = self.nested_unary()?;
+        self.depth -= 1;
+        Ok(segment)
+    }
+
+    fn nested_unary(&mut self) -> Result<Segment, String> {
+        if self.next_is_keyword("NOT") {
+            return Ok(Segment::Not(Box::new(self.unary()?)));
+        }
+
+        match self.next() {
+            Some(Token::LeftParen) => {
+                let segment = self.expression()?;
+                match self.next() {
+                    Some(Token::RightParen) => Ok(segment),
+                    _ => Err("Expected `)`.".into()),
+                }
+            }
+            Some(Token::Word(word)) => self.predicate(word).map(Segment::Predicate),
+            Some(other) => Err(format!("Unexpected {:?}, expected a condition.", other)),
+            None => Err("Unexpected end of expression, expected a condition.".into()),
+        }
+    }
+
+    fn predicate(&mut self, word: String) -> Result<Predicate, String> {
+        self.conditions += 1;
+        if self.conditions > MAX_CONDITIONS {
+            return Err(format!(
+                "Segments hold at most {} conditions.",
+                MAX_CONDITIONS
+            ));
+        }
+        if word.eq_ignore_ascii_case("confirmed") {
+            return Ok(Predicate::Confirmed);
+        }
+        if let Some(tag) = word.strip_prefix("tag:") {
+            let tag = match tag {
+                "" => match self.next() {
+                    Some(Token::Quoted(tag)) => tag,
+                    _ => return Err("Expected a tag name after `tag:`.".into()),
+                },
+                tag => tag.to_owned(),
+            };
+            return Ok(Predicate::Tag(tag.to_lowercase()));
+        }
+
+        let field = parse_field(&word)?;
+        let operator = match self.next() {
+            Some(Token::Operator(operator)) => operator,
+            _ => return Err(format!("Expected a comparison operator after `{}`.", word)),
+        };
+        let value = match self.next() {
+            Some(Token::Quoted(value)) => Literal::String(value),
+            Some(Token::Word(word)) => parse_bare_literal(word),
+            _ => return Err(format!("Expected a value to compare `{}` with.", field)),
+        };
+
+        Ok(Predicate::Compare {
+            field,
+            operator,
+            value,
+        })
+    }
+}
+
+fn parse_field(word: &str) -> Result<Field, String> {
+    match word.to_lowercase().as_str() {
+        "subscribed_at" => Ok(Field::SubscribedAt),
+        "email" => Ok(Field::Email),
+        "name" => Ok(Field::Name),
+        "status" => Ok(Field::Status),
+        other => match other.strip_prefix("attr.") {
+            Some(key) if !key.is_empty() => Ok(Field::Attribute(key.to_owned())),
+            _ => Err(format!("{} is not a known field.", word)),
+        },
+    }
+}
+
+/// Unquoted values are read as booleans, numbers, dates (`2026-01-01`, midnight UTC)
+/// or RFC 3339 timestamps, falling back to plain strings.
+fn parse_bare_literal(word: String) -> Literal {
+    if let Ok(value) = word.parse::<bool>() {
+        return Literal::Boolean(value);
+    }
+    if let Ok(value) = word.parse::<f64>() {
+        if value.is_finite() {
+            return Literal::Number(value);
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(&word, "%Y-%m-%d") {
+        return Literal::Timestamp(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(&word) {
+        return Literal::Timestamp(timestamp.with_timezone(&Utc));
+    }
+    Literal::String(word)
+}
+
+pub(super) fn parse(input: &str) -> Result<Segment, String> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        position: 0,
+        depth: 0,
+        conditions: 0,
+    };
+    let segment = parser.expression()?;
+    match parser.next() {
+        None => Ok(segment),
+        Some(token) => Err(format!(
+            "Unexpected {:?} after a complete condition.",
+            token
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_err;
+    use sqlx::types::chrono::{TimeZone, Utc};
+
+    use crate::segments::{Field, Literal, Operator, Predicate, Segment};
+
+    fn compare(field: Field, operator: Operator, value: Literal) -> Segment {
+        Segment::Predicate(Predicate::Compare {
+            field,
+            operator,
+            value,
+        })
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let segment = Segment::parse("tag:a OR tag:b AND NOT tag:c").unwrap();
+        let expected = Segment::Or(
+            Box::new(Segment::Predicate(Predicate::Tag("a".into()))),
+            Box::new(Segment::And(
+                Box::new(Segment::Predicate(Predicate::Tag("b".into()))),
+                Box::new(Segment::Not(Box::new(Segment::Predicate(Predicate::Tag(
+                    "c".into(),
+                ))))),
+            )),
+        );
+        assert_eq!(segment, expected);
+    }
+
+    #[test]
+    fn the_example_from_the_editors_parses() {
+        let segment =
+            Segment::parse("confirmed AND tag:beta AND subscribed_at > 2026-01-01").unwrap();
+        let expected = Segment::And(
+            Box::new(Segment::And(
+                Box::new(Segment::Predicate(Predicate::Confirmed)),
+                Box::new(Segment::Predicate(Predicate::Tag("beta".into()))),
+            )),
+            Box::new(compare(
+                Field::SubscribedAt,
+                Operator::Gt,
+                Literal::Timestamp(Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap()),
+            )),
+        );
+        assert_eq!(segment, expected);
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive_and_parentheses_group() {
+        let segment = Segment::parse("not (Confirmed or tag:\"beta\")").unwrap();
+        let expected = Segment::Not(Box::new(Segment::Or(
+            Box::new(Segment::Predicate(Predicate::Confirmed)),
+            Box::new(Segment::Predicate(Predicate::Tag("beta".into()))),
+        )));
+        assert_eq!(segment, expected);
+    }
+
+    #[test]
+    fn attribute_values_are_typed() {
+        assert_eq!(
+            Segment::parse("attr.seats >= 10").unwrap(),
+            compare(
+                Field::Attribute("seats".into()),
+                Operator::Gte,
+                Literal::Number(10.0)
+            )
+        );
+        assert_eq!(
+            Segment::parse("attr.trial != true").unwrap(),
+            compare(
+                Field::Attribute("trial".into()),
+                Operator::NotEq,
+                Literal::Boolean(true)
+            )
+        );
+        assert_eq!(
+            Segment::parse(r#"attr.plan = "pro \"plus\"""#).unwrap(),
+            compare(
+                Field::Attribute("plan".into()),
+                Operator::Eq,
+                Literal::String(r#"pro "plus""#.into())
+            )
+        );
+    }
+
+    #[test]
+    fn malformed_expressions_are_rejected() {
+        for invalid in [
+            "",
+            "confirmed AND",
+            "(confirmed",
+            "confirmed)",
+            "tag:",
+            "subscribed_at >",
+            "unknown_field = 1",
+            "attr. = 1",
+            "email ! \"a\"",
+            "name = \"unterminated",
+            "confirmed tag:beta",
+            "email = a@b.c; DROP TABLE subscriptions",
+        ] {
+            assert_err!(Segment::parse(invalid), "{} should not parse", invalid);
+        }
+    }
+
+    #[test]
+    fn deeply_nested_expressions_are_rejected_without_overflowing() {
+        let nots = format!("{}confirmed", "NOT ".repeat(100_000));
+        let parentheses = format!("{}confirmed{}", "(".repeat(100_000), ")".repeat(100_000));
+        let conditions = vec!["confirmed"; 100_000].join(" AND ");
+
+        for invalid in [nots, parentheses, conditions] {
+            assert_err!(Segment::parse(&invalid));
+        }
+        assert!(Segment::parse(&format!("{}confirmed", "NOT ".repeat(63))).is_ok());
+    }
+}
@@ -0,0 +1,318 @@
+//! Throttling of outgoing email, so we stay under the rate the email provider and the
+//! receiving mail servers accept.
+//!
+//! Every send takes a token from the global bucket and, if the recipient domain has its own
+//! limit, from that domain's bucket. Buckets refill continuously at their rate and hold at
+//! most one second worth of tokens. A bucket can be paused, which the email client does when
+//! the provider answers with `429 Too Many Requests`.
+//!
+//! Buckets live in Redis so every worker shares them. If Redis cannot be reached, each
+//! instance falls back to buckets in memory.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use redis::{aio::ConnectionManager, Script};
+
+use crate::{configuration::SendThrottleSettings, domain::SubscriberEmail};
+
+const GLOBAL_BUCKET: &str = "send_throttle:global";
+
+/// Buckets are dropped from Redis after being left untouched for this long.
+const BUCKET_TTL_MILLISECONDS: u64 = 60 * 60 * 1000;
+
+/// Take a token from every bucket in `KEYS`, or none if any of them is empty or paused.
+/// `ARGV` holds the current time in milliseconds followed by the rate and burst of each
+/// bucket. Returns how many milliseconds to wait before trying again, or 0 once taken.
+const TAKE_SCRIPT: &str = r#"
+local now = tonumber(ARGV[1])
+local wait = 0
+local tokens = {}
+for i, key in ipairs(KEYS) do
+    local rate = tonumber(ARGV[2 * i])
+    local burst = tonumber(ARGV[2 * i + 1])
+    local bucket = redis.call('HMGET', key, 'tokens', 'updated_at', 'paused_until')
+    local updated_at = tonumber(bucket[2]) or now
+    local paused_until = tonumber(bucket[3]) or 0
+    tokens[i] = math.min(burst, (tonumber(bucket[1]) or burst) + (now - updated_at) * rate / 1000)
+    if paused_until > now then
+        wait = math.max(wait, paused_until - now)
+    elseif tokens[i] < 1 then
+        wait = math.max(wait, math.ceil((1 - tokens[i]) * 1000 / rate))
+    end
+end
+if wait > 0 then
+    return wait
+end
+for i, key in ipairs(KEYS) do
+    redis.call('HSET', key, 'tokens', tostring(tokens[i] - 1), 'updated_at', tostring(now))
+    redis.call('PEXPIRE', key, ARGV[#ARGV])
+end
+return 0
+"#;
+
+const PAUSE_SCRIPT: &str = r#"
+local paused_until = tonumber(redis.call('HGET', KEYS[1], 'paused_until')) or 0
+if tonumber(ARGV[1]) > paused_until then
+    redis.call('HSET', KEYS[1], 'paused_until', ARGV[1])
+end
+redis.call('PEXPIRE', KEYS[1], ARGV[2])
+return 0
+"#;
+
+pub struct SendThrottle {
+    global: BucketLimit,
+    per_domain: HashMap<String, BucketLimit>,
+    redis: Option<ConnectionManager>,
+    memory: Mutex<HashMap<String, Bucket>>,
+}
+
+#[derive(Clone, Copy)]
+struct BucketLimit {
+    messages_per_second: f64,
+    burst: f64,
+}
+
+impl BucketLimit {
+    fn new(messages_per_second: f64) -> Self {
+        let messages_per_second = messages_per_second.max(0.001);
+        Self {
+            messages_per_second,
+            burst: messages_per_second.max(1.0),
+        }
+    }
+}
+
+impl SendThrottle {
+    /// Share buckets through `redis`, or keep them in memory if it could not be reached.
+    pub fn new(settings: &SendThrottleSettings, redis: Option<ConnectionManager>) -> Self {
+        Self {
+            global: BucketLimit::new(settings.messages_per_second),
+            per_domain: settings
+                .per_domain
+                .iter()
+                .map(|limit| {
+                    (
+                        limit.domain.to_lowercase(),
+                        BucketLimit::new(limit.messages_per_second),
+                    )
+                })
+                .collect(),
+            redis,
+            memory: Mutex::default(),
+        }
+    }
+
+    pub fn in_memory(settings: &SendThrottleSettings) -> Self {
+        Self::new(settings, None)
+    }
+
+    /// Wait until an email to `recipient` can be sent.
+    pub async fn acquire(&self, recipient: &SubscriberEmail) {
+        let buckets = self.buckets(recipient);
+        loop {
+            let wait = self.try_take(&buckets).await;
+            if wait.is_zero() {
+                return;
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Stop every send until `duration` has passed.
+    pub async fn pause(&self, duration: Duration) {
+        tracing::warn!(
+            pause_seconds = duration.as_secs_f64(),
+            "Pausing outgoing email after being throttled by the email provider"
+        );
+        let paused_until = now_milliseconds() + duration.as_millis() as u64;
+        if let Some(mut connection) = self.redis.clone() {
+            let result: redis::RedisResult<()> = Script::new(PAUSE_SCRIPT)
+                .key(GLOBAL_BUCKET)
+                .arg(paused_until)
+                .arg(BUCKET_TTL_MILLISECONDS)
+                .invoke_async(&mut connection)
+                .await;
+            match result {
+                Ok(()) => return,
+                Err(e) => tracing::warn!(
+                    error.message = %e,
+                    "Failed to pause outgoing email in Redis. Pausing it in memory."
+                ),
+            }
+        }
+        let mut memory = self.memory.lock().unwrap();
+        let bucket = memory
+            .entry(GLOBAL_BUCKET.to_owned())
+            .or_insert_with(|| Bucket::full(&self.global, now_milliseconds()));
+        bucket.paused_until = bucket.paused_until.max(paused_until);
+    }
+
+    fn buckets(&self, recipient: &SubscriberEmail) -> Vec<(String, BucketLimit)> {
+        let mut buckets = vec![(GLOBAL_BUCKET.to_owned(), self.global)];
+        let domain = recipient
+            .as_ref()
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.to_lowercase())
+            .unwrap_or_default();
+        if let Some(limit) = self.per_domain.get(&domain) {
+            buckets.push((format!("send_throttle:domain:{}", domain), *limit));
+        }
+        buckets
+    }
+
+    async fn try_take(&self, buckets: &[(String, BucketLimit)]) -> Duration {
+        let now = now_milliseconds();
+        if let Some(mut connection) = self.redis.clone() {
+            let script = Script::new(TAKE_SCRIPT);
+            let mut invocation = script.prepare_invoke();
+            invocation.arg(now);
+            for (key, limit) in buckets {
+                invocation
+                    .key(key)
+                    .arg(limit.messages_per_second)
+                    .arg(limit.burst);
+            }
+            invocation.arg(BUCKET_TTL_MILLISECONDS);
+            let result: redis::RedisResult<u64> = invocation.invoke_async(&mut connection).await;
+            match result {
+                Ok(wait) => return Duration::from_millis(wait),
+                Err(e) => tracing::warn!(
+                    error.message = %e,
+                    "Failed to take a send token in Redis. Taking it in memory."
+                ),
+            }
+        }
+        self.try_take_in_memory(buckets, now)
+    }
+
+    fn try_take_in_memory(&self, buckets: &[(String, BucketLimit)], now: u64) -> Duration {
+        let mut memory = self.memory.lock().unwrap();
+        let mut wait = 0;
+        for (key, limit) in buckets {
+            let bucket = memory
+                .entry(key.clone())
+                .or_insert_with(|| Bucket::full(limit, now));
+            bucket.refill(limit, now);
+            wait = wait.max(bucket.wait(limit, now));
+        }
+        if wait == 0 {
+            for (key, _) in buckets {
+                memory
+                    .get_mut(key)
+                    .expect("Every bucket was just inserted.")
+                    .tokens -= 1.0;
+            }
+        }
+        Duration::from_millis(wait)
+    }
+}
+
+/// In-memory counterpart of a bucket hash in Redis.
+struct Bucket {
+    tokens: f64,
+    updated_at: u64,
+    paused_until: u64,
+}
+
+impl Bucket {
+    fn full(limit: &BucketLimit, now: u64) -> Self {
+        Self {
+            tokens: limit.burst,
+            updated_at: now,
+            paused_until: 0,
+        }
+    }
+
+    fn refill(&mut self, limit: &BucketLimit, now: u64) {
+        let elapsed = now.saturating_sub(self.updated_at) as f64;
+        self.tokens = (self.tokens + elapsed * limit.messages_per_second / 1000.0).min(limit.burst);
+        self.updated_at = now;
+    }
+
+    /// Milliseconds until a token can be taken.
+    fn wait(&self, limit: &BucketLimit, now: u64) -> u64 {
+        if self.paused_until > now {
+            self.paused_until - now
+        } else if self.tokens < 1.0 {
+            ((1.0 - self.tokens) * 1000.0 / limit.messages_per_second).ceil() as u64
+        } else {
+            0
+        }
+    }
+}
+
+fn now_milliseconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("The system clock is set before 1970.")
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        configuration::{DomainThrottleSettings, SendThrottleSettings},
+        domain::SubscriberEmail,
+        send_throttle::SendThrottle,
+    };
+
+    fn throttle() -> SendThrottle {
+        SendThrottle::in_memory(&SendThrottleSettings {
+            messages_per_second: 2.0,
+            per_domain: vec![DomainThrottleSettings {
+                domain: "Outlook.com".into(),
+                messages_per_second: 0.5,
+            }],
+        })
+    }
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn sends_wait_once_the_burst_is_used_up() {
+        let throttle = throttle();
+        let start = std::time::Instant::now();
+
+        for _ in 0..3 {
+            throttle.acquire(&email("ursula@example.com")).await;
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn throttled_domains_are_limited_separately_from_other_recipients() {
+        let throttle = throttle();
+
+        throttle.acquire(&email("ursula@outlook.com")).await;
+        let outlook_wait = throttle
+            .try_take(&throttle.buckets(&email("le@outlook.com")))
+            .await;
+        let other_wait = throttle
+            .try_take(&throttle.buckets(&email("le@example.com")))
+            .await;
+
+        assert!(outlook_wait >= Duration::from_millis(1900));
+        assert!(other_wait.is_zero());
+    }
+
+    #[tokio::test]
+    async fn a_pause_holds_every_send() {
+        let throttle = throttle();
+
+        throttle.pause(Duration::from_secs(30)).await;
+        let wait = throttle
+            .try_take(&throttle.buckets(&email("ursula@example.com")))
+            .await;
+
+        assert!(wait > Duration::from_secs(29));
+    }
+}
@@ -0,0 +1,50 @@
+//! Graceful shutdown on `SIGTERM` or `SIGINT`.
+//!
+//! Once a signal arrives the server stops accepting connections and finishes the requests in
+//! flight, while the worker and scheduler finish what they are doing and stop. Whatever is
+//! still running when `application.shutdown_timeout_seconds` runs out is aborted.
+
+use std::io;
+
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+/// Listens for the signals asking the process to stop.
+pub struct ShutdownSignal {
+    #[cfg(unix)]
+    terminate: Signal,
+    #[cfg(unix)]
+    interrupt: Signal,
+}
+
+impl ShutdownSignal {
+    /// Start listening right away, so signals received before [`recv`] is awaited are not
+    /// lost and do not kill the process.
+    ///
+    /// [`recv`]: ShutdownSignal::recv
+    pub fn listen() -> io::Result<Self> {
+        Ok(Self {
+            #[cfg(unix)]
+            terminate: signal(SignalKind::terminate())?,
+            #[cfg(unix)]
+            interrupt: signal(SignalKind::interrupt())?,
+        })
+    }
+
+    /// Wait for the first signal.
+    #[cfg(unix)]
+    pub async fn recv(mut self) {
+        let name = tokio::select! {
+            _ = self.terminate.recv() => "SIGTERM",
+            _ = self.interrupt.recv() => "SIGINT",
+        };
+        tracing::info!("Received {}, shutting down", name);
+    }
+
+    /// Wait for the first signal.
+    #[cfg(not(unix))]
+    pub async fn recv(self) {
+        let _ = tokio::signal::ctrl_c().await;
+        tracing::info!("Received Ctrl-C, shutting down");
+    }
+}
@@ -0,0 +1,278 @@
+//! Assembly of the application from its settings, so `main` and the integration tests build
+//! it the same way.
+
+use std::{future::Future, net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use axum::{
+    extract::connect_info::IntoMakeServiceWithConnectInfo,
+    http::{header::CONTENT_TYPE, HeaderName, HeaderValue, Method},
+    middleware::{self, AddExtension},
+    routing::{get, post},
+    serve::Serve,
+    Router,
+};
+use futures::FutureExt;
+use reqwest::Url;
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use tokio::{
+    net::TcpListener,
+    task::{JoinError, JoinSet},
+};
+use tokio_util::sync::CancellationToken;
+use tower_http::{
+    cors::{AllowOrigin, CorsLayer},
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    sensitive_headers::{SetSensitiveRequestHeadersLayer, SetSensitiveResponseHeadersLayer},
+    trace::{DefaultOnFailure, DefaultOnRequest, DefaultOnResponse, TraceLayer},
+};
+
+use crate::{
+    bot_protection::BotProtection,
+    configuration::{ApplicationSettings, DatabaseSettings, Settings},
+    email_client::EmailClient,
+    issue_delivery_worker::run_worker_until_stopped,
+    prometheus::{install_recorder, metrics_endpoint, track_http_metrics},
+    rate_limit::{connect_to_redis, rate_limit, RateLimiter},
+    routes::{
+        admin, check_your_inbox_page, confirmed_page, email_health, form_token, liveness,
+        postmark_webhook, readiness, subscribe, subscribe_page, track_click, track_open,
+        unsubscribe, unsubscribed_page, widget_v1_config, widget_v1_script,
+    },
+    scheduler::run_scheduler_until_stopped,
+    send_throttle::SendThrottle,
+    telemetry::{
+        make_request_span, REQUEST_ID_HEADER, SENSITIVE_REQUEST_HEADERS, SENSITIVE_RESPONSE_HEADERS,
+    },
+    ApiContext, MIGRATOR,
+};
+
+type Server = Serve<
+    IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    AddExtension<Router, axum::extract::ConnectInfo<SocketAddr>>,
+>;
+
+pub struct Application {
+    port: u16,
+    server: Server,
+    connection_pool: PgPool,
+    email_client: EmailClient,
+    application: ApplicationSettings,
+}
+
+impl Application {
+    /// Migrate the database, connect to Redis and bind to `application.host` and
+    /// `application.port`. With port 0 the OS picks a free port, which is also put in
+    /// `application.base_url` so links point back to this instance.
+    pub async fn build(mut configuration: Settings) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind((
+            configuration.application.host.as_str(),
+            configuration.application.port,
+        ))
+        .await
+        .context("Failed to bind the HTTP listener")?;
+        let port = listener.local_addr()?.port();
+        if configuration.application.port == 0 {
+            let mut base_url = Url::parse(&configuration.application.base_url)
+                .context("Invalid application.base_url")?;
+            base_url
+                .set_port(Some(port))
+                .map_err(|()| anyhow::anyhow!("application.base_url cannot have a port"))?;
+            configuration.application.base_url = base_url.as_str().trim_end_matches('/').into();
+        }
+
+        let connection_pool = get_connection_pool(&configuration.database);
+        MIGRATOR
+            .run(&connection_pool)
+            .await
+            .context("Failed to migrate the database")?;
+
+        let redis = match connect_to_redis(&configuration.redis_uri).await {
+            Ok(connection) => Some(connection),
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to connect to Redis. Rate limits and send throttling are counted in memory."
+                );
+                None
+            }
+        };
+        let throttle = SendThrottle::new(&configuration.email_client.throttle, redis.clone());
+        let email_client = configuration
+            .email_client
+            .client()?
+            .with_throttle(Arc::new(throttle));
+
+        let application = configuration.application;
+        let allowed_origins = application
+            .allowed_origins
+            .iter()
+            .map(|origin| HeaderValue::from_str(origin))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Invalid origin in application.allowed_origins")?;
+        let app_context = ApiContext {
+            connection_pool: connection_pool.clone(),
+            email_client: Arc::new(email_client.clone()),
+            bot_protection: Arc::new(BotProtection::from_settings(&application, redis.clone())),
+            rate_limiter: Arc::new(RateLimiter::new(&application.rate_limit, redis.clone())),
+            redis,
+            metrics: install_recorder(),
+            application: Arc::new(application.clone()),
+        };
+        let server = axum::serve(
+            listener,
+            router(app_context, allowed_origins)
+                .into_make_service_with_connect_info::<SocketAddr>(),
+        );
+
+        Ok(Self {
+            port,
+            server,
+            connection_pool,
+            email_client,
+            application,
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn connection_pool(&self) -> &PgPool {
+        &self.connection_pool
+    }
+
+    /// Serve requests, and run the delivery worker and scheduler unless
+    /// `application.background_workers` is off, until `shutdown` completes or one of them
+    /// stops. `main` passes [`ShutdownSignal::recv`]; tests pass a future they complete
+    /// themselves, such as a cancelled [`CancellationToken`].
+    ///
+    /// [`ShutdownSignal::recv`]: crate::shutdown::ShutdownSignal::recv The others then get `application.shutdown_timeout_seconds` to finish their work
+    /// before the connection pool is closed.
+    pub async fn run_until_stopped(self, shutdown: impl Future<Output = ()>) {
+        let stop = CancellationToken::new();
+        let deadline = Duration::from_secs(self.application.shutdown_timeout_seconds);
+        let mut tasks = JoinSet::new();
+        let server = self
+            .server
+            .with_graceful_shutdown(stop.clone().cancelled_owned());
+        tasks.spawn(async move { ("API", server.await.map_err(anyhow::Error::from)) });
+        if self.application.background_workers {
+            tasks.spawn(
+                run_worker_until_stopped(
+                    self.connection_pool.clone(),
+                    self.email_client,
+                    self.application,
+                    stop.clone(),
+                )
+                .map(|outcome| ("Background worker", outcome)),
+            );
+            tasks.spawn(
+                run_scheduler_until_stopped(self.connection_pool.clone(), stop.clone())
+                    .map(|outcome| ("Scheduler", outcome)),
+            );
+        }
+
+        tokio::select! {
+            _ = shutdown => {}
+            Some(outcome) = tasks.join_next() => report_exit(outcome),
+        }
+        stop.cancel();
+        let drain = async {
+            while let Some(outcome) = tasks.join_next().await {
+                report_exit(outcome);
+            }
+        };
+        if tokio::time::timeout(deadline, drain).await.is_err() {
+            tracing::warn!(
+                "Work still in progress after {} seconds was aborted",
+                deadline.as_secs()
+            );
+            tasks.shutdown().await;
+        }
+        self.connection_pool.close().await;
+    }
+}
+
+pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
+    PgPoolOptions::new().connect_lazy_with(configuration.with_db())
+}
+
+fn router(app_context: ApiContext, allowed_origins: Vec<HeaderValue>) -> Router {
+    // Public signup routes. Admin routes sit behind the admin token instead.
+    let rate_limited = Router::new()
+        .route("/subscriptions", post(subscribe))
+        .route_layer(middleware::from_fn_with_state(
+            app_context.clone(),
+            rate_limit,
+        ));
+
+    Router::new()
+        // Kept for probes configured before liveness and readiness were split.
+        .route("/health_check", get(liveness))
+        .route("/health/live", get(liveness))
+        .route("/health/ready", get(readiness))
+        .route("/health/email", get(email_health))
+        .route("/subscribe", get(subscribe_page))
+        .route("/subscribe/check-your-inbox", get(check_your_inbox_page))
+        .route("/subscribe/confirmed", get(confirmed_page))
+        .route("/subscribe/unsubscribed", get(unsubscribed_page))
+        .route("/subscriptions/form-token", get(form_token))
+        .route("/subscriptions/unsubscribe", get(unsubscribe))
+        .route("/track/open", get(track_open))
+        .route("/track/click", get(track_click))
+        .route("/webhooks/postmark", post(postmark_webhook))
+        .route("/widget/v1.js", get(widget_v1_script))
+        .route("/widget/v1/config", get(widget_v1_config))
+        .route("/metrics", get(metrics_endpoint))
+        .merge(rate_limited)
+        .nest("/admin", admin::router(app_context.clone()))
+        .route_layer(middleware::from_fn(track_http_metrics))
+        .layer(SetSensitiveResponseHeadersLayer::new(
+            SENSITIVE_RESPONSE_HEADERS,
+        ))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(make_request_span)
+                .on_request(DefaultOnRequest::new().level(tracing::Level::INFO))
+                .on_response(DefaultOnResponse::new().level(tracing::Level::INFO))
+                .on_failure(DefaultOnFailure::new().level(tracing::Level::ERROR)),
+        )
+        .layer(SetSensitiveRequestHeadersLayer::new(
+            SENSITIVE_REQUEST_HEADERS,
+        ))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .layer(
+            CorsLayer::new()
+                .allow_origin(AllowOrigin::list(allowed_origins))
+                .allow_methods([Method::GET, Method::POST])
+                .allow_headers([CONTENT_TYPE])
+                .expose_headers([HeaderName::from_static(REQUEST_ID_HEADER)]),
+        )
+        .with_state(app_context)
+}
+
+fn report_exit(outcome: Result<(&str, anyhow::Result<()>), JoinError>) {
+    match outcome {
+        Ok((task_name, Ok(()))) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok((task_name, Err(e))) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "A task failed to complete"
+            )
+        }
+    }
+}
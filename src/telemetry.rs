@@ -0,0 +1,213 @@
+//! Distributed tracing with OpenTelemetry.
+//!
+//! `tracing` spans become OpenTelemetry spans through the layer returned by [`otel_layer`],
+//! and are exported to a collector over OTLP/HTTP when one is configured. Trace context
+//! travels in W3C `traceparent` headers: it is extracted from incoming requests, injected
+//! into requests to the email provider, and stored with scheduled issues so delivery spans
+//! can link back to the request that scheduled them.
+//!
+//! Logs leave out secrets and personal data by default: sensitive headers are marked as such
+//! before requests are logged, secret query parameters are blanked, and subscriber emails are
+//! replaced by a hash that still lets entries about the same subscriber be correlated.
+
+use axum::http::{
+    header::{AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, SET_COOKIE},
+    HeaderMap, HeaderName, Request, Uri,
+};
+use opentelemetry::{
+    propagation::TextMapPropagator,
+    trace::{TraceContextExt, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{self, Tracer, TracerProvider},
+    Resource,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::configuration::TelemetrySettings;
+
+const TRACEPARENT: &str = "traceparent";
+
+/// Identifies a request in logs. Generated unless the caller sent one, and echoed back in the
+/// response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Headers whose values never appear in logs.
+pub const SENSITIVE_REQUEST_HEADERS: [HeaderName; 3] = [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE];
+pub const SENSITIVE_RESPONSE_HEADERS: [HeaderName; 1] = [SET_COOKIE];
+
+/// Query parameters carrying tokens or personal data, whose values are blanked in logs.
+const SENSITIVE_QUERY_PARAMETERS: [&str; 4] = ["token", "signature", "email", "name"];
+
+static REDACT_SUBSCRIBER_EMAILS: AtomicBool = AtomicBool::new(true);
+
+/// Build a provider exporting spans in batches to the configured collector, or `None` if
+/// there is no collector to export to. Shut it down before exiting so the last batch is
+/// sent.
+pub fn init_tracer_provider(
+    settings: &TelemetrySettings,
+) -> anyhow::Result<Option<TracerProvider>> {
+    let Some(endpoint) = &settings.otlp_endpoint else {
+        return Ok(None);
+    };
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_http_client(reqwest::Client::new())
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')));
+    let provider =
+        opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(exporter)
+            .with_trace_config(trace::Config::default().with_resource(Resource::new([
+                KeyValue::new("service.name", settings.service_name.clone()),
+            ])))
+            .install_batch(runtime::Tokio)?;
+    Ok(Some(provider))
+}
+
+/// Layer turning `tracing` spans into OpenTelemetry spans of `provider`.
+pub fn otel_layer<S>(provider: &TracerProvider) -> OpenTelemetryLayer<S, Tracer>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer("newsletter-deliverer"))
+}
+
+/// Span of an incoming request, carrying its request ID and continuing the trace of the
+/// caller. Sensitive headers must have been marked as such beforehand.
+pub fn make_request_span<B>(request: &Request<B>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %redacted_uri(request.uri()),
+        version = ?request.version(),
+        headers = ?request.headers(),
+        request_id,
+    );
+    set_parent_from_headers(&span, request.headers());
+    span
+}
+
+/// Continue the trace of the caller, if its request carries a `traceparent` header.
+pub fn set_parent_from_headers(span: &Span, headers: &HeaderMap) {
+    let context = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    if context.span().span_context().is_valid() {
+        span.set_parent(context);
+    }
+}
+
+/// Add the `traceparent` of the current span to the headers of an outgoing request.
+pub fn inject_current_context(headers: &mut HeaderMap) {
+    TraceContextPropagator::new()
+        .inject_context(&Span::current().context(), &mut HeaderInjector(headers));
+}
+
+/// The `traceparent` of the current span, to be stored with work picked up later.
+pub fn current_traceparent() -> Option<String> {
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&Span::current().context(), &mut carrier);
+    carrier.remove(TRACEPARENT)
+}
+
+/// Link `span` to the span a stored `traceparent` came from.
+pub fn link_to_traceparent(span: &Span, traceparent: &str) {
+    let carrier = HashMap::from([(TRACEPARENT.to_owned(), traceparent.to_owned())]);
+    let context = TraceContextPropagator::new().extract(&carrier);
+    let span_context = context.span().span_context().clone();
+    if span_context.is_valid() {
+        span.add_link(span_context);
+    }
+}
+
+/// Log subscriber emails as they are instead of hashing them, for local debugging.
+pub fn log_subscriber_emails(enabled: bool) {
+    REDACT_SUBSCRIBER_EMAILS.store(!enabled, Ordering::Relaxed);
+}
+
+/// Displays a subscriber email as it may appear in logs.
+pub struct RedactedEmail<'a>(pub &'a str);
+
+impl fmt::Display for RedactedEmail<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !REDACT_SUBSCRIBER_EMAILS.load(Ordering::Relaxed) {
+            return f.write_str(self.0);
+        }
+        let digest = Sha256::digest(self.0.trim().to_lowercase());
+        write!(f, "sha256:{}", hex::encode(&digest[..8]))
+    }
+}
+
+/// `uri` with the values of sensitive query parameters blanked.
+pub fn redacted_uri(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.to_string();
+    };
+    let query = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if SENSITIVE_QUERY_PARAMETERS.contains(&name) => {
+                format!("{}=[redacted]", name)
+            }
+            _ => pair.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{}?{}", uri.path(), query)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Uri;
+
+    use crate::telemetry::{redacted_uri, RedactedEmail};
+
+    #[test]
+    fn subscriber_emails_are_hashed_case_insensitively() {
+        let logged = RedactedEmail("Ursula_le_guin@gmail.com").to_string();
+
+        assert!(logged.starts_with("sha256:"));
+        assert!(!logged.contains("gmail"));
+        assert_eq!(
+            logged,
+            RedactedEmail("ursula_le_guin@gmail.com").to_string()
+        );
+        assert_ne!(logged, RedactedEmail("le_guin@gmail.com").to_string());
+    }
+
+    #[test]
+    fn sensitive_query_parameters_are_blanked() {
+        let uri: Uri = "/subscriptions/unsubscribe?token=abc123&issue=42"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            redacted_uri(&uri),
+            "/subscriptions/unsubscribe?token=[redacted]&issue=42"
+        );
+    }
+
+    #[test]
+    fn uris_without_a_query_are_unchanged() {
+        let uri: Uri = "/health/live".parse().unwrap();
+
+        assert_eq!(redacted_uri(&uri), "/health/live");
+    }
+}
@@ -0,0 +1,303 @@
+//! Email templates rendered once per recipient.
+//!
+//! Templates are stored in Postgres and written in Jinja syntax: `{{ subscriber.name }}`,
+//! `{{ unsubscribe_url }}`, `{% if subscriber.attributes.plan == "pro" %}`, and layouts or
+//! partials pulled in by name with `{% extends "layout" %}` and `{% include "footer" %}`.
+//! Every template has an HTML and a plain-text source; the HTML source of a template only
+//! sees the HTML sources of the templates it extends or includes, and the same goes for text.
+//!
+//! Values are HTML-escaped when rendered into HTML sources, so subscriber-provided data such
+//! as names can never inject markup.
+
+use minijinja::{AutoEscape, Environment};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlx::{types::Uuid, PgPool};
+
+/// Name under which the content of a newsletter issue is compiled. It is not a valid
+/// [`TemplateName`], so it never clashes with a stored template.
+pub const ISSUE_TEMPLATE: &str = "@issue";
+
+#[derive(Debug)]
+pub struct TemplateName(String);
+
+impl TemplateName {
+    pub fn parse(s: String) -> Result<TemplateName, String> {
+        let is_empty = s.is_empty();
+        let is_too_long = s.len() > 64;
+        let contains_forbidden_characters = s
+            .chars()
+            .any(|c| !c.is_ascii_lowercase() && !c.is_ascii_digit() && c != '-' && c != '_');
+
+        if is_empty || is_too_long || contains_forbidden_characters {
+            Err(format!("{} is not a valid template name.", s))
+        } else {
+            Ok(Self(s))
+        }
+    }
+}
+
+impl AsRef<str> for TemplateName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+pub struct StoredTemplate {
+    pub name: String,
+    pub html_source: String,
+    pub text_source: String,
+}
+
+/// Everything a template can refer to when rendered for one recipient.
+#[derive(Serialize)]
+pub struct RenderContext {
+    pub subscriber: SubscriberContext,
+    pub unsubscribe_url: String,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberContext {
+    pub name: String,
+    pub email: String,
+    pub attributes: Value,
+}
+
+impl RenderContext {
+    /// A made-up recipient, used to check templates when they are saved and for test sends.
+    pub fn sample(email: &str, base_url: &str) -> RenderContext {
+        RenderContext {
+            subscriber: SubscriberContext {
+                name: "Test Recipient".into(),
+                email: email.into(),
+                attributes: Value::Object(Map::new()),
+            },
+            unsubscribe_url: unsubscribe_url(base_url, "test", None),
+        }
+    }
+}
+
+/// The issue, if given, is credited with the unsubscribe in its delivery status.
+pub fn unsubscribe_url(base_url: &str, unsubscribe_token: &str, issue_id: Option<Uuid>) -> String {
+    let url = format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        base_url.trim_end_matches('/'),
+        unsubscribe_token
+    );
+    match issue_id {
+        Some(issue_id) => format!("{}&issue={}", url, issue_id),
+        None => url,
+    }
+}
+
+pub struct RenderedEmail {
+    pub html: String,
+    pub text: String,
+}
+
+/// A compiled set of templates.
+#[derive(Debug)]
+pub struct EmailTemplates {
+    html: Environment<'static>,
+    text: Environment<'static>,
+}
+
+impl EmailTemplates {
+    pub fn new(templates: Vec<StoredTemplate>) -> Result<EmailTemplates, String> {
+        let mut html = Environment::new();
+        html.set_auto_escape_callback(|_| AutoEscape::Html);
+        let mut text = Environment::new();
+        text.set_auto_escape_callback(|_| AutoEscape::None);
+
+        let mut compiled = EmailTemplates { html, text };
+        for template in templates {
+            compiled.add(template)?;
+        }
+        Ok(compiled)
+    }
+
+    /// Compile a template, replacing any template of the same name.
+    pub fn add(&mut self, template: StoredTemplate) -> Result<(), String> {
+        self.html
+            .add_template_owned(template.name.clone(), template.html_source)
+            .map_err(|e| format!("The HTML source does not compile: {}", e))?;
+        self.text
+            .add_template_owned(template.name, template.text_source)
+            .map_err(|e| format!("The text source does not compile: {}", e))?;
+        Ok(())
+    }
+
+    /// Compile the content of a newsletter issue under [`ISSUE_TEMPLATE`], so it can use
+    /// the stored layouts and partials.
+    pub fn add_issue(&mut self, html_source: &str, text_source: &str) -> Result<(), String> {
+        self.add(StoredTemplate {
+            name: ISSUE_TEMPLATE.into(),
+            html_source: html_source.into(),
+            text_source: text_source.into(),
+        })
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.html.remove_template(name);
+        self.text.remove_template(name);
+    }
+
+    pub fn render(&self, name: &str, context: &RenderContext) -> Result<RenderedEmail, String> {
+        let html = self
+            .html
+            .get_template(name)
+            .and_then(|template| template.render(context))
+            .map_err(|e| format!("The HTML source fails to render: {}", e))?;
+        let text = self
+            .text
+            .get_template(name)
+            .and_then(|template| template.render(context))
+            .map_err(|e| format!("The text source fails to render: {}", e))?;
+        Ok(RenderedEmail { html, text })
+    }
+
+    /// Render a template for a sample recipient, surfacing errors such as missing layouts
+    /// or partials that compiling alone does not catch.
+    pub fn check(&self, name: &str, base_url: &str) -> Result<(), String> {
+        self.render(
+            name,
+            &RenderContext::sample("subscriber@example.com", base_url),
+        )
+        .map(|_| ())
+    }
+
+    /// The names of all stored templates.
+    pub fn names(&self) -> Vec<String> {
+        self.html
+            .templates()
+            .map(|(name, _)| name.to_owned())
+            .filter(|name| name != ISSUE_TEMPLATE)
+            .collect()
+    }
+}
+
+/// Compile every stored template.
+#[tracing::instrument(name = "Loading email templates", skip(pool))]
+pub async fn load_templates(pool: &PgPool) -> anyhow::Result<EmailTemplates> {
+    let templates = sqlx::query_as!(
+        StoredTemplate,
+        r#"SELECT name, html_source, text_source FROM email_templates"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    EmailTemplates::new(templates).map_err(anyhow::Error::msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use serde_json::json;
+
+    use crate::templates::{
+        EmailTemplates, RenderContext, StoredTemplate, SubscriberContext, TemplateName,
+    };
+
+    fn template(name: &str, html_source: &str, text_source: &str) -> StoredTemplate {
+        StoredTemplate {
+            name: name.into(),
+            html_source: html_source.into(),
+            text_source: text_source.into(),
+        }
+    }
+
+    fn context(name: &str) -> RenderContext {
+        RenderContext {
+            subscriber: SubscriberContext {
+                name: name.into(),
+                email: "ursula@example.com".into(),
+                attributes: json!({"plan": "pro"}),
+            },
+            unsubscribe_url: "https://example.com/subscriptions/unsubscribe?token=abc".into(),
+        }
+    }
+
+    #[test]
+    fn variables_and_conditionals_are_rendered_per_recipient() {
+        let templates = EmailTemplates::new(vec![template(
+            "welcome",
+            r#"<p>Hi {{ subscriber.name }}</p>{% if subscriber.attributes.plan == "pro" %}<p>Pro</p>{% endif %}"#,
+            "Hi {{ subscriber.name }}, leave at {{ unsubscribe_url }}",
+        )])
+        .unwrap();
+
+        let rendered = templates.render("welcome", &context("Ursula")).unwrap();
+
+        assert_eq!(rendered.html, "<p>Hi Ursula</p><p>Pro</p>");
+        assert_eq!(
+            rendered.text,
+            "Hi Ursula, leave at https://example.com/subscriptions/unsubscribe?token=abc"
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html_but_not_in_text() {
+        let templates = EmailTemplates::new(vec![template(
+            "welcome",
+            "<p>{{ subscriber.name }}</p>",
+            "{{ subscriber.name }}",
+        )])
+        .unwrap();
+
+        let rendered = templates
+            .render("welcome", &context("Tom & Jerry's"))
+            .unwrap();
+
+        assert_eq!(rendered.html, "<p>Tom &amp; Jerry&#x27;s</p>");
+        assert_eq!(rendered.text, "Tom & Jerry's");
+    }
+
+    #[test]
+    fn layouts_and_partials_resolve_within_the_same_format() {
+        let templates = EmailTemplates::new(vec![
+            template(
+                "layout",
+                "<main>{% block body %}{% endblock %}</main>{% include \"footer\" %}",
+                "{% block body %}{% endblock %}\n-- {% include \"footer\" %}",
+            ),
+            template("footer", "<footer>Bye</footer>", "Bye"),
+            template(
+                "issue",
+                "{% extends \"layout\" %}{% block body %}Hello{% endblock %}",
+                "{% extends \"layout\" %}{% block body %}Hello{% endblock %}",
+            ),
+        ])
+        .unwrap();
+
+        let rendered = templates.render("issue", &context("Ursula")).unwrap();
+
+        assert_eq!(rendered.html, "<main>Hello</main><footer>Bye</footer>");
+        assert_eq!(rendered.text, "Hello\n-- Bye");
+    }
+
+    #[test]
+    fn syntax_errors_are_reported_when_compiling() {
+        assert_err!(EmailTemplates::new(vec![template(
+            "broken",
+            "{% if subscriber.name %}unclosed",
+            ""
+        )]));
+        assert_err!(EmailTemplates::new(vec![template("broken", "", "{{ }}")]));
+    }
+
+    #[test]
+    fn missing_partials_are_reported_when_checking() {
+        let templates =
+            EmailTemplates::new(vec![template("issue", "{% include \"nope\" %}", "")]).unwrap();
+
+        assert_err!(templates.check("issue", "https://example.com"));
+    }
+
+    #[test]
+    fn template_names_are_lowercase_identifiers() {
+        assert_ok!(TemplateName::parse("weekly-layout_2".into()));
+        for name in ["", "Layout", "@issue", "a/b", &"a".repeat(65)] {
+            assert_err!(TemplateName::parse(name.to_string()));
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Open and click tracking for issues that opt into it.
+//!
+//! Every link in a tracked email points to a redirect on our server and a 1x1 pixel is
+//! appended to the body. Both URLs carry the issue and subscriber ids, signed with the
+//! application HMAC secret so they cannot be forged to inflate stats or to turn the
+//! redirect into an open redirect.
+
+use hmac::{Hmac, Mac};
+use lol_html::{element, html_content::ContentType, rewrite_str, RewriteStrSettings};
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use sqlx::types::Uuid;
+
+use crate::html;
+
+/// A transparent 1x1 GIF.
+pub const TRACKING_PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// Builds and verifies the signed tracking URLs of one issue sent to one subscriber.
+pub struct Tracker<'a> {
+    base_url: &'a str,
+    hmac_secret: &'a Secret<String>,
+}
+
+impl<'a> Tracker<'a> {
+    pub fn new(base_url: &'a str, hmac_secret: &'a Secret<String>) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/'),
+            hmac_secret,
+        }
+    }
+
+    pub fn open_url(&self, issue_id: Uuid, subscriber_id: Uuid) -> String {
+        let mut url = self.endpoint("open");
+        url.query_pairs_mut()
+            .append_pair("issue", &issue_id.to_string())
+            .append_pair("subscriber", &subscriber_id.to_string())
+            .append_pair(
+                "signature",
+                &self.sign(&open_message(issue_id, subscriber_id)),
+            );
+        url.into()
+    }
+
+    pub fn click_url(&self, issue_id: Uuid, subscriber_id: Uuid, target: &str) -> String {
+        let mut url = self.endpoint("click");
+        url.query_pairs_mut()
+            .append_pair("issue", &issue_id.to_string())
+            .append_pair("subscriber", &subscriber_id.to_string())
+            .append_pair("url", target)
+            .append_pair(
+                "signature",
+                &self.sign(&click_message(issue_id, subscriber_id, target)),
+            );
+        url.into()
+    }
+
+    pub fn verify_open(&self, issue_id: Uuid, subscriber_id: Uuid, signature: &str) -> bool {
+        self.verify(&open_message(issue_id, subscriber_id), signature)
+    }
+
+    pub fn verify_click(
+        &self,
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+        target: &str,
+        signature: &str,
+    ) -> bool {
+        self.verify(&click_message(issue_id, subscriber_id, target), signature)
+    }
+
+    /// Point every web link of an HTML body at the click redirect and append the open pixel.
+    pub fn add_tracking(
+        &self,
+        html: &str,
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+    ) -> anyhow::Result<String> {
+        let pixel = format!(
+            "<img src=\"{}\" width=\"1\" height=\"1\" alt=\"\" style=\"display:block;border:0;\">",
+            html::escape(&self.open_url(issue_id, subscriber_id))
+        );
+        let mut pixel_added = false;
+
+        let mut tracked = rewrite_str(
+            html,
+            RewriteStrSettings::new()
+                .append_element_content_handler(element!("a[href]", |el| {
+                    let href = unescape_attribute(&el.get_attribute("href").unwrap_or_default());
+                    // The normalized URL is signed, so the redirect never carries characters
+                    // that cannot go in a header, such as a newline pasted into the href.
+                    // Links that do not parse are left as they are.
+                    match Url::parse(&href) {
+                        Ok(url) if matches!(url.scheme(), "http" | "https") => {
+                            let click_url = self.click_url(issue_id, subscriber_id, url.as_str());
+                            el.set_attribute("href", &html::escape(&click_url))?;
+                        }
+                        _ => {}
+                    }
+                    Ok(())
+                }))
+                .append_element_content_handler(element!("body", |el| {
+                    el.append(&pixel, ContentType::Html);
+                    pixel_added = true;
+                    Ok(())
+                })),
+        )?;
+        if !pixel_added {
+            tracked.push_str(&pixel);
+        }
+        Ok(tracked)
+    }
+
+    fn endpoint(&self, kind: &str) -> Url {
+        Url::parse(&format!("{}/track/{}", self.base_url, kind))
+            .expect("The application base URL is invalid.")
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any size.")
+    }
+
+    fn sign(&self, message: &str) -> String {
+        let mut mac = self.mac();
+        mac.update(message.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    fn verify(&self, message: &str, signature: &str) -> bool {
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+        let mut mac = self.mac();
+        mac.update(message.as_bytes());
+        mac.verify_slice(&signature).is_ok()
+    }
+}
+
+/// Attribute values are read and written as they appear in the markup.
+fn unescape_attribute(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn open_message(issue_id: Uuid, subscriber_id: Uuid) -> String {
+    format!("open:{}:{}", issue_id, subscriber_id)
+}
+
+fn click_message(issue_id: Uuid, subscriber_id: Uuid, target: &str) -> String {
+    format!("click:{}:{}:{}", issue_id, subscriber_id, target)
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+    use secrecy::Secret;
+    use sqlx::types::Uuid;
+
+    use crate::tracking::Tracker;
+
+    fn query_value(url: &str, key: &str) -> String {
+        Url::parse(url)
+            .unwrap()
+            .query_pairs()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.into_owned())
+            .unwrap()
+    }
+
+    #[test]
+    fn signed_urls_verify_only_for_the_data_they_were_issued_for() {
+        let secret = Secret::new("secret".to_owned());
+        let tracker = Tracker::new("https://news.example.com/", &secret);
+        let (issue, subscriber) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let click = tracker.click_url(issue, subscriber, "https://example.com/a?b=c");
+        let signature = query_value(&click, "signature");
+
+        assert!(click.starts_with("https://news.example.com/track/click?"));
+        assert_eq!(query_value(&click, "url"), "https://example.com/a?b=c");
+        assert!(tracker.verify_click(issue, subscriber, "https://example.com/a?b=c", &signature));
+        assert!(!tracker.verify_click(issue, subscriber, "https://evil.example", &signature));
+        assert!(!tracker.verify_click(
+            issue,
+            Uuid::new_v4(),
+            "https://example.com/a?b=c",
+            &signature
+        ));
+        assert!(!tracker.verify_open(issue, subscriber, &signature));
+        assert!(!tracker.verify_open(issue, subscriber, "not hex"));
+    }
+
+    #[test]
+    fn signatures_depend_on_the_secret() {
+        let (issue, subscriber) = (Uuid::new_v4(), Uuid::new_v4());
+        let first_secret = Secret::new("first".to_owned());
+        let second_secret = Secret::new("second".to_owned());
+        let open =
+            Tracker::new("https://news.example.com", &first_secret).open_url(issue, subscriber);
+
+        let tracker = Tracker::new("https://news.example.com", &second_secret);
+
+        assert!(!tracker.verify_open(issue, subscriber, &query_value(&open, "signature")));
+    }
+
+    #[test]
+    fn web_links_are_rewritten_and_a_pixel_is_appended() {
+        let secret = Secret::new("secret".to_owned());
+        let tracker = Tracker::new("https://news.example.com", &secret);
+        let html = "<html><body><a href=\"https://example.com/post?a=1&amp;b=2\">Post</a>\
+                    <a href=\"mailto:hi@example.com\">Mail</a></body></html>";
+
+        let tracked = tracker
+            .add_tracking(html, Uuid::new_v4(), Uuid::new_v4())
+            .unwrap();
+
+        let click_url = tracked
+            .split("href=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap()
+            .replace("&amp;", "&");
+        assert!(click_url.starts_with("https://news.example.com/track/click?"));
+        assert_eq!(
+            query_value(&click_url, "url"),
+            "https://example.com/post?a=1&b=2"
+        );
+        assert!(tracked.contains("href=\"mailto:hi@example.com\""));
+        assert!(tracked.contains("<img src=\"https://news.example.com/track/open?"));
+        assert!(tracked.ends_with("></body></html>"));
+    }
+
+    #[test]
+    fn links_are_signed_in_their_normalized_form() {
+        let secret = Secret::new("secret".to_owned());
+        let tracker = Tracker::new("https://news.example.com", &secret);
+        let html = "<a href=\"https://example.com/po\nst\">Post</a>";
+
+        let tracked = tracker
+            .add_tracking(html, Uuid::new_v4(), Uuid::new_v4())
+            .unwrap();
+
+        let click_url = tracked
+            .split("href=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap()
+            .replace("&amp;", "&");
+        assert_eq!(query_value(&click_url, "url"), "https://example.com/post");
+    }
+}
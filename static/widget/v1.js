@@ -0,0 +1,133 @@
+/*
+ * Newsletter signup widget, version 1.
+ *
+ * Usage:
+ *   <div id="newsletter-signup"></div>
+ *   <script src="https://news.example.com/widget/v1.js" data-target="#newsletter-signup" async></script>
+ *
+ * Without `data-target`, the form is inserted right after the script tag. The page's origin
+ * must be listed in `application.allowed_origins` for the signup request to be accepted.
+ */
+(function () {
+  "use strict";
+
+  var script = document.currentScript;
+  if (!script) {
+    return;
+  }
+  var baseUrl = new URL(script.src).origin;
+
+  function element(tag, attributes, text) {
+    var el = document.createElement(tag);
+    Object.keys(attributes || {}).forEach(function (name) {
+      el.setAttribute(name, attributes[name]);
+    });
+    if (text) {
+      el.textContent = text;
+    }
+    return el;
+  }
+
+  function loadCaptcha(form, siteKey) {
+    var script = element("script", {
+      "src": "https://challenges.cloudflare.com/turnstile/v0/api.js",
+      "async": "",
+      "defer": ""
+    });
+    document.head.appendChild(script);
+    form.appendChild(element("div", { "class": "cf-turnstile", "data-sitekey": siteKey }));
+  }
+
+  function render(config, token) {
+    var container = element("div", { "class": "newsletter-widget" });
+    var form = element("form", { "novalidate": "" });
+    var heading = element("p", { "class": "newsletter-widget-heading" }, "Subscribe to " + config.name);
+    var name = element("input", { "type": "text", "name": "name", "placeholder": "Name", "required": "" });
+    var email = element("input", { "type": "email", "name": "email", "placeholder": "Email", "required": "" });
+    // Hidden from people; bots fill it in.
+    var honeypot = element("input", { "type": "text", "name": "website", "tabindex": "-1", "autocomplete": "off", "aria-hidden": "true" });
+    var button = element("button", { "type": "submit" }, "Subscribe");
+    var message = element("p", { "class": "newsletter-widget-message", "role": "status" });
+    button.style.background = config.accent_color;
+    button.style.color = "#ffffff";
+    button.style.border = "none";
+    button.style.padding = "8px 16px";
+    button.style.cursor = "pointer";
+    honeypot.style.position = "absolute";
+    honeypot.style.left = "-10000px";
+
+    form.appendChild(heading);
+    form.appendChild(name);
+    form.appendChild(email);
+    form.appendChild(honeypot);
+    if (token.captcha_site_key) {
+      loadCaptcha(form, token.captcha_site_key);
+    }
+    form.appendChild(button);
+    container.appendChild(form);
+    container.appendChild(message);
+
+    form.addEventListener("submit", function (event) {
+      event.preventDefault();
+      button.disabled = true;
+      message.textContent = "";
+      var timezone = Intl.DateTimeFormat().resolvedOptions().timeZone;
+      var captcha = form.querySelector("[name='cf-turnstile-response']");
+      fetch(config.subscribe_url, {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({
+          name: name.value.trim(),
+          email: email.value.trim(),
+          timezone: timezone || undefined,
+          website: honeypot.value || undefined,
+          form_token: token.form_token,
+          captcha_response: captcha ? captcha.value : undefined
+        })
+      })
+        .then(function (response) {
+          if (response.ok) {
+            form.style.display = "none";
+            message.textContent = "Thanks for subscribing! Our next issue will land in your inbox.";
+          } else if (response.status === 400 || response.status === 422) {
+            message.textContent = "Please check your details and try again.";
+          } else if (response.status === 409) {
+            form.style.display = "none";
+            message.textContent = "You're already subscribed. Thanks for sticking with us!";
+          } else if (response.status === 429) {
+            message.textContent = "Too many attempts. Please try again later.";
+          } else {
+            message.textContent = "Something went wrong on our side. Please try again in a moment.";
+          }
+        })
+        .catch(function () {
+          message.textContent = "Something went wrong on our side. Please try again in a moment.";
+        })
+        .then(function () {
+          button.disabled = false;
+        });
+    });
+
+    var target = script.getAttribute("data-target");
+    var parent = target && document.querySelector(target);
+    if (parent) {
+      parent.appendChild(container);
+    } else {
+      script.parentNode.insertBefore(container, script.nextSibling);
+    }
+  }
+
+  function getJson(path) {
+    return fetch(baseUrl + path).then(function (response) {
+      return response.json();
+    });
+  }
+
+  // The form token is fetched when the form is shown, so the time it takes to fill in
+  // the form can be checked.
+  Promise.all([getJson("/widget/v1/config"), getJson("/subscriptions/form-token")])
+    .then(function (results) {
+      render(results[0], results[1]);
+    })
+    .catch(function () {});
+})();
@@ -1,23 +1,36 @@
-use std::{
-    future::IntoFuture,
-    net::{Ipv4Addr, SocketAddr},
-};
+mod helpers;
+
+use std::{sync::Arc, time::Duration};
 
-use anyhow::Context;
+use helpers::{spawn_app, spawn_app_with, TestApp, ALLOWED_ORIGIN};
 use newsletter_deliverer::{
-    configuration::{get_configuration, DatabaseSettings},
-    run,
+    configuration::{
+        CaptchaSettings, EmailProviderSettings, LogFormat, SendThrottleSettings, TelemetrySettings,
+    },
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    issue_delivery_worker::{try_execute_task, ExecutionOutcome},
+    scheduler::enqueue_due_issues,
+    send_throttle::SendThrottle,
+    startup::Application,
+    telemetry::{init_tracer_provider, otel_layer},
+    tracking::Tracker,
 };
+use opentelemetry::trace::TraceId;
+use opentelemetry_sdk::{
+    export::trace::SpanData, testing::trace::InMemorySpanExporter, trace::TracerProvider,
+};
+use secrecy::{ExposeSecret, Secret};
 use serde_json::json;
-use sqlx::{Connection, PgConnection, PgPool};
 use test_case::test_case;
-use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::subscriber::DefaultGuard;
+use tracing_subscriber::layer::SubscriberExt;
 use uuid::Uuid;
-
-pub struct TestApp {
-    pub address: String,
-    pub db_pool: PgPool,
-}
+use wiremock::{
+    matchers::{body_partial_json, body_string_contains, header, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
 
 #[tokio::test]
 async fn health_check_works() {
@@ -27,7 +40,7 @@ async fn health_check_works() {
 
     // Act
     let response = client
-        .get(&format!("{}/health_check", &app.address))
+        .get(format!("{}/health_check", &app.address))
         .send()
         .await
         .expect("Failed to execute request");
@@ -38,15 +51,191 @@ async fn health_check_works() {
 }
 
 #[tokio::test]
-async fn subscribe_returns_a_200_for_valid_data() {
+async fn liveness_does_not_check_dependencies() {
+    // Arrange
+    let app = spawn_app_with(|configuration| {
+        configuration.application.health.require_redis = true;
+    })
+    .await
+    .unwrap();
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/health/live", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(Some(0), response.content_length());
+}
+
+#[tokio::test]
+async fn readiness_reports_each_component_and_ignores_optional_ones() {
+    // Arrange
+    let app = spawn_app_with(|configuration| {
+        configuration.application.health.check_email_provider = true;
+    })
+    .await
+    .unwrap();
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["ready"], true);
+    for component in ["database", "migrations", "email_provider"] {
+        assert_eq!(body["components"][component]["up"], true, "{}", component);
+        assert_eq!(body["components"][component]["required"], true);
+        assert!(body["components"][component]["latency_ms"].is_number());
+    }
+    // Tests run without Redis.
+    assert_eq!(body["components"]["redis"]["up"], false);
+    assert_eq!(body["components"]["redis"]["required"], false);
+    assert!(body["components"]["redis"]["error"].is_string());
+}
+
+#[tokio::test]
+async fn readiness_returns_a_503_when_a_required_component_is_down() {
+    // Arrange
+    let app = spawn_app_with(|configuration| {
+        configuration.application.health.require_redis = true;
+    })
+    .await
+    .unwrap();
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(503, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["ready"], false);
+    assert_eq!(body["components"]["database"]["up"], true);
+    assert_eq!(body["components"]["redis"]["required"], true);
+}
+
+#[tokio::test]
+async fn metrics_are_exposed_in_the_prometheus_format() {
     // Arrange
     let app = spawn_app().await.unwrap();
-    let configuration = get_configuration().expect("Failed to read configuration");
-    let connection_string = configuration.database.connection_string();
-    let mut connection = PgConnection::connect(&connection_string)
+    let client = reqwest::Client::new();
+    create_subscriber(&app, "alice@example.com").await;
+    let duplicate = app
+        .post_subscriptions(&json!({"name": "Alice", "email": "alice@example.com"}))
+        .await;
+    assert_eq!(409, duplicate.status().as_u16());
+
+    // Act
+    let response = client
+        .get(format!("{}/metrics", &app.address))
+        .send()
         .await
-        .expect("Failed to connect to Postgres.");
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    for expected in [
+        "subscriptions_total{outcome=\"accepted\"}",
+        "subscriptions_total{outcome=\"duplicate\"}",
+        "issue_delivery_queue_depth ",
+        "issue_delivery_queue_oldest_task_age_seconds ",
+        "db_pool_connections{state=\"in_use\"}",
+        "email_provider_circuit_state{provider=\"primary\"} 0",
+    ] {
+        assert!(
+            body.contains(expected),
+            "Missing {} in:\n{}",
+            expected,
+            body
+        );
+    }
+    assert!(body
+        .lines()
+        .any(|line| line.starts_with("http_requests_total{")
+            && line.contains("route=\"/subscriptions\"")
+            && line.contains("status=\"200\"")));
+    assert!(body.contains("http_request_duration_seconds_bucket{"));
+}
+
+#[tokio::test]
+async fn test_sends_fail_over_and_health_reports_the_open_circuit() {
+    // Arrange
+    let failover_server = MockServer::start().await;
+    let failover_uri = failover_server.uri();
+    let app = spawn_app_with(|configuration| {
+        configuration.email_client.circuit_breaker.minimum_requests = 1;
+        configuration.email_client.failover = Some(EmailProviderSettings {
+            base_url: failover_uri,
+            authorization_token: Secret::new("failover-token".into()),
+        });
+    })
+    .await
+    .unwrap();
     let client = reqwest::Client::new();
+    let draft = create_draft(
+        &app,
+        json!({"title": "Failover", "text_content": "Hi", "html_content": "<p>Hi</p>"}),
+    )
+    .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(header("X-Postmark-Server-Token", "failover-token"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&failover_server)
+        .await;
+
+    // Act
+    let test_send = client
+        .post(format!(
+            "{}/admin/newsletters/{}/test",
+            &app.address,
+            draft["id"].as_str().unwrap()
+        ))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .json(&json!({"recipients": ["editor@example.com", "reviewer@example.com"]}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let health = client
+        .get(format!("{}/health/email", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, test_send.status().as_u16());
+    assert_eq!(200, health.status().as_u16());
+    assert_eq!(
+        health.json::<serde_json::Value>().await.unwrap(),
+        json!({"providers": [
+            {"name": "primary", "state": "open"},
+            {"name": "failover", "state": "closed"}
+        ]})
+    );
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_200_for_valid_data() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
 
     // Act
     let body = json!({
@@ -54,14 +243,9 @@ async fn subscribe_returns_a_200_for_valid_data() {
         "email": "sergo777ser777@gmail.com"
     });
 
-    let response = client
-        .post(&format!("{}/subscriptions", &app.address))
-        .json(&body)
-        .send()
-        .await
-        .expect("Failed to execute request.");
+    let response = app.post_subscriptions(&body).await;
     let saved = sqlx::query!("SELECT email, name FROM subscriptions",)
-        .fetch_one(&mut connection)
+        .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fetch saved subscription.");
 
@@ -81,15 +265,9 @@ async fn subscribe_returns_a_400_when_data_is_missing(
 ) {
     // Arrange
     let app = spawn_app().await.unwrap();
-    let client = reqwest::Client::new();
 
     // Act
-    let response = client
-        .post(&format!("{}/subscriptions", &app.address))
-        .json(&invalid_body)
-        .send()
-        .await
-        .expect("Failed to execute request.");
+    let response = app.post_subscriptions(&invalid_body).await;
 
     // Assert
     assert_eq!(
@@ -110,15 +288,9 @@ async fn subscribe_returns_a_400_when_field_are_present_but_empty(
 ) {
     // Arrange
     let app = spawn_app().await.unwrap();
-    let client = reqwest::Client::new();
 
     // Act
-    let response = client
-        .post(&format!("{}/subscriptions", &app.address))
-        .json(&body)
-        .send()
-        .await
-        .expect("Failed to execute request.");
+    let response = app.post_subscriptions(&body).await;
 
     // Assert
     assert_eq!(
@@ -129,55 +301,2543 @@ async fn subscribe_returns_a_400_when_field_are_present_but_empty(
     );
 }
 
-/// Spin up an instance of application
-/// and returns its address (i.e. http://localhost:XXXX)
-async fn spawn_app() -> anyhow::Result<TestApp> {
-    let mut configuration = get_configuration().expect("Failed to read configuration.");
-    configuration.database.database_name = Uuid::new_v4().to_string();
+#[tokio::test]
+async fn subscribe_accepts_a_form_post_and_redirects_to_the_hosted_page() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let token = fetch_form_token(&app).await;
+
+    // Act
+    let response = client
+        .post(format!("{}/subscriptions", &app.address))
+        .form(&[
+            ("name", "Ursula Le Guin"),
+            ("email", "ursula@example.com"),
+            ("timezone", "Europe/Berlin"),
+            ("form_token", &token),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(303, response.status().as_u16());
+    let location = response.headers()["location"].to_str().unwrap();
+    assert_eq!(
+        location,
+        format!("{}/subscribe/check-your-inbox", app.address)
+    );
+    let page = client.get(location).send().await.unwrap();
+    assert_eq!(200, page.status().as_u16());
+    assert!(page.text().await.unwrap().contains("Check your inbox"));
+    let saved = sqlx::query!("SELECT name, timezone FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Ursula Le Guin");
+    assert_eq!(saved.timezone.as_deref(), Some("Europe/Berlin"));
+}
+
+#[test_case(&[("name", ""), ("email", "ursula@example.com")], "Please enter your name."; "an empty name")]
+#[test_case(&[("name", "Ursula")], "Please enter a valid email address."; "a missing email")]
+#[test_case(&[("name", "Ursula"), ("email", "not-an-email")], "Please enter a valid email address."; "an invalid email")]
+#[tokio::test]
+async fn an_invalid_form_post_redirects_back_with_a_friendly_message(
+    form: &[(&str, &str)],
+    message: &str,
+) {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let token = fetch_form_token(&app).await;
+    let mut form = form.to_vec();
+    form.push(("form_token", &token));
+
+    // Act
+    let response = client
+        .post(format!("{}/subscriptions", &app.address))
+        .form(&form)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(303, response.status().as_u16());
+    let location = response.headers()["location"].to_str().unwrap();
+    assert!(location.starts_with(&format!("{}/subscribe?error=", app.address)));
+    let page = client
+        .get(location)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains(&format!("<p class=\"error\">{}</p>", message)));
+    assert!(page.contains("<form method=\"post\" action=\"/subscriptions\">"));
+    let saved = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 0);
+}
+
+#[tokio::test]
+async fn the_signup_page_escapes_values_echoed_back() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+
+    // Act
+    let response = reqwest::get(&format!(
+        "{}/subscribe?error=Oops&name=%3Cscript%3Ealert(1)%3C%2Fscript%3E",
+        &app.address
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let page = response.text().await.unwrap();
+    assert!(page.contains("<title>Subscribe - Newsletter</title>"));
+    assert!(page.contains("value=\"&lt;script&gt;alert(1)&lt;/script&gt;\""));
+    assert!(!page.contains("<script>alert(1)"));
+}
+
+#[tokio::test]
+async fn the_widget_script_and_its_config_are_served() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let client = reqwest::Client::new();
+
+    // Act
+    let script = client
+        .get(format!("{}/widget/v1.js", &app.address))
+        .send()
+        .await
+        .unwrap();
+    let config = client
+        .get(format!("{}/widget/v1/config", &app.address))
+        .header("Origin", ALLOWED_ORIGIN)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(200, script.status().as_u16());
+    assert!(script.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/javascript"));
+    assert!(script.text().await.unwrap().contains("/widget/v1/config"));
+    assert_eq!(200, config.status().as_u16());
+    assert_eq!(
+        config.headers()["access-control-allow-origin"],
+        ALLOWED_ORIGIN
+    );
+    let config: serde_json::Value = config.json().await.unwrap();
+    assert_eq!(
+        config,
+        json!({
+            "name": "Newsletter",
+            "accent_color": "#1a73e8",
+            "subscribe_url": format!("{}/subscriptions", app.address)
+        })
+    );
+}
+
+#[test_case(409, "You're already subscribed."; "a returning subscriber")]
+#[test_case(429, "Too many attempts."; "a visitor over the rate limit")]
+#[tokio::test]
+async fn the_widget_explains_signups_it_cannot_accept(status: u16, message: &str) {
+    // Arrange
+    let app = spawn_app_with(|configuration| {
+        configuration.application.rate_limit.per_ip.requests = 2;
+    })
+    .await
+    .unwrap();
+    let client = reqwest::Client::new();
+    let signup = |email: &str| {
+        client
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Origin", ALLOWED_ORIGIN)
+            .json(&json!({"name": "Ursula", "email": email}))
+            .send()
+    };
+    assert_eq!(200, signup("ursula@example.com").await.unwrap().status());
+
+    // Act
+    let response = match status {
+        409 => signup("ursula@example.com").await.unwrap(),
+        _ => {
+            signup("first@example.com").await.unwrap();
+            signup("second@example.com").await.unwrap()
+        }
+    };
+    let script = client
+        .get(format!("{}/widget/v1.js", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(status, response.status().as_u16());
+    let branch = script
+        .split(&format!("response.status === {}", status))
+        .nth(1)
+        .expect("The widget does not handle the status.");
+    let handler = branch.split("} else").next().unwrap();
+    assert!(handler.contains(message), "{}", handler);
+}
+
+#[test_case(ALLOWED_ORIGIN, true; "an allowed origin")]
+#[test_case("https://evil.example", false; "any other origin")]
+#[tokio::test]
+async fn cross_origin_signups_are_only_allowed_from_listed_origins(origin: &str, allowed: bool) {
+    // Arrange
+    let app = spawn_app().await.unwrap();
 
-    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
-    let listener = TcpListener::bind(addr)
+    // Act
+    let response = reqwest::Client::new()
+        .request(
+            reqwest::Method::OPTIONS,
+            format!("{}/subscriptions", &app.address),
+        )
+        .header("Origin", origin)
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .send()
         .await
-        .expect("Failed to bind to addr");
-    let port = listener.local_addr().unwrap().port();
-    let address = format!("http://127.0.0.1:{}", port);
+        .unwrap();
+
+    // Assert
+    let allow_origin = response
+        .headers()
+        .get("access-control-allow-origin")
+        .map(|value| value.to_str().unwrap().to_owned());
+    assert_eq!(allow_origin, allowed.then(|| origin.to_owned()));
+}
+
+#[tokio::test]
+async fn a_signup_filling_in_the_honeypot_is_dropped_silently() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
 
-    let connection_pool = configure_database(&configuration.database).await.unwrap();
+    // Act
+    let response = app
+        .post_subscriptions(&json!({
+            "name": "Bot",
+            "email": "victim@example.com",
+            "website": "https://spam.example"
+        }))
+        .await;
 
-    let server = run(listener, connection_pool.clone())
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
         .await
-        .context("Failed to get server")?;
+        .unwrap();
+    assert_eq!(saved.count, 0);
+}
+
+async fn fetch_form_token(app: &TestApp) -> String {
+    let body: serde_json::Value =
+        reqwest::get(&format!("{}/subscriptions/form-token", &app.address))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+    body["form_token"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn signups_without_a_valid_form_token_are_rejected_when_tokens_are_required() {
+    // Arrange
+    let app = spawn_app_with(|configuration| {
+        configuration
+            .application
+            .bot_protection
+            .require_form_token_for_json = true;
+        configuration.application.bot_protection.min_fill_seconds = 60;
+    })
+    .await
+    .unwrap();
+    let token = fetch_form_token(&app).await;
+    let (timestamp, signature) = token.split_once('.').unwrap();
+    let backdated = format!("{}.{}", timestamp.parse::<i64>().unwrap() - 3600, signature);
+
+    for (form_token, description) in [
+        (None, "no token"),
+        (Some(token.clone()), "a token issued too recently"),
+        (Some(backdated), "a tampered token"),
+    ] {
+        // Act
+        let response = app
+            .post_subscriptions(&json!({
+                "name": "Ursula",
+                "email": "ursula@example.com",
+                "form_token": form_token
+            }))
+            .await;
 
-    let _ = tokio::spawn(server.into_future());
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject a signup with {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_signup_with_a_valid_form_token_is_accepted() {
+    // Arrange
+    let app = spawn_app_with(|configuration| {
+        configuration
+            .application
+            .bot_protection
+            .require_form_token_for_json = true;
+        configuration.application.bot_protection.min_fill_seconds = 0;
+    })
+    .await
+    .unwrap();
+    let token = fetch_form_token(&app).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(
+            &json!({"name": "Ursula", "email": "ursula@example.com", "form_token": token}),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
 
-    Ok(TestApp {
-        address,
-        db_pool: connection_pool,
+#[tokio::test]
+async fn a_form_token_cannot_be_replayed_for_another_signup() {
+    // Arrange
+    let app = spawn_app_with(|configuration| {
+        configuration.application.bot_protection.min_fill_seconds = 0;
     })
+    .await
+    .unwrap();
+    let token = fetch_form_token(&app).await;
+    let first = app
+        .post_subscriptions(
+            &json!({"name": "Ursula", "email": "ursula@example.com", "form_token": token}),
+        )
+        .await;
+    assert_eq!(200, first.status().as_u16());
+
+    // Act
+    let replay = app
+        .post_subscriptions(
+            &json!({"name": "Bot", "email": "bot@example.com", "form_token": token}),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(400, replay.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_form_post_without_a_token_is_rejected_by_default() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    // Act
+    let response = client
+        .post(format!("{}/subscriptions", &app.address))
+        .form(&[("name", "Ursula"), ("email", "ursula@example.com")])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(303, response.status().as_u16());
+    let location = response.headers()["location"].to_str().unwrap();
+    assert!(location.starts_with(&format!("{}/subscribe?error=", app.address)));
+    let saved = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 0);
 }
 
-pub async fn configure_database(config: &DatabaseSettings) -> anyhow::Result<PgPool> {
-    // Create database
-    let mut connection = PgConnection::connect_with(&config.without_db())
+#[tokio::test]
+async fn a_form_post_submitted_too_quickly_redirects_back_with_a_friendly_message() {
+    // Arrange
+    let app = spawn_app_with(|configuration| {
+        configuration.application.bot_protection.min_fill_seconds = 60;
+    })
+    .await
+    .unwrap();
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let page = client
+        .get(format!("{}/subscribe", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
         .await
-        .expect("Failed to connect to Postgres");
+        .unwrap();
+    let token = page
+        .split("name=\"form_token\" value=\"")
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .unwrap();
 
-    // Create the new database
-    let db_name = &config.database_name;
-    let create_db_query = format!(r#"CREATE DATABASE "{}""#, db_name);
-    sqlx::query(&create_db_query)
-        .execute(&mut connection)
+    // Act
+    let response = client
+        .post(format!("{}/subscriptions", &app.address))
+        .form(&[
+            ("name", "Ursula"),
+            ("email", "ursula@example.com"),
+            ("website", ""),
+            ("form_token", token),
+        ])
+        .send()
         .await
-        .context("Failed to create database")?;
+        .expect("Failed to execute request.");
 
-    // Migrate database
-    let connection_pool = PgPool::connect_with(config.with_db())
+    // Assert
+    assert_eq!(303, response.status().as_u16());
+    let location = response.headers()["location"].to_str().unwrap();
+    let page = client
+        .get(location)
+        .send()
         .await
-        .expect("Failed to connect to Postgres.");
-    sqlx::migrate!("./migrations")
-        .run(&connection_pool)
+        .unwrap()
+        .text()
         .await
-        .expect("Failed to migrate the database");
+        .unwrap();
+    assert!(page.contains("That was quick!"));
+}
+
+#[test_case("human", 200; "a solved captcha")]
+#[test_case("bot", 400; "a failed captcha")]
+#[tokio::test]
+async fn signups_are_checked_against_the_captcha_provider(captcha_response: &str, expected: u16) {
+    // Arrange
+    let captcha_server = MockServer::start().await;
+    Mock::given(path("/siteverify"))
+        .and(body_string_contains("response=human"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"success": true})))
+        .with_priority(1)
+        .mount(&captcha_server)
+        .await;
+    Mock::given(path("/siteverify"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"success": false})))
+        .mount(&captcha_server)
+        .await;
+    let verify_url = format!("{}/siteverify", captcha_server.uri());
+    let app = spawn_app_with(|configuration| {
+        configuration.application.bot_protection.captcha = Some(CaptchaSettings {
+            site_key: "site-key".into(),
+            secret_key: Secret::new("secret-key".into()),
+            verify_url,
+        });
+    })
+    .await
+    .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions(&json!({
+            "name": "Ursula",
+            "email": "ursula@example.com",
+            "captcha_response": captcha_response
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(expected, response.status().as_u16());
+    let verification = &captcha_server.received_requests().await.unwrap()[0];
+    assert!(String::from_utf8_lossy(&verification.body).contains("secret=secret-key"));
+}
+
+async fn post_signup(app: &TestApp, email: &str, forwarded_for: Option<&str>) -> reqwest::Response {
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .json(&json!({"name": "Ursula", "email": email}));
+    if let Some(forwarded_for) = forwarded_for {
+        request = request.header("X-Forwarded-For", forwarded_for);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn signups_are_rate_limited_per_client_ip() {
+    // Arrange
+    let app = spawn_app_with(|configuration| {
+        configuration.application.rate_limit.per_ip.requests = 2;
+    })
+    .await
+    .unwrap();
+
+    // Act
+    let first = post_signup(&app, "first@example.com", None).await;
+    let second = post_signup(&app, "second@example.com", None).await;
+    let third = post_signup(&app, "third@example.com", None).await;
 
-    Ok(connection_pool)
+    // Assert
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(200, second.status().as_u16());
+    assert_eq!(429, third.status().as_u16());
+    let retry_after: u64 = third.headers()["retry-after"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=60).contains(&retry_after));
+}
+
+#[tokio::test]
+async fn signups_are_rate_limited_per_target_email() {
+    // Arrange
+    let app = spawn_app_with(|configuration| {
+        configuration.application.rate_limit.per_email.requests = 1;
+    })
+    .await
+    .unwrap();
+
+    // Act
+    let first = post_signup(&app, "ursula@example.com", None).await;
+    let same_address = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .form(&[("name", "Ursula"), ("email", "Ursula@Example.com")])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let other_address = post_signup(&app, "other@example.com", None).await;
+
+    // Assert
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(429, same_address.status().as_u16());
+    assert_eq!(200, other_address.status().as_u16());
+}
+
+#[tokio::test]
+async fn clients_behind_a_trusted_proxy_are_limited_separately() {
+    // Arrange
+    let app = spawn_app_with(|configuration| {
+        configuration.application.rate_limit.per_ip.requests = 1;
+        configuration.application.rate_limit.trusted_proxies = vec!["127.0.0.0/8".parse().unwrap()];
+    })
+    .await
+    .unwrap();
+
+    // Act
+    let first = post_signup(&app, "a@example.com", Some("198.51.100.1")).await;
+    let other_client = post_signup(&app, "b@example.com", Some("198.51.100.2")).await;
+    let spoofed_hop = post_signup(&app, "c@example.com", Some("203.0.113.9, 198.51.100.1")).await;
+
+    // Assert
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(200, other_client.status().as_u16());
+    assert_eq!(429, spoofed_hop.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_persists_public_attributes_and_tags() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    seed_attribute_schema(&app).await;
+
+    // Act
+    let body = json!({
+        "name": "Sergey Nekhoroshev",
+        "email": "sergo777ser777@gmail.com",
+        "attributes": {"source": "conference-2026"},
+        "tags": ["Beta"]
+    });
+    let response = app.post_subscriptions(&body).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!(
+        r#"
+        SELECT s.attributes, t.name AS tag
+        FROM subscriptions s
+        JOIN subscription_tags st ON st.subscriber_id = s.id
+        JOIN tags t ON t.id = st.tag_id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.attributes, json!({"source": "conference-2026"}));
+    assert_eq!(saved.tag, "beta");
+}
+
+#[test_case(json!({"plan": "pro"}), json!([]), "a private attribute")]
+#[test_case(json!({"source": 2026}), json!([]), "an attribute of the wrong type")]
+#[test_case(json!({"favourite_colour": "green"}), json!([]), "an unknown attribute")]
+#[test_case(json!({}), json!(["vip"]), "a private tag")]
+#[test_case(json!({}), json!(["does-not-exist"]), "an unknown tag")]
+#[tokio::test]
+async fn subscribe_returns_a_400_for_attributes_or_tags_not_settable_publicly(
+    attributes: serde_json::Value,
+    tags: serde_json::Value,
+    description: &str,
+) {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    seed_attribute_schema(&app).await;
+
+    // Act
+    let body = json!({
+        "name": "Sergey",
+        "email": "sergo777ser777@gmail.com",
+        "attributes": attributes,
+        "tags": tags
+    });
+    let response = app.post_subscriptions(&body).await;
+
+    // Assert
+    assert_eq!(
+        400,
+        response.status().as_u16(),
+        "The API did not fail with 400 Bad Request when the payload had {}.",
+        description
+    );
+}
+
+#[test_case(None, "no token")]
+#[test_case(Some("Token wrong-token"), "a wrong token")]
+#[tokio::test]
+async fn admin_api_rejects_requests_without_a_valid_token(
+    authorization: Option<&str>,
+    description: &str,
+) {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let client = reqwest::Client::new();
+
+    // Act
+    let mut request = client.get(format!("{}/admin/attributes", &app.address));
+    if let Some(authorization) = authorization {
+        request = request.header("Authorization", authorization);
+    }
+    let response = request.send().await.expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(
+        401,
+        response.status().as_u16(),
+        "The admin API did not fail with 401 Unauthorized when the request had {}.",
+        description
+    );
+}
+
+#[tokio::test]
+async fn admin_can_set_any_declared_attribute_and_tag() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    seed_attribute_schema(&app).await;
+    let client = reqwest::Client::new();
+    app.post_subscriptions(&json!({"name": "Sergey", "email": "sergo777ser777@gmail.com"}))
+        .await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let subscriber_url = format!("{}/admin/subscribers/{}", &app.address, subscriber_id);
+    let authorization = format!("Token {}", app.admin_token);
+
+    // Act
+    let attributes_response = client
+        .put(format!("{}/attributes", subscriber_url))
+        .header("Authorization", &authorization)
+        .json(&json!({"plan": "pro", "source": "conference-2026"}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let tags_response = client
+        .put(format!("{}/tags", subscriber_url))
+        .header("Authorization", &authorization)
+        .json(&json!({"tags": ["vip", "brand-new"]}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let subscriber: serde_json::Value = client
+        .get(&subscriber_url)
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(200, attributes_response.status().as_u16());
+    assert_eq!(200, tags_response.status().as_u16());
+    assert_eq!(
+        subscriber["attributes"],
+        json!({"plan": "pro", "source": "conference-2026"})
+    );
+    assert_eq!(subscriber["tags"], json!(["brand-new", "vip"]));
+}
+
+#[tokio::test]
+async fn admin_attributes_are_validated_against_the_schema() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    seed_attribute_schema(&app).await;
+    let client = reqwest::Client::new();
+    app.post_subscriptions(&json!({"name": "Sergey", "email": "sergo777ser777@gmail.com"}))
+        .await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    // Act
+    let response = client
+        .put(format!(
+            "{}/admin/subscribers/{}/attributes",
+            &app.address, subscriber_id
+        ))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .json(&json!({"plan": 42}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn segment_preview_counts_matching_subscribers() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    seed_attribute_schema(&app).await;
+    let client = reqwest::Client::new();
+    let authorization = format!("Token {}", app.admin_token);
+    for (name, email, tags) in [
+        ("Alice", "alice@example.com", json!(["beta"])),
+        ("Bob", "bob@example.com", json!(["beta"])),
+        ("Carol", "carol@example.com", json!([])),
+    ] {
+        app.post_subscriptions(&json!({"name": name, "email": email, "tags": tags}))
+            .await;
+    }
+    sqlx::query!("UPDATE subscriptions SET attributes = '{\"plan\": \"pro\"}' WHERE name = 'Bob'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let saved = client
+        .put(format!("{}/admin/segments/beta-pro", &app.address))
+        .header("Authorization", &authorization)
+        .json(&json!({"expression": "confirmed AND tag:beta AND attr.plan = \"pro\""}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let saved_preview: serde_json::Value = client
+        .get(format!("{}/admin/segments/beta-pro/preview", &app.address))
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+    let adhoc_preview: serde_json::Value = client
+        .post(format!("{}/admin/segments/preview", &app.address))
+        .header("Authorization", &authorization)
+        .json(&json!({"expression": "tag:beta OR subscribed_at > 2026-01-01"}))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(200, saved.status().as_u16());
+    assert_eq!(saved_preview["matching_subscribers"], 1);
+    assert_eq!(adhoc_preview["matching_subscribers"], 3);
+}
+
+#[test_case("confirmed AND", "an incomplete expression")]
+#[test_case("attr.undeclared = 1", "an undeclared attribute")]
+#[test_case("subscribed_at > \"yesterday\"", "a value of the wrong type")]
+#[tokio::test]
+async fn saving_an_invalid_segment_returns_a_400(expression: &str, description: &str) {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let client = reqwest::Client::new();
+
+    // Act
+    let response = client
+        .put(format!("{}/admin/segments/broken", &app.address))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .json(&json!({ "expression": expression }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(
+        400,
+        response.status().as_u16(),
+        "The API did not fail with 400 Bad Request when the segment had {}.",
+        description
+    );
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_enqueued_exactly_once_when_due() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    create_subscriber(&app, "alice@example.com").await;
+    create_subscriber(&app, "bob@example.com").await;
+    let issue = publish_newsletter(
+        &app,
+        json!({
+            "title": "Monday issue",
+            "text_content": "Hello",
+            "html_content": "<p>Hello</p>",
+            "scheduled_at": "2099-01-01T09:00:00Z"
+        }),
+    )
+    .await;
+    assert_eq!(issue["status"], "scheduled");
+    assert_eq!(enqueue_due_issues(&app.db_pool).await.unwrap(), 0);
+
+    // Act
+    // The send time passes while the issue sits in the schedule.
+    sqlx::query!("UPDATE newsletter_issues SET scheduled_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let (first, second) = tokio::join!(
+        enqueue_due_issues(&app.db_pool),
+        enqueue_due_issues(&app.db_pool)
+    );
+    let third = enqueue_due_issues(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!(first.unwrap() + second.unwrap() + third, 1);
+    let queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 2);
+}
+
+#[tokio::test]
+async fn an_issue_that_cannot_be_started_does_not_hold_up_the_others() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    create_subscriber(&app, "alice@example.com").await;
+    let saved = reqwest::Client::new()
+        .put(format!("{}/admin/segments/testers", &app.address))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .json(&json!({ "expression": "confirmed" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, saved.status().as_u16());
+    let broken = publish_newsletter(
+        &app,
+        json!({
+            "title": "For testers",
+            "text_content": "Hello",
+            "html_content": "<p>Hello</p>",
+            "saved_segment": "testers",
+            "scheduled_at": "2099-01-01T09:00:00Z"
+        }),
+    )
+    .await;
+    let valid = publish_newsletter(
+        &app,
+        json!({
+            "title": "For everyone",
+            "text_content": "Hello",
+            "html_content": "<p>Hello</p>",
+            "scheduled_at": "2099-01-01T09:00:00Z"
+        }),
+    )
+    .await;
+    // The segment disappears behind the API's back, and both send times pass.
+    sqlx::query!("DELETE FROM segments")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("UPDATE newsletter_issues SET scheduled_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let first = enqueue_due_issues(&app.db_pool).await.unwrap();
+    let second = enqueue_due_issues(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!((first, second), (1, 0));
+    let status = |id: &serde_json::Value| {
+        sqlx::query_scalar!(
+            "SELECT status FROM newsletter_issues WHERE id = $1",
+            Uuid::parse_str(id.as_str().unwrap()).unwrap()
+        )
+        .fetch_one(&app.db_pool)
+    };
+    assert_eq!(status(&broken["id"]).await.unwrap(), "draft");
+    assert_eq!(status(&valid["id"]).await.unwrap(), "sending");
+    let queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 1);
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_not_enqueued_and_sending_issues_cannot_be_cancelled() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    create_subscriber(&app, "alice@example.com").await;
+    let client = reqwest::Client::new();
+    let scheduled = publish_newsletter(
+        &app,
+        json!({
+            "title": "Later",
+            "text_content": "Hello",
+            "html_content": "<p>Hello</p>",
+            "scheduled_at": "2099-01-01T09:00:00Z"
+        }),
+    )
+    .await;
+    let sent_now = publish_newsletter(
+        &app,
+        json!({"title": "Now", "text_content": "Hello", "html_content": "<p>Hello</p>"}),
+    )
+    .await;
+    let cancel = |issue: &serde_json::Value| {
+        client
+            .post(format!(
+                "{}/admin/newsletters/{}/cancel",
+                &app.address,
+                issue["id"].as_str().unwrap()
+            ))
+            .header("Authorization", format!("Token {}", app.admin_token))
+            .send()
+    };
+
+    // Act
+    let cancelled = cancel(&scheduled).await.unwrap();
+    sqlx::query!("UPDATE newsletter_issues SET scheduled_at = now() - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let enqueued = enqueue_due_issues(&app.db_pool).await.unwrap();
+    let too_late = cancel(&sent_now).await.unwrap();
+
+    // Assert
+    assert_eq!(200, cancelled.status().as_u16());
+    assert_eq!(enqueued, 0);
+    assert_eq!(sent_now["status"], "sending");
+    assert_eq!(409, too_late.status().as_u16());
+}
+
+#[tokio::test]
+async fn delivery_worker_sends_the_issue_to_segment_members_only() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    seed_attribute_schema(&app).await;
+    let email_server = MockServer::start().await;
+    let email_client = EmailClient::new(
+        &email_server.uri(),
+        SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+        Secret::new("token".into()),
+        std::time::Duration::from_millis(200),
+    );
+    create_subscriber(&app, "alice@example.com").await;
+    app.post_subscriptions(&json!({"name": "Bob", "email": "bob@example.com", "tags": ["beta"]}))
+        .await;
+    publish_newsletter(
+        &app,
+        json!({
+            "title": "Beta news",
+            "text_content": "Hello",
+            "html_content": "<p>Hello</p>",
+            "segment": "tag:beta"
+        }),
+    )
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&email_server)
+        .await;
+
+    // Act
+    loop {
+        if let ExecutionOutcome::EmptyQueue =
+            try_execute_task(&app.db_pool, &email_client, &app.application_settings)
+                .await
+                .unwrap()
+        {
+            break;
+        }
+    }
+
+    // Assert
+    let request = &email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["To"], "bob@example.com");
+    assert_eq!(body["Subject"], "Beta news");
+}
+
+#[tokio::test]
+async fn issues_sent_to_a_saved_segment_use_its_expression_at_send_time() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    seed_attribute_schema(&app).await;
+    let client = reqwest::Client::new();
+    let authorization = format!("Token {}", app.admin_token);
+    create_subscriber(&app, "alice@example.com").await;
+    app.post_subscriptions(&json!({"name": "Bob", "email": "bob@example.com", "tags": ["beta"]}))
+        .await;
+    let save_segment = |expression: &str| {
+        client
+            .put(format!("{}/admin/segments/testers", &app.address))
+            .header("Authorization", &authorization)
+            .json(&json!({ "expression": expression }))
+            .send()
+    };
+    assert_eq!(
+        200,
+        save_segment("tag:alpha").await.unwrap().status().as_u16()
+    );
+    let draft = create_draft(
+        &app,
+        json!({
+            "title": "Beta news",
+            "text_content": "Hello",
+            "html_content": "<p>Hello</p>",
+            "saved_segment": "testers"
+        }),
+    )
+    .await;
+    let unknown = client
+        .post(format!("{}/admin/newsletters", &app.address))
+        .header("Authorization", &authorization)
+        .json(&json!({
+            "title": "Nobody",
+            "text_content": "Hello",
+            "html_content": "<p>Hello</p>",
+            "saved_segment": "missing"
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Act
+    assert_eq!(
+        200,
+        save_segment("tag:beta").await.unwrap().status().as_u16()
+    );
+    let delete_in_use = client
+        .delete(format!("{}/admin/segments/testers", &app.address))
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .unwrap();
+    let scheduled: serde_json::Value = client
+        .put(format!(
+            "{}/admin/newsletters/{}/schedule",
+            &app.address,
+            draft["id"].as_str().unwrap()
+        ))
+        .header("Authorization", &authorization)
+        .json(&json!({}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(400, unknown.status().as_u16());
+    assert_eq!(409, delete_in_use.status().as_u16());
+    assert_eq!(draft["saved_segment"], "testers");
+    assert_eq!(scheduled["status"], "sending");
+    assert_eq!(scheduled["segment"], "tag:beta");
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let queued: Vec<_> = queued.into_iter().map(|r| r.subscriber_email).collect();
+    assert_eq!(queued, ["bob@example.com"]);
+}
+
+#[tokio::test]
+async fn local_time_issues_are_delivered_per_timezone_bucket() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let client = reqwest::Client::new();
+    for (email, timezone) in [
+        ("tokyo@example.com", Some("Asia/Tokyo")),
+        ("osaka@example.com", Some("Asia/Tokyo")),
+        ("la@example.com", Some("America/Los_Angeles")),
+        ("unknown@example.com", None),
+    ] {
+        let response = app
+            .post_subscriptions(
+                &json!({"name": "Subscriber", "email": email, "timezone": timezone}),
+            )
+            .await;
+        assert_eq!(200, response.status().as_u16());
+    }
+    // One hour from now on a UTC wall clock: already past in Tokyo, still ahead in UTC and LA.
+    let local_send_at = (chrono::Utc::now() + chrono::TimeDelta::hours(1)).naive_utc();
+    let issue = publish_newsletter(
+        &app,
+        json!({
+            "title": "Good morning",
+            "text_content": "Hello",
+            "html_content": "<p>Hello</p>",
+            "local_send_at": local_send_at.format("%Y-%m-%dT%H:%M:%S").to_string()
+        }),
+    )
+    .await;
+
+    // Act
+    let enqueued = enqueue_due_issues(&app.db_pool).await.unwrap();
+    let buckets: serde_json::Value = client
+        .get(format!(
+            "{}/admin/newsletters/{}/buckets",
+            &app.address,
+            issue["id"].as_str().unwrap()
+        ))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(issue["status"], "sending");
+    assert_eq!(enqueued, 1);
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue ORDER BY 1")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let queued: Vec<_> = queued.into_iter().map(|r| r.subscriber_email).collect();
+    assert_eq!(queued, ["osaka@example.com", "tokyo@example.com"]);
+
+    let buckets = buckets.as_array().unwrap();
+    assert_eq!(buckets.len(), 3);
+    assert_eq!(buckets[0]["timezone"], "Asia/Tokyo");
+    assert_eq!(buckets[0]["recipients"], 2);
+    assert_eq!(buckets[0]["remaining"], 2);
+    assert_eq!(buckets[1]["timezone"], "UTC");
+    assert_eq!(buckets[1]["enqueued_at"], serde_json::Value::Null);
+    assert_eq!(buckets[2]["timezone"], "America/Los_Angeles");
+}
+
+#[tokio::test]
+async fn a_subscriber_who_changes_timezone_between_buckets_gets_the_issue_once() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    for (email, timezone) in [
+        ("traveller@example.com", "Asia/Tokyo"),
+        ("la@example.com", "America/Los_Angeles"),
+    ] {
+        app.post_subscriptions(
+            &json!({"name": "Subscriber", "email": email, "timezone": timezone}),
+        )
+        .await;
+    }
+    let local_send_at = (chrono::Utc::now() + chrono::TimeDelta::hours(1)).naive_utc();
+    publish_newsletter(
+        &app,
+        json!({
+            "title": "Good morning",
+            "text_content": "Hello",
+            "html_content": "<p>Hello</p>",
+            "local_send_at": local_send_at.format("%Y-%m-%dT%H:%M:%S").to_string()
+        }),
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let email_client = app.configuration.email_client.clone().client().unwrap();
+
+    // Act
+    enqueue_due_issues(&app.db_pool).await.unwrap();
+    while let ExecutionOutcome::TaskCompleted =
+        try_execute_task(&app.db_pool, &email_client, &app.application_settings)
+            .await
+            .unwrap()
+    {}
+    sqlx::query!(
+        "UPDATE subscriptions SET timezone = 'America/Los_Angeles' \
+         WHERE email = 'traveller@example.com'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!("UPDATE issue_delivery_buckets SET send_at = now() WHERE enqueued_at IS NULL")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    enqueue_due_issues(&app.db_pool).await.unwrap();
+
+    // Assert
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let queued: Vec<_> = queued.into_iter().map(|r| r.subscriber_email).collect();
+    assert_eq!(queued, ["la@example.com"]);
+}
+
+#[tokio::test]
+async fn subscribers_joining_a_timezone_during_the_fan_out_get_the_issue() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    for (email, timezone) in [
+        ("tokyo@example.com", "Asia/Tokyo"),
+        ("mover@example.com", "America/Los_Angeles"),
+    ] {
+        app.post_subscriptions(
+            &json!({"name": "Subscriber", "email": email, "timezone": timezone}),
+        )
+        .await;
+    }
+    let local_send_at = (chrono::Utc::now() + chrono::TimeDelta::hours(1)).naive_utc();
+    let issue = publish_newsletter(
+        &app,
+        json!({
+            "title": "Good morning",
+            "text_content": "Hello",
+            "html_content": "<p>Hello</p>",
+            "local_send_at": local_send_at.format("%Y-%m-%dT%H:%M:%S").to_string()
+        }),
+    )
+    .await;
+    enqueue_due_issues(&app.db_pool).await.unwrap();
+
+    // Act
+    // A timezone without a bucket, whose send time has already passed.
+    app.post_subscriptions(
+        &json!({"name": "Subscriber", "email": "kolkata@example.com", "timezone": "Asia/Kolkata"}),
+    )
+    .await;
+    // Into a timezone whose bucket was already enqueued.
+    sqlx::query!(
+        "UPDATE subscriptions SET timezone = 'Asia/Tokyo' WHERE email = 'mover@example.com'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let enqueued = enqueue_due_issues(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!(enqueued, 1);
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue ORDER BY 1")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let queued: Vec<_> = queued.into_iter().map(|r| r.subscriber_email).collect();
+    assert_eq!(
+        queued,
+        [
+            "kolkata@example.com",
+            "mover@example.com",
+            "tokyo@example.com"
+        ]
+    );
+    let recipients = sqlx::query_scalar!(
+        "SELECT recipients FROM newsletter_issues WHERE id = $1",
+        uuid::Uuid::parse_str(issue["id"].as_str().unwrap()).unwrap()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(recipients, 3);
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_for_an_unknown_timezone() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions(&json!({
+            "name": "Sergey",
+            "email": "sergo777ser777@gmail.com",
+            "timezone": "Mars/Olympus_Mons"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn edits_are_kept_as_revisions_and_test_sends_use_the_latest_one() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let client = reqwest::Client::new();
+    let authorization = format!("Token {}", app.admin_token);
+    create_subscriber(&app, "alice@example.com").await;
+    let draft = create_draft(
+        &app,
+        json!({"title": "First take", "text_content": "Hello", "html_content": "<p>Hello</p>"}),
+    )
+    .await;
+    let issue_url = format!(
+        "{}/admin/newsletters/{}",
+        &app.address,
+        draft["id"].as_str().unwrap()
+    );
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let edited: serde_json::Value = client
+        .put(&issue_url)
+        .header("Authorization", &authorization)
+        .json(&json!({"title": "Second take", "text_content": "Hi", "html_content": "<p>Hi</p>"}))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+    let test_send = client
+        .post(format!("{}/test", issue_url))
+        .header("Authorization", &authorization)
+        .json(&json!({"recipients": ["editor@example.com", "reviewer@example.com"]}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let revisions: serde_json::Value = client
+        .get(format!("{}/revisions", issue_url))
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(draft["status"], "draft");
+    assert_eq!(edited["current_revision"], 2);
+    assert_eq!(edited["title"], "Second take");
+    assert_eq!(200, test_send.status().as_u16());
+    let titles: Vec<_> = revisions
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|revision| revision["title"].as_str().unwrap())
+        .collect();
+    assert_eq!(titles, ["First take", "Second take"]);
+
+    let recipients: Vec<_> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            assert_eq!(body["Subject"], "Second take");
+            body["To"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    assert_eq!(recipients, ["editor@example.com", "reviewer@example.com"]);
+    let queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
+}
+
+#[test_case(json!([]); "no recipients")]
+#[test_case(json!(["not-an-email"]); "an invalid address")]
+#[tokio::test]
+async fn test_sends_return_a_400_for_invalid_recipients(recipients: serde_json::Value) {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let draft = create_draft(
+        &app,
+        json!({"title": "Draft", "text_content": "Hello", "html_content": "<p>Hello</p>"}),
+    )
+    .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/admin/newsletters/{}/test",
+            &app.address,
+            draft["id"].as_str().unwrap()
+        ))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .json(&json!({ "recipients": recipients }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn editing_only_the_content_keeps_the_segment_and_tracking() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let client = reqwest::Client::new();
+    let authorization = format!("Token {}", app.admin_token);
+    let draft = create_draft(
+        &app,
+        json!({
+            "title": "First take",
+            "text_content": "Hello",
+            "html_content": "<p>Hello</p>",
+            "segment": "confirmed",
+            "tracking_enabled": true
+        }),
+    )
+    .await;
+    let issue_url = format!(
+        "{}/admin/newsletters/{}",
+        &app.address,
+        draft["id"].as_str().unwrap()
+    );
+    let edit = |body: serde_json::Value| {
+        client
+            .put(&issue_url)
+            .header("Authorization", &authorization)
+            .json(&body)
+            .send()
+    };
+
+    // Act
+    let content_only: serde_json::Value = edit(json!({
+        "title": "Second take",
+        "text_content": "Hi",
+        "html_content": "<p>Hi</p>"
+    }))
+    .await
+    .unwrap()
+    .json()
+    .await
+    .unwrap();
+    let cleared: serde_json::Value = edit(json!({
+        "title": "Third take",
+        "text_content": "Hi",
+        "html_content": "<p>Hi</p>",
+        "segment": null,
+        "tracking_enabled": false
+    }))
+    .await
+    .unwrap()
+    .json()
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(content_only["title"], "Second take");
+    assert_eq!(content_only["segment"], "confirmed");
+    assert_eq!(content_only["tracking_enabled"], true);
+    assert_eq!(cleared["segment"], serde_json::Value::Null);
+    assert_eq!(cleared["tracking_enabled"], false);
+}
+
+#[tokio::test]
+async fn issues_are_marked_sent_once_delivered_and_can_no_longer_be_edited() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    create_subscriber(&app, "alice@example.com").await;
+    let email_client = EmailClient::new(
+        &app.email_server.uri(),
+        SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+        Secret::new("token".into()),
+        std::time::Duration::from_millis(200),
+    );
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let issue = publish_newsletter(
+        &app,
+        json!({"title": "Now", "text_content": "Hello", "html_content": "<p>Hello</p>"}),
+    )
+    .await;
+    let issue_url = format!(
+        "{}/admin/newsletters/{}",
+        &app.address,
+        issue["id"].as_str().unwrap()
+    );
+
+    // Act
+    enqueue_due_issues(&app.db_pool).await.unwrap();
+    let still_sending = sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    while let ExecutionOutcome::TaskCompleted =
+        try_execute_task(&app.db_pool, &email_client, &app.application_settings)
+            .await
+            .unwrap()
+    {}
+    enqueue_due_issues(&app.db_pool).await.unwrap();
+    let client = reqwest::Client::new();
+    let sent: serde_json::Value = client
+        .get(&issue_url)
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+    let edit = client
+        .put(&issue_url)
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .json(&json!({"title": "Too late", "text_content": "Hi", "html_content": "<p>Hi</p>"}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(still_sending.status, "sending");
+    assert_eq!(sent["status"], "sent");
+    assert_ne!(sent["sent_at"], serde_json::Value::Null);
+    assert_eq!(409, edit.status().as_u16());
+}
+
+#[tokio::test]
+async fn issues_are_rendered_per_recipient_with_stored_layouts() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let client = reqwest::Client::new();
+    let authorization = format!("Token {}", app.admin_token);
+    app.post_subscriptions(&json!({"name": "Tom & Jerry", "email": "tom@example.com"}))
+        .await;
+    let layout = client
+        .put(format!("{}/admin/templates/layout", &app.address))
+        .header("Authorization", &authorization)
+        .json(&json!({
+            "html_source": "<main>{% block body %}{% endblock %}</main><a href=\"{{ unsubscribe_url }}\">Unsubscribe</a>",
+            "text_source": "{% block body %}{% endblock %}\nUnsubscribe: {{ unsubscribe_url }}"
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, layout.status().as_u16());
+    let email_client = EmailClient::new(
+        &app.email_server.uri(),
+        SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+        Secret::new("token".into()),
+        std::time::Duration::from_millis(200),
+    );
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(
+        &app,
+        json!({
+            "title": "Hello",
+            "html_content": "{% extends \"layout\" %}{% block body %}Hi {{ subscriber.name }}{% endblock %}",
+            "text_content": "{% extends \"layout\" %}{% block body %}Hi {{ subscriber.name }}{% endblock %}"
+        }),
+    )
+    .await;
+
+    // Act
+    while let ExecutionOutcome::TaskCompleted =
+        try_execute_task(&app.db_pool, &email_client, &app.application_settings)
+            .await
+            .unwrap()
+    {}
+
+    // Assert
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("<main>Hi Tom &amp; Jerry</main>"));
+    let links = app.issue_links(request);
+    let unsubscribe_url = links.unsubscribe.unwrap();
+    let unsubscribe_url = unsubscribe_url.as_str();
+    assert!(
+        unsubscribe_url.starts_with(&format!("{}/subscriptions/unsubscribe?token=", app.address))
+    );
+
+    let unsubscribed = client.get(unsubscribe_url).send().await.unwrap();
+    assert_eq!(200, unsubscribed.status().as_u16());
+    assert!(unsubscribed
+        .text()
+        .await
+        .unwrap()
+        .contains("You have been unsubscribed"));
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "unsubscribed");
+}
+
+#[test_case("{% if subscriber.name %}unclosed", "Hi"; "an unclosed block")]
+#[test_case("{% include \"missing\" %}", "Hi"; "a missing partial")]
+#[test_case("<p>Hi</p>", "{{ subscriber.name | nope }}"; "an unknown filter")]
+#[tokio::test]
+async fn saving_a_broken_template_returns_a_400(html_source: &str, text_source: &str) {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+
+    // Act
+    let response = reqwest::Client::new()
+        .put(format!("{}/admin/templates/welcome", &app.address))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .json(&json!({"html_source": html_source, "text_source": text_source}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn templates_still_in_use_cannot_be_deleted() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let client = reqwest::Client::new();
+    let authorization = format!("Token {}", app.admin_token);
+    for (name, source) in [
+        ("footer", "Bye"),
+        ("welcome", "Hi {% include \"footer\" %}"),
+    ] {
+        let response = client
+            .put(format!("{}/admin/templates/{}", &app.address, name))
+            .header("Authorization", &authorization)
+            .json(&json!({"html_source": source, "text_source": source}))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(200, response.status().as_u16());
+    }
+    let delete = |name: &str| {
+        client
+            .delete(format!("{}/admin/templates/{}", &app.address, name))
+            .header("Authorization", &authorization)
+            .send()
+    };
+
+    // Act
+    let footer_in_use = delete("footer").await.unwrap();
+    let welcome = delete("welcome").await.unwrap();
+    let footer = delete("footer").await.unwrap();
+
+    // Assert
+    assert_eq!(409, footer_in_use.status().as_u16());
+    assert_eq!(200, welcome.status().as_u16());
+    assert_eq!(200, footer.status().as_u16());
+}
+
+#[tokio::test]
+async fn templates_used_by_unsent_issues_cannot_be_deleted() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let client = reqwest::Client::new();
+    let authorization = format!("Token {}", app.admin_token);
+    let layout = "<main>{% block body %}{% endblock %}</main>";
+    let response = client
+        .put(format!("{}/admin/templates/layout", &app.address))
+        .header("Authorization", &authorization)
+        .json(&json!({"html_source": layout, "text_source": layout}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    let content = "{% extends \"layout\" %}{% block body %}Hi{% endblock %}";
+    let draft = create_draft(
+        &app,
+        json!({"title": "Uses the layout", "html_content": content, "text_content": content}),
+    )
+    .await;
+    let delete = || {
+        client
+            .delete(format!("{}/admin/templates/layout", &app.address))
+            .header("Authorization", &authorization)
+            .send()
+    };
+
+    // Act
+    let in_use = delete().await.unwrap();
+    sqlx::query!("UPDATE newsletter_issues SET status = 'sent'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let once_sent = delete().await.unwrap();
+
+    // Assert
+    assert_eq!(409, in_use.status().as_u16());
+    let message = in_use.text().await.unwrap();
+    assert!(message.contains("Uses the layout"));
+    assert!(message.contains(draft["id"].as_str().unwrap()));
+    assert_eq!(200, once_sent.status().as_u16());
+}
+
+#[tokio::test]
+async fn markdown_issues_get_generated_bodies_unless_overridden() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let markdown = "# Hello {{ subscriber.name }}\n\nRead [the post](https://example.com/post).";
+
+    // Act
+    let generated = create_draft(
+        &app,
+        json!({"title": "Generated", "markdown_content": markdown}),
+    )
+    .await;
+    let overridden = create_draft(
+        &app,
+        json!({"title": "Overridden", "markdown_content": markdown, "text_content": "Custom text"}),
+    )
+    .await;
+
+    // Assert
+    let revision = |issue: &serde_json::Value| {
+        let issue_id = Uuid::parse_str(issue["id"].as_str().unwrap()).unwrap();
+        sqlx::query!(
+            "SELECT markdown_content, html_content, text_content FROM newsletter_issue_revisions WHERE newsletter_issue_id = $1",
+            issue_id
+        )
+        .fetch_one(&app.db_pool)
+    };
+    let generated = revision(&generated).await.unwrap();
+    assert_eq!(generated.markdown_content.as_deref(), Some(markdown));
+    assert!(generated
+        .html_content
+        .contains("<table role=\"presentation\""));
+    assert!(generated
+        .html_content
+        .contains("Hello {{ subscriber.name }}</h1>"));
+    assert_eq!(
+        generated.text_content,
+        "Hello {{ subscriber.name }}\n===========================\n\n\
+         Read the post [1].\n\n[1] https://example.com/post\n"
+    );
+    let overridden = revision(&overridden).await.unwrap();
+    assert!(overridden
+        .html_content
+        .contains("<table role=\"presentation\""));
+    assert_eq!(overridden.text_content, "Custom text");
+}
+
+#[test_case(json!({"title": "Empty"}); "no content at all")]
+#[test_case(json!({"title": "Half", "html_content": "<p>Hi</p>"}); "html without text or markdown")]
+#[tokio::test]
+async fn creating_an_issue_without_a_body_returns_a_400(body: serde_json::Value) {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/newsletters", &app.address))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn outgoing_html_is_inlined_sanitized_and_absolutized() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let draft = create_draft(
+        &app,
+        json!({
+            "title": "Pasted",
+            "html_content": "<html><head><style>a { color: green; }</style></head><body>\
+                <a href=\"/posts/1\" onclick=\"track()\">Read</a><script>track()</script></body></html>",
+            "text_content": "Read"
+        }),
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/admin/newsletters/{}/test",
+            &app.address,
+            draft["id"].as_str().unwrap()
+        ))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .json(&json!({"recipients": ["editor@example.com"]}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.contains(&format!(
+        "<a href=\"{}/posts/1\" style=\"color: green;\">Read</a>",
+        app.address
+    )));
+    assert!(!html.contains("<style"));
+    assert!(!html.contains("track()"));
+}
+
+#[tokio::test]
+async fn opens_and_clicks_are_recorded_for_tracked_issues() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    create_subscriber(&app, "alice@example.com").await;
+    create_subscriber(&app, "bob@example.com").await;
+    let email_client = EmailClient::new(
+        &app.email_server.uri(),
+        SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+        Secret::new("token".into()),
+        std::time::Duration::from_millis(200),
+    );
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let issue = publish_newsletter(
+        &app,
+        json!({
+            "title": "Tracked",
+            "html_content": "<p><a href=\"https://example.com/post?id=1&amp;ref=mail\">Read</a></p>",
+            "text_content": "Read",
+            "tracking_enabled": true
+        }),
+    )
+    .await;
+    while let ExecutionOutcome::TaskCompleted =
+        try_execute_task(&app.db_pool, &email_client, &app.application_settings)
+            .await
+            .unwrap()
+    {}
+    let requests = app.email_server.received_requests().await.unwrap();
+    let links = app.issue_links(&requests[0]);
+    let click_url = links
+        .click_to("https://example.com/post?id=1&ref=mail")
+        .to_string();
+    let open_url = links.open.unwrap().to_string();
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    // Act
+    let open = client.get(&open_url).send().await.unwrap();
+    let click = client.get(&click_url).send().await.unwrap();
+    let second_click = client.get(&click_url).send().await.unwrap();
+    let forged = client
+        .get(click_url.replace("example.com%2Fpost", "evil.example%2Fpost"))
+        .send()
+        .await
+        .unwrap();
+    let stats: serde_json::Value = client
+        .get(format!(
+            "{}/admin/newsletters/{}/stats",
+            &app.address,
+            issue["id"].as_str().unwrap()
+        ))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(200, open.status().as_u16());
+    assert_eq!(open.headers()["content-type"], "image/gif");
+    assert_eq!(303, click.status().as_u16());
+    assert_eq!(
+        click.headers()["location"],
+        "https://example.com/post?id=1&ref=mail"
+    );
+    assert_eq!(303, second_click.status().as_u16());
+    assert_eq!(404, forged.status().as_u16());
+    assert_eq!(stats["recipients"], 2);
+    assert_eq!(stats["unique_opens"], 1);
+    assert_eq!(stats["open_rate"], 0.5);
+    assert_eq!(stats["unique_clicks"], 1);
+    assert_eq!(stats["click_through_rate"], 0.5);
+    assert_eq!(
+        stats["links"],
+        json!([{"url": "https://example.com/post?id=1&ref=mail", "clicks": 2, "unique_clicks": 1}])
+    );
+}
+
+#[tokio::test]
+async fn a_signed_link_that_is_not_a_url_returns_a_400() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let tracker = Tracker::new(
+        &app.application_settings.base_url,
+        &app.application_settings.hmac_secret,
+    );
+    let click_url = tracker.click_url(Uuid::new_v4(), Uuid::new_v4(), "https://exa\nmple.com:x/");
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    // Act
+    let response = client.get(&click_url).send().await.unwrap();
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn delivery_status_counts_outcomes_and_feedback() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    for email in ["alice@example.com", "bob@example.com", "carol@example.com"] {
+        create_subscriber(&app, email).await;
+    }
+    let email_client = EmailClient::new(
+        &app.email_server.uri(),
+        SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+        Secret::new("token".into()),
+        std::time::Duration::from_millis(200),
+    );
+    Mock::given(path("/email"))
+        .and(body_partial_json(json!({"To": "bob@example.com"})))
+        .respond_with(ResponseTemplate::new(500))
+        .with_priority(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let issue = publish_newsletter(
+        &app,
+        json!({"title": "Status", "html_content": "<p>Hi</p>", "text_content": "Hi"}),
+    )
+    .await;
+    let issue_id = issue["id"].as_str().unwrap();
+    while let ExecutionOutcome::TaskCompleted =
+        try_execute_task(&app.db_pool, &email_client, &app.application_settings)
+            .await
+            .unwrap()
+    {}
+    let client = reqwest::Client::new();
+    let webhook_url = format!(
+        "{}/webhooks/postmark?token={}",
+        &app.address,
+        app.application_settings.webhook_token.expose_secret()
+    );
+    let carol_token = sqlx::query!(
+        "SELECT unsubscribe_token FROM subscriptions WHERE email = 'carol@example.com'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .unsubscribe_token;
+
+    // Act
+    let bounce = client
+        .post(&webhook_url)
+        .json(&json!({"RecordType": "Bounce", "Type": "HardBounce", "Email": "alice@example.com"}))
+        .send()
+        .await
+        .unwrap();
+    let unauthenticated = client
+        .post(format!("{}/webhooks/postmark?token=wrong", &app.address))
+        .json(&json!({"RecordType": "SpamComplaint", "Email": "alice@example.com"}))
+        .send()
+        .await
+        .unwrap();
+    let unsubscribe = client
+        .get(format!(
+            "{}/subscriptions/unsubscribe?token={}&issue={}",
+            &app.address, carol_token, issue_id
+        ))
+        .send()
+        .await
+        .unwrap();
+    let status: serde_json::Value = client
+        .get(format!(
+            "{}/admin/newsletters/{}/status",
+            &app.address, issue_id
+        ))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(204, bounce.status().as_u16());
+    assert_eq!(401, unauthenticated.status().as_u16());
+    assert_eq!(200, unsubscribe.status().as_u16());
+    assert_eq!(
+        status,
+        json!({
+            "status": "sending",
+            "recipients": 3,
+            "queued": 0,
+            "retrying": 1,
+            "sent": 2,
+            "failed": 0,
+            "skipped": 0,
+            "bounced": 1,
+            "complained": 0,
+            "unsubscribed": 1
+        })
+    );
+}
+
+#[tokio::test]
+async fn status_stream_ends_with_the_final_status() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    create_subscriber(&app, "alice@example.com").await;
+    let email_client = EmailClient::new(
+        &app.email_server.uri(),
+        SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+        Secret::new("token".into()),
+        std::time::Duration::from_millis(200),
+    );
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let issue = publish_newsletter(
+        &app,
+        json!({"title": "Status", "html_content": "<p>Hi</p>", "text_content": "Hi"}),
+    )
+    .await;
+    while let ExecutionOutcome::TaskCompleted =
+        try_execute_task(&app.db_pool, &email_client, &app.application_settings)
+            .await
+            .unwrap()
+    {}
+    enqueue_due_issues(&app.db_pool).await.unwrap();
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/admin/newsletters/{}/status/stream",
+            &app.address,
+            issue["id"].as_str().unwrap()
+        ))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    let body = tokio::time::timeout(std::time::Duration::from_secs(5), response.text())
+        .await
+        .expect("The stream did not end.")
+        .unwrap();
+    let events: Vec<&str> = body.trim().split("\n\n").collect();
+    assert_eq!(events.len(), 1);
+    let data: serde_json::Value =
+        serde_json::from_str(events[0].split("data: ").nth(1).unwrap()).unwrap();
+    assert!(events[0].starts_with("event: status\n"));
+    assert_eq!(data["status"], "sent");
+    assert_eq!(data["sent"], 1);
+}
+
+#[tokio::test]
+async fn the_status_stream_of_a_scheduled_issue_ends_after_the_first_event() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    let issue = publish_newsletter(
+        &app,
+        json!({
+            "title": "Later",
+            "html_content": "<p>Hi</p>",
+            "text_content": "Hi",
+            "scheduled_at": "2099-01-01T09:00:00Z"
+        }),
+    )
+    .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/admin/newsletters/{}/status/stream",
+            &app.address,
+            issue["id"].as_str().unwrap()
+        ))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body = tokio::time::timeout(std::time::Duration::from_secs(5), response.text())
+        .await
+        .expect("The stream did not end.")
+        .unwrap();
+    let events: Vec<&str> = body.trim().split("\n\n").collect();
+    assert_eq!(events.len(), 1);
+    let data: serde_json::Value =
+        serde_json::from_str(events[0].split("data: ").nth(1).unwrap()).unwrap();
+    assert_eq!(data["status"], "scheduled");
+}
+
+#[tokio::test]
+async fn throttled_deliveries_are_postponed_without_using_up_a_retry() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    create_subscriber(&app, "alice@example.com").await;
+    let throttle = SendThrottle::in_memory(&SendThrottleSettings {
+        messages_per_second: 100.0,
+        per_domain: vec![],
+    });
+    let email_client = EmailClient::new(
+        &app.email_server.uri(),
+        SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+        Secret::new("token".into()),
+        std::time::Duration::from_millis(200),
+    )
+    .with_throttle(Arc::new(throttle));
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "120"))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(
+        &app,
+        json!({"title": "Throttled", "html_content": "<p>Hi</p>", "text_content": "Hi"}),
+    )
+    .await;
+
+    // Act
+    let first = try_execute_task(&app.db_pool, &email_client, &app.application_settings)
+        .await
+        .unwrap();
+    let second = try_execute_task(&app.db_pool, &email_client, &app.application_settings)
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(matches!(first, ExecutionOutcome::TaskCompleted));
+    assert!(matches!(second, ExecutionOutcome::EmptyQueue));
+    let task = sqlx::query!(
+        r#"
+        SELECT n_retries, execute_after > now() + interval '100 seconds' AS "postponed!"
+        FROM issue_delivery_queue
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(task.n_retries, 0);
+    assert!(task.postponed);
+}
+
+#[tokio::test]
+async fn shutting_down_lets_the_delivery_in_progress_finish() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+    create_subscriber(&app, "alice@example.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(
+        &app,
+        json!({"title": "Last one", "html_content": "<p>Hi</p>", "text_content": "Hi"}),
+    )
+    .await;
+    let mut configuration = app.configuration.clone();
+    configuration.application.background_workers = true;
+    let application = Application::build(configuration).await.unwrap();
+    let connection_pool = application.connection_pool().clone();
+    let shutdown = CancellationToken::new();
+    let stopped = tokio::spawn(application.run_until_stopped(shutdown.clone().cancelled_owned()));
+    while app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // Act
+    shutdown.cancel();
+
+    // Assert
+    tokio::time::timeout(Duration::from_secs(5), stopped)
+        .await
+        .expect("The app did not stop.")
+        .unwrap();
+    assert!(connection_pool.is_closed());
+    let queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
+}
+
+const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+/// Record the spans of the current thread, which runs the apps spawned by tokio tests.
+fn capture_spans() -> (InMemorySpanExporter, TracerProvider, DefaultGuard) {
+    let exporter = InMemorySpanExporter::default();
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let guard = tracing::subscriber::set_default(
+        tracing_subscriber::registry().with(otel_layer(&provider)),
+    );
+    (exporter, provider, guard)
+}
+
+#[tokio::test]
+async fn incoming_trace_context_is_propagated_to_the_email_provider() {
+    // Arrange
+    let (_exporter, _provider, _guard) = capture_spans();
+    let app = spawn_app().await.unwrap();
+    let draft = create_draft(
+        &app,
+        json!({"title": "Traced", "text_content": "Hi", "html_content": "<p>Hi</p>"}),
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/admin/newsletters/{}/test",
+            &app.address,
+            draft["id"].as_str().unwrap()
+        ))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .header("traceparent", TRACEPARENT)
+        .json(&json!({"recipients": ["editor@example.com"]}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let traceparent = email_request.headers["traceparent"].to_str().unwrap();
+    assert!(traceparent.starts_with("00-0af7651916cd43dd8448eb211c80319c-"));
+    assert_ne!(traceparent, TRACEPARENT);
+}
+
+#[tokio::test]
+async fn delivery_spans_link_to_the_request_that_scheduled_the_issue() {
+    // Arrange
+    let (exporter, _provider, _guard) = capture_spans();
+    let app = spawn_app().await.unwrap();
+    create_subscriber(&app, "alice@example.com").await;
+    let draft = create_draft(
+        &app,
+        json!({"title": "Traced", "text_content": "Hi", "html_content": "<p>Hi</p>"}),
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let schedule = reqwest::Client::new()
+        .put(format!(
+            "{}/admin/newsletters/{}/schedule",
+            &app.address,
+            draft["id"].as_str().unwrap()
+        ))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .header("traceparent", TRACEPARENT)
+        .json(&json!({}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, schedule.status().as_u16());
+    let email_client = EmailClient::new(
+        &app.email_server.uri(),
+        SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+        Secret::new("token".into()),
+        std::time::Duration::from_millis(200),
+    );
+
+    // Act
+    try_execute_task(&app.db_pool, &email_client, &app.application_settings)
+        .await
+        .unwrap();
+
+    // Assert
+    let scheduling_trace = TraceId::from_hex("0af7651916cd43dd8448eb211c80319c").unwrap();
+    let delivery = exporter
+        .get_finished_spans()
+        .unwrap()
+        .into_iter()
+        .find(|span| span.name == "try_execute_task")
+        .expect("The delivery span was not recorded.");
+    assert_ne!(delivery.span_context.trace_id(), scheduling_trace);
+    assert!(delivery
+        .links
+        .iter()
+        .any(|link| link.span_context.trace_id() == scheduling_trace));
+    let subscriber_email = span_attribute(&delivery, "subscriber_email");
+    assert!(subscriber_email.starts_with("sha256:"));
+    assert!(!subscriber_email.contains("alice"));
+}
+
+fn span_attribute(span: &SpanData, key: &str) -> String {
+    span.attributes
+        .iter()
+        .find(|attribute| attribute.key.as_str() == key)
+        .unwrap_or_else(|| panic!("The span has no {} attribute.", key))
+        .value
+        .to_string()
+}
+
+#[tokio::test]
+async fn responses_carry_a_generated_request_id() {
+    // Arrange
+    let app = spawn_app().await.unwrap();
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/health/live", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let request_id = response.headers()["x-request-id"].to_str().unwrap();
+    assert!(Uuid::parse_str(request_id).is_ok());
+}
+
+#[tokio::test]
+async fn request_logs_keep_the_callers_request_id_and_leave_out_secrets() {
+    // Arrange
+    let (exporter, _provider, _guard) = capture_spans();
+    let app = spawn_app().await.unwrap();
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/subscriptions/unsubscribe?token=secret-unsubscribe-token",
+            &app.address
+        ))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .header("X-Request-Id", "request-from-the-edge")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.headers()["x-request-id"], "request-from-the-edge");
+    let request = exporter
+        .get_finished_spans()
+        .unwrap()
+        .into_iter()
+        .find(|span| span.name == "request")
+        .expect("The request span was not recorded.");
+    assert_eq!(
+        span_attribute(&request, "request_id"),
+        "request-from-the-edge"
+    );
+    assert!(!span_attribute(&request, "uri").contains("secret-unsubscribe-token"));
+    let headers = span_attribute(&request, "headers");
+    assert!(headers.contains("authorization"));
+    assert!(!headers.contains(&app.admin_token));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn traces_are_exported_to_the_otlp_collector() {
+    // Arrange
+    let collector = MockServer::start().await;
+    Mock::given(path("/v1/traces"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&collector)
+        .await;
+    let provider = init_tracer_provider(&TelemetrySettings {
+        otlp_endpoint: Some(collector.uri()),
+        service_name: "newsletter-deliverer-tests".into(),
+        log_format: LogFormat::Json,
+        log_subscriber_emails: false,
+    })
+    .unwrap()
+    .expect("An endpoint was configured.");
+
+    // Act
+    tracing::subscriber::with_default(
+        tracing_subscriber::registry().with(otel_layer(&provider)),
+        || tracing::info_span!("exported_span").in_scope(|| {}),
+    );
+    tokio::task::spawn_blocking(move || provider.force_flush())
+        .await
+        .unwrap();
+
+    // Assert
+    let requests = collector.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    let body = &requests[0].body;
+    for expected in [&b"exported_span"[..], b"newsletter-deliverer-tests"] {
+        assert!(body
+            .windows(expected.len())
+            .any(|window| window == expected));
+    }
+}
+
+async fn create_subscriber(app: &TestApp, email: &str) {
+    let response = app
+        .post_subscriptions(&json!({"name": "Subscriber", "email": email}))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+async fn create_draft(app: &TestApp, body: serde_json::Value) -> serde_json::Value {
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/newsletters", &app.address))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(201, response.status().as_u16());
+    response.json().await.unwrap()
+}
+
+/// Create a draft from the content fields of `body` and schedule it with the remaining ones.
+async fn publish_newsletter(app: &TestApp, body: serde_json::Value) -> serde_json::Value {
+    let mut schedule = body.as_object().unwrap().clone();
+    let content: serde_json::Map<_, _> = [
+        "title",
+        "markdown_content",
+        "text_content",
+        "html_content",
+        "segment",
+        "saved_segment",
+        "tracking_enabled",
+    ]
+    .into_iter()
+    .filter_map(|key| schedule.remove_entry(key))
+    .collect();
+    let draft = create_draft(app, content.into()).await;
+
+    let response = reqwest::Client::new()
+        .put(format!(
+            "{}/admin/newsletters/{}/schedule",
+            &app.address,
+            draft["id"].as_str().unwrap()
+        ))
+        .header("Authorization", format!("Token {}", app.admin_token))
+        .json(&schedule)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    response.json().await.unwrap()
+}
+
+/// Declare a public `source` attribute, a private `plan` attribute,
+/// a public `beta` tag and a private `vip` tag through the admin API.
+async fn seed_attribute_schema(app: &TestApp) {
+    let client = reqwest::Client::new();
+    let authorization = format!("Token {}", app.admin_token);
+    let requests = [
+        (
+            "attributes/source",
+            json!({"value_type": "string", "publicly_settable": true}),
+        ),
+        ("attributes/plan", json!({"value_type": "string"})),
+        ("tags/beta", json!({"publicly_settable": true})),
+        ("tags/vip", json!({})),
+    ];
+    for (path, body) in requests {
+        let response = client
+            .put(format!("{}/admin/{}", &app.address, path))
+            .header("Authorization", &authorization)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(200, response.status().as_u16());
+    }
 }
@@ -0,0 +1,183 @@
+//! Harness running one application per test, each against a database of its own that is
+//! dropped with the [`TestApp`].
+
+use anyhow::Context;
+use linkify::{LinkFinder, LinkKind};
+use newsletter_deliverer::{
+    configuration::{get_configuration, ApplicationSettings, DatabaseSettings, Settings},
+    startup::Application,
+};
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{Connection, PgConnection, PgPool};
+use uuid::Uuid;
+use wiremock::MockServer;
+
+pub const ALLOWED_ORIGIN: &str = "https://blog.example.com";
+
+pub struct TestApp {
+    pub address: String,
+    pub db_pool: PgPool,
+    pub admin_token: String,
+    pub email_server: MockServer,
+    pub application_settings: ApplicationSettings,
+    pub configuration: Settings,
+}
+
+/// Links back to the application found in a newsletter issue sent through the mock email
+/// server.
+pub struct IssueLinks {
+    /// The unsubscribe link of the plain text body.
+    pub unsubscribe: Option<Url>,
+    /// The tracking pixel of the HTML body.
+    pub open: Option<Url>,
+    /// Links of the HTML body rewritten to go through click tracking.
+    pub clicks: Vec<Url>,
+}
+
+impl IssueLinks {
+    /// The tracked link leading to `target`.
+    pub fn click_to(&self, target: &str) -> &Url {
+        self.clicks
+            .iter()
+            .find(|url| {
+                url.query_pairs()
+                    .any(|(name, value)| name == "url" && value == target)
+            })
+            .unwrap_or_else(|| panic!("No tracked link leads to {}", target))
+    }
+}
+
+impl TestApp {
+    pub async fn post_subscriptions(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub fn issue_links(&self, email_request: &wiremock::Request) -> IssueLinks {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let links = |s: &str| -> Vec<Url> {
+            LinkFinder::new()
+                .links(s)
+                .filter(|link| *link.kind() == LinkKind::Url)
+                .map(|link| Url::parse(&link.as_str().replace("&amp;", "&")).unwrap())
+                .filter(|url| url.as_str().starts_with(&self.address))
+                .collect()
+        };
+        let html = links(body["HtmlBody"].as_str().unwrap());
+        let plain_text = links(body["TextBody"].as_str().unwrap());
+        IssueLinks {
+            unsubscribe: plain_text
+                .iter()
+                .find(|url| url.path() == "/subscriptions/unsubscribe")
+                .cloned(),
+            open: html.iter().find(|url| url.path() == "/track/open").cloned(),
+            clicks: html
+                .iter()
+                .filter(|url| url.path() == "/track/click")
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        let database = self.configuration.database.clone();
+        // Dropping cannot wait on the test's runtime, so it gets a runtime of its own.
+        let dropped = std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to build a runtime")
+                .block_on(drop_database(&database))
+        })
+        .join()
+        .expect("Dropping the test database panicked");
+        if let Err(e) = dropped {
+            eprintln!("Failed to drop the test database: {:?}", e);
+        }
+    }
+}
+
+/// Spin up an instance of application
+/// and returns its address (i.e. http://localhost:XXXX)
+pub async fn spawn_app() -> anyhow::Result<TestApp> {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spin up an instance of application with `configure` applied to its configuration.
+/// Form tokens may be used as soon as they are issued.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> anyhow::Result<TestApp> {
+    let email_server = MockServer::start().await;
+    let mut configuration = get_configuration().expect("Failed to read configuration.");
+    configuration.database.database_name = format!("test_{}", Uuid::new_v4().simple());
+    configuration.email_client.base_url = email_server.uri();
+    configuration.application.port = 0;
+    // Tests run deliveries themselves, one task at a time.
+    configuration.application.background_workers = false;
+    configuration.application.bot_protection.min_fill_seconds = 0;
+    // An unparseable Redis URI makes rate limits count in memory right away, so tests never
+    // share counters through Redis.
+    configuration.redis_uri = Secret::new(String::new());
+    configuration.application.rate_limit.per_ip.requests = 1000;
+    configuration.application.rate_limit.per_email.requests = 1000;
+    configuration.application.allowed_origins = vec![ALLOWED_ORIGIN.to_owned()];
+    configure(&mut configuration);
+
+    let connection_pool = configure_database(&configuration.database).await?;
+
+    let application = Application::build(configuration.clone())
+        .await
+        .context("Failed to build the application")?;
+    let address = format!("http://127.0.0.1:{}", application.port());
+    let mut application_settings = configuration.application.clone();
+    application_settings.base_url = address.clone();
+    tokio::spawn(application.run_until_stopped(std::future::pending()));
+
+    Ok(TestApp {
+        address,
+        db_pool: connection_pool,
+        admin_token: application_settings.admin_token.expose_secret().to_owned(),
+        email_server,
+        application_settings,
+        configuration,
+    })
+}
+
+/// Create the database named in `config` and migrate it.
+async fn configure_database(config: &DatabaseSettings) -> anyhow::Result<PgPool> {
+    let mut connection = PgConnection::connect_with(&config.without_db())
+        .await
+        .context("Failed to connect to Postgres")?;
+    sqlx::query(&format!(r#"CREATE DATABASE "{}""#, config.database_name))
+        .execute(&mut connection)
+        .await
+        .context("Failed to create database")?;
+
+    let connection_pool = PgPool::connect_with(config.with_db())
+        .await
+        .context("Failed to connect to the test database")?;
+    sqlx::migrate!("./migrations")
+        .run(&connection_pool)
+        .await
+        .context("Failed to migrate the database")?;
+
+    Ok(connection_pool)
+}
+
+/// Drop the database named in `config`, closing the connections still open to it.
+async fn drop_database(config: &DatabaseSettings) -> anyhow::Result<()> {
+    let mut connection = PgConnection::connect_with(&config.without_db()).await?;
+    sqlx::query(&format!(
+        r#"DROP DATABASE IF EXISTS "{}" WITH (FORCE)"#,
+        config.database_name
+    ))
+    .execute(&mut connection)
+    .await?;
+    Ok(())
+}